@@ -0,0 +1,46 @@
+//! Local stand-in for a webhook endpoint: prints every alert POSTed to it.
+//!
+//! cargo run --example webhook_receiver [address], then add a webhook target
+//! for http://127.0.0.1:8080/alerts (or the given address) in the Alerts view.
+
+use std::{env, io::{BufRead, BufReader, Read, Write}, net::{TcpListener, TcpStream}};
+
+fn handle(stream: TcpStream) -> std::io::Result<()> {
+	let mut reader = BufReader::new(stream);
+
+	let mut request_line = String::new();
+	reader.read_line(&mut request_line)?;
+
+	let mut content_length = 0;
+	loop {
+		let mut header = String::new();
+		if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+			break;
+		}
+		if let Some((name, value)) = header.split_once(':') {
+			if name.eq_ignore_ascii_case("content-length") {
+				content_length = value.trim().parse().unwrap_or(0);
+			}
+		}
+	}
+
+	let mut body = vec![0; content_length];
+	reader.read_exact(&mut body)?;
+	println!("{} {}", request_line.trim(), String::from_utf8_lossy(&body));
+
+	reader.into_inner().write_all(b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n")
+}
+
+fn main() -> std::io::Result<()> {
+	let address = env::args().nth(1).unwrap_or_else(|| "127.0.0.1:8080".to_string());
+	let listener = TcpListener::bind(&address)?;
+	println!("Listening for alerts on http://{address}");
+
+	for stream in listener.incoming() {
+		if let Err(err) = stream.and_then(handle) {
+			eprintln!("Error reading request: {err}");
+		}
+	}
+
+	Ok(())
+}
@@ -0,0 +1,358 @@
+use std::{fs::OpenOptions, io::Write, process::Command, sync::mpsc::{self, Receiver, Sender}, thread, time::Duration};
+
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{alerts::AlertEvent, market_hours::unix_now, storage};
+
+const SINKS_FILE: &str = "alert_sinks.json";
+const DEFAULT_LOG_FILE: &str = "alerts.jsonl";
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Wait before the first webhook retry, doubled for each further one
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Where fired alerts are delivered to
+#[derive(Serialize, Deserialize, Clone)]
+pub enum Sink {
+	/// Appends one JSON object per alert to a file
+	JsonLines { path: String },
+	/// Runs a shell command with the alert in `STONITOR_*` environment variables
+	Command { command: String },
+	/// POSTs the alert as JSON, retrying failed requests
+	Webhook { url: String, retries: u32 },
+}
+
+impl Sink {
+	pub fn label(&self) -> &'static str {
+		match self {
+			Sink::JsonLines { .. } => "Log file",
+			Sink::Command { .. } => "Command",
+			Sink::Webhook { .. } => "Webhook",
+		}
+	}
+
+	fn deliver(&self, event: &AlertEvent) -> Result<(), String> {
+		let json = serde_json::to_string(event).map_err(|err| err.to_string())?;
+
+		match self {
+			Sink::JsonLines { path } => {
+				let path = match path.trim() {
+					"" => storage::data_path(DEFAULT_LOG_FILE).ok_or("no data directory")?,
+					path => path.into(),
+				};
+				let mut file = OpenOptions::new().create(true).append(true).open(&path)
+					.map_err(|err| format!("can't open '{}': {err}", path.display()))?;
+				writeln!(file, "{json}").map_err(|err| format!("can't write '{}': {err}", path.display()))
+			},
+			Sink::Command { command } => {
+				let mut shell = if cfg!(windows) {
+					let mut shell = Command::new("cmd");
+					shell.arg("/C");
+					shell
+				} else {
+					let mut shell = Command::new("sh");
+					shell.arg("-c");
+					shell
+				};
+
+				let status = shell.arg(command)
+					.env("STONITOR_TIME", event.time.to_string())
+					.env("STONITOR_RULE_ID", event.rule_id.to_string())
+					.env("STONITOR_SYMBOL", &event.symbol)
+					.env("STONITOR_MESSAGE", &event.message)
+					.env("STONITOR_JSON", &json)
+					.status()
+					.map_err(|err| format!("can't run '{command}': {err}"))?;
+
+				if status.success() { Ok(()) } else { Err(format!("'{command}' exited with {status}")) }
+			},
+			Sink::Webhook { url, retries } => {
+				let client = reqwest::blocking::Client::builder().timeout(WEBHOOK_TIMEOUT).build().map_err(|err| err.to_string())?;
+
+				let mut backoff = RETRY_BACKOFF;
+				let mut attempt = 0;
+				loop {
+					let result = client.post(url)
+						.header("Content-Type", "application/json")
+						.body(json.clone())
+						.send()
+						.map_err(|err| err.to_string())
+						.and_then(|response| match response.status() {
+							status if status.is_success() => Ok(()),
+							status => Err(format!("server answered {status}")),
+						});
+
+					match result {
+						Err(err) if attempt < *retries => {
+							eprintln!("Error posting alert to '{url}', retrying: {err}");
+							thread::sleep(backoff);
+							backoff *= 2;
+							attempt += 1;
+						},
+						result => return result.map_err(|err| format!("POST to '{url}' failed: {err}")),
+					}
+				}
+			},
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SinkConfig {
+	pub id: u64,
+	pub sink: Sink,
+	pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct SinkConfigs {
+	sinks: Vec<SinkConfig>,
+	next_id: u64,
+}
+
+/// Outcome of delivering to one sink
+type DeliveryResult = (u64, Result<(), String>);
+
+/// Configured delivery targets and the outcome of their last delivery
+pub struct AlertSinks {
+	configs: SinkConfigs,
+	sender: Sender<DeliveryResult>,
+	receiver: Receiver<DeliveryResult>,
+	last_result: Vec<DeliveryResult>,
+}
+
+impl AlertSinks {
+	pub fn new() -> Self {
+		let (sender, receiver) = mpsc::channel();
+		Self {
+			configs: SinkConfigs::default(),
+			sender,
+			receiver,
+			last_result: vec![],
+		}
+	}
+
+	pub fn load() -> Self {
+		Self {
+			configs: storage::load(SINKS_FILE).unwrap_or_default(),
+			..Self::new()
+		}
+	}
+
+	fn save(&self) {
+		storage::save(SINKS_FILE, &self.configs);
+	}
+
+	/// Sends `events` to every enabled sink on worker threads, so slow commands
+	/// or webhook retries don't hold up the UI
+	pub fn deliver(&self, ctx: &Context, events: &[AlertEvent]) {
+		if events.is_empty() {
+			return;
+		}
+
+		for config in self.configs.sinks.iter().filter(|config| config.enabled) {
+			self.spawn_delivery(ctx, config.clone(), events.to_vec());
+		}
+	}
+
+	fn spawn_delivery(&self, ctx: &Context, config: SinkConfig, events: Vec<AlertEvent>) {
+		let sender = self.sender.clone();
+		let ctx = ctx.clone();
+		thread::spawn(move || {
+			// One failed event doesn't keep the others from being sent
+			let mut errors: Vec<String> = events.iter().filter_map(|event| config.sink.deliver(event).err()).collect();
+			for err in &errors {
+				eprintln!("Error delivering alert: {err}");
+			}
+			let result = match errors.len() {
+				0 => Ok(()),
+				1 => Err(errors.remove(0)),
+				count => Err(format!("{count} of {} alerts failed: {}", events.len(), errors.join("; "))),
+			};
+			if sender.send((config.id, result)).is_ok() {
+				ctx.request_repaint();
+			}
+		});
+	}
+
+	pub fn show(&mut self, ui: &mut Ui) {
+		while let Ok((id, result)) = self.receiver.try_recv() {
+			self.last_result.retain(|(last_id, _)| *last_id != id);
+			self.last_result.push((id, result));
+		}
+
+		let mut changed = false;
+		let mut remove = None;
+		let mut send_test = None;
+
+		Grid::new("alert_sinks").striped(true).spacing([12., 6.]).show(ui, |ui| {
+			for (index, config) in self.configs.sinks.iter_mut().enumerate() {
+				changed |= ui.checkbox(&mut config.enabled, "").changed();
+				ui.label(RichText::new(config.sink.label()).strong());
+
+				ui.horizontal(|ui| match &mut config.sink {
+					Sink::JsonLines { path } => {
+						changed |= ui.add(TextEdit::singleline(path).hint_text(format!("{DEFAULT_LOG_FILE} in the data directory")).desired_width(300.)).changed();
+					},
+					Sink::Command { command } => {
+						changed |= ui.add(TextEdit::singleline(command).hint_text("notify-send \"$STONITOR_SYMBOL\" \"$STONITOR_MESSAGE\"").desired_width(300.)).changed();
+					},
+					Sink::Webhook { url, retries } => {
+						changed |= ui.add(TextEdit::singleline(url).hint_text("http://localhost:8080/alerts").desired_width(220.)).changed();
+						ui.label("Retries:");
+						changed |= ui.add(DragValue::new(retries).range(0..=10)).changed();
+					},
+				});
+
+				match self.last_result.iter().find(|(id, _)| *id == config.id) {
+					Some((_, Ok(()))) => {
+						ui.label(RichText::new("Delivered").color(Color32::GREEN));
+					},
+					Some((_, Err(err))) => {
+						ui.label(RichText::new("Failed").color(Color32::RED)).on_hover_text(err);
+					},
+					None => {
+						ui.label("");
+					},
+				}
+
+				if ui.small_button("Test").on_hover_text("Send a test alert to this target").clicked() {
+					send_test = Some(config.clone());
+				}
+				if ui.small_button("✖").on_hover_text("Remove target").clicked() {
+					remove = Some(index);
+				}
+				ui.end_row();
+			}
+		});
+
+		ui.horizontal(|ui| {
+			let mut add = None;
+			if ui.button("➕ Log file").clicked() {
+				add = Some(Sink::JsonLines { path: String::new() });
+			}
+			if ui.button("➕ Command").clicked() {
+				add = Some(Sink::Command { command: String::new() });
+			}
+			if ui.button("➕ Webhook").clicked() {
+				add = Some(Sink::Webhook { url: String::new(), retries: 3 });
+			}
+
+			if let Some(sink) = add {
+				self.configs.sinks.push(SinkConfig {
+					id: self.configs.next_id,
+					sink,
+					enabled: true,
+				});
+				self.configs.next_id += 1;
+				changed = true;
+			}
+		});
+
+		if let Some(index) = remove {
+			let removed = self.configs.sinks.remove(index);
+			self.last_result.retain(|(id, _)| *id != removed.id);
+			changed = true;
+		}
+		if changed {
+			self.save();
+		}
+
+		if let Some(config) = send_test {
+			let event = AlertEvent {
+				time: unix_now(),
+				rule_id: 0,
+				symbol: "TEST".to_string(),
+				message: "test alert from Stonitor".to_string(),
+			};
+			self.spawn_delivery(ui.ctx(), config, vec![event]);
+		}
+	}
+}
+
+impl Default for AlertSinks {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{env, fs, io::{BufRead, BufReader, Read}, net::TcpListener};
+
+	use super::*;
+
+	fn event(rule_id: u64) -> AlertEvent {
+		AlertEvent {
+			time: 1_700_000_000,
+			rule_id,
+			symbol: "AAPL".to_string(),
+			message: "AAPL above 200".to_string(),
+		}
+	}
+
+	/// Answers one request per status on a local port, returns its url and the request bodies
+	fn serve(statuses: &'static [&'static str]) -> (String, thread::JoinHandle<Vec<String>>) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+		let handle = thread::spawn(move || {
+			statuses.iter().map(|status| {
+				let (stream, _) = listener.accept().unwrap();
+				let mut reader = BufReader::new(stream);
+				let mut content_length = 0;
+				loop {
+					let mut line = String::new();
+					if reader.read_line(&mut line).unwrap() == 0 || line.trim().is_empty() {
+						break;
+					}
+					if let Some((name, value)) = line.split_once(':') {
+						if name.eq_ignore_ascii_case("content-length") {
+							content_length = value.trim().parse().unwrap();
+						}
+					}
+				}
+				let mut body = vec![0; content_length];
+				reader.read_exact(&mut body).unwrap();
+				write!(reader.into_inner(), "HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").unwrap();
+				String::from_utf8(body).unwrap()
+			}).collect()
+		});
+		(url, handle)
+	}
+
+	#[test]
+	fn webhook_retries_until_delivered() {
+		let (url, server) = serve(&["500 Internal Server Error", "204 No Content"]);
+
+		assert_eq!(Sink::Webhook { url, retries: 1 }.deliver(&event(1)), Ok(()));
+
+		let bodies = server.join().unwrap();
+		let json = serde_json::to_string(&event(1)).unwrap();
+		assert_eq!(bodies, [json.clone(), json]);
+	}
+
+	#[test]
+	fn webhook_without_retries_reports_the_error() {
+		let (url, server) = serve(&["500 Internal Server Error"]);
+
+		let result = Sink::Webhook { url, retries: 0 }.deliver(&event(1));
+		assert!(result.is_err_and(|err| err.contains("500")));
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn json_lines_appends_one_line_per_event() {
+		let path = env::temp_dir().join(format!("alert_sinks_test_{}.jsonl", std::process::id()));
+		let _ = fs::remove_file(&path);
+		let sink = Sink::JsonLines { path: path.display().to_string() };
+
+		for rule_id in [1, 2] {
+			assert_eq!(sink.deliver(&event(rule_id)), Ok(()));
+		}
+
+		let contents = fs::read_to_string(&path).unwrap();
+		fs::remove_file(&path).unwrap();
+		let lines: Vec<&str> = contents.lines().collect();
+		assert_eq!(lines, [serde_json::to_string(&event(1)).unwrap(), serde_json::to_string(&event(2)).unwrap()]);
+	}
+}
@@ -0,0 +1,512 @@
+use std::time::{Duration, Instant};
+
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+use yahoo_finance_api::{Quote, YMetaData};
+
+use crate::{
+	alert_sinks::AlertSinks,
+	config,
+	market_hours::{format_local_time, market_session, session_end, unix_now, MarketSession, RefreshPolicy},
+	quote_book::{QuoteBook, StockInfo},
+	rule_language::{parse, BarInterval, Expr, ParseError},
+	storage,
+	yahoo_api_helper::{fetch_bars, YahooFetchHandle},
+};
+
+const ALERTS_FILE: &str = "alerts.json";
+const ALERT_LOG_FILE: &str = "alert_log.json";
+/// Oldest log entries are dropped beyond this
+const MAX_LOG_ENTRIES: usize = 500;
+/// Time the data provider gets to finalize a bar before it's fetched
+const BAR_CLOSE_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum AlertCondition {
+	PriceAbove(f64),
+	PriceBelow(f64),
+	/// Absolute percent change since the previous close
+	DayChangeAbove(f64),
+	/// Today's volume as a multiple of the average daily volume
+	VolumeAboveAverage(f64),
+	YearHigh,
+	YearLow,
+	/// Rule in the rule language, evaluated whenever a bar of `interval` closes
+	Expression { expression: String, interval: BarInterval },
+}
+
+impl AlertCondition {
+	pub const ALL: [AlertCondition; 7] = [
+		AlertCondition::PriceAbove(0.),
+		AlertCondition::PriceBelow(0.),
+		AlertCondition::DayChangeAbove(5.),
+		AlertCondition::VolumeAboveAverage(2.),
+		AlertCondition::YearHigh,
+		AlertCondition::YearLow,
+		AlertCondition::Expression { expression: String::new(), interval: BarInterval::FiveMinutes },
+	];
+
+	pub fn label(&self) -> &'static str {
+		match self {
+			AlertCondition::PriceAbove(_) => "Price crosses above",
+			AlertCondition::PriceBelow(_) => "Price crosses below",
+			AlertCondition::DayChangeAbove(_) => "Day change exceeds %",
+			AlertCondition::VolumeAboveAverage(_) => "Volume above × average",
+			AlertCondition::YearHigh => "New 52-week high",
+			AlertCondition::YearLow => "New 52-week low",
+			AlertCondition::Expression { .. } => "Expression",
+		}
+	}
+
+	fn value_mut(&mut self) -> Option<&mut f64> {
+		match self {
+			AlertCondition::PriceAbove(value) | AlertCondition::PriceBelow(value) | AlertCondition::DayChangeAbove(value) | AlertCondition::VolumeAboveAverage(value) => Some(value),
+			AlertCondition::YearHigh | AlertCondition::YearLow | AlertCondition::Expression { .. } => None,
+		}
+	}
+
+	fn same_kind(&self, other: &AlertCondition) -> bool {
+		std::mem::discriminant(self) == std::mem::discriminant(other)
+	}
+
+	/// Crossings only fire once the price was seen on the other side of the level
+	fn is_crossing(&self) -> bool {
+		matches!(self, AlertCondition::PriceAbove(_) | AlertCondition::PriceBelow(_))
+	}
+
+	/// Whether evaluating needs the symbol's 52-week range or average volume
+	fn needs_details(&self) -> bool {
+		matches!(self, AlertCondition::VolumeAboveAverage(_) | AlertCondition::YearHigh | AlertCondition::YearLow)
+	}
+
+	/// Whether the condition holds for the latest quote and what to report if
+	/// it fires, `None` while the needed data isn't fetched yet
+	fn evaluate(&self, stock: &StockInfo) -> Option<(bool, String)> {
+		stock.metadata.as_ref()?;
+		let price = stock.price;
+		let currency = stock.currency();
+		let details = stock.details.as_ref();
+
+		Some(match *self {
+			AlertCondition::PriceAbove(level) => (price > level, format!("crossed above {level:.2} at {price:.2} {currency}")),
+			AlertCondition::PriceBelow(level) => (price < level, format!("crossed below {level:.2} at {price:.2} {currency}")),
+			AlertCondition::DayChangeAbove(percent) => {
+				let basis = stock.change_basis()?;
+				let change = (price - basis) / basis * 100.;
+				(change.abs() >= percent, format!("moved {change:+.2}% today to {price:.2} {currency}"))
+			},
+			AlertCondition::VolumeAboveAverage(multiple) => {
+				let average = details?.average_volume.filter(|average| *average > 0.)?;
+				let volume: f64 = stock.intraday.iter().map(|quote| quote.volume as f64).sum();
+				(volume >= multiple * average, format!("volume {:.1}× the daily average", volume / average))
+			},
+			// The 1-year range includes today, so reaching it means a new extreme
+			AlertCondition::YearHigh => {
+				let (_, high) = details?.year_range?;
+				(price >= high, format!("new 52-week high at {price:.2} {currency}"))
+			},
+			AlertCondition::YearLow => {
+				let (low, _) = details?.year_range?;
+				(price <= low, format!("new 52-week low at {price:.2} {currency}"))
+			},
+			// Evaluated on bars by `BarFeed` instead of the latest quote
+			AlertCondition::Expression { .. } => return None,
+		})
+	}
+}
+
+/// Bars an expression rule is evaluated on, fetched again once the next bar closed
+#[derive(Default)]
+struct BarFeed {
+	fetch_handle: YahooFetchHandle,
+	bars: Vec<Quote>,
+	metadata: Option<YMetaData>,
+	next_fetch: Option<Instant>,
+	/// Open time of the last closed bar the rule was evaluated on
+	last_evaluated: Option<u64>,
+	/// Source of the last parsed expression and its result
+	parsed: Option<(String, Result<Expr, ParseError>)>,
+}
+
+impl BarFeed {
+	/// Fetches bars when due and evaluates `expression` once on every newly closed bar
+	fn poll(&mut self, ctx: &Context, symbol: &str, expression: &str, interval: BarInterval, refresh_policy: &RefreshPolicy) -> Option<(bool, String)> {
+		let now = Instant::now();
+		let due = self.next_fetch.is_none_or(|next_fetch| next_fetch <= now);
+		if (due || self.fetch_handle.is_some()) && fetch_bars(ctx, &mut self.fetch_handle, symbol, interval.api_interval(), interval.api_range(), &mut self.bars, &mut self.metadata) {
+			self.next_fetch = Some(self.next_bar_close(interval, refresh_policy));
+		}
+		if let (None, Some(next_fetch)) = (&self.fetch_handle, self.next_fetch) {
+			ctx.request_repaint_after(next_fetch.saturating_duration_since(now));
+		}
+
+		let index = self.last_closed_bar(interval)?;
+		let bar = &self.bars[index];
+		if self.last_evaluated == Some(bar.timestamp) {
+			return None;
+		}
+		self.last_evaluated = Some(bar.timestamp);
+
+		let Ok(expr) = parsed(&mut self.parsed, expression) else {
+			return None;
+		};
+		let holds = expr.holds(&self.bars, index)?;
+		let timezone = config::get().timezone;
+		let bar_time = format_local_time(bar.timestamp as i64, timezone.offset(self.metadata.as_ref().map_or(0, |metadata| metadata.gmtoffset)));
+		let message = format!("`{expression}` on the {} bar of {bar_time} {}, close {:.2}", interval.label(), timezone.label(), bar.close);
+
+		Some((holds, message))
+	}
+
+	/// The latest bar may still be forming, in which case the one before it is the last closed one
+	fn last_closed_bar(&self, interval: BarInterval) -> Option<usize> {
+		let last = self.bars.len().checked_sub(1)?;
+		let now = unix_now();
+		let closed = match interval {
+			BarInterval::OneDay => self.metadata.as_ref().is_some_and(|metadata| market_session(metadata, now) != MarketSession::Open),
+			_ => self.bars[last].timestamp as i64 + interval.seconds() <= now,
+		};
+
+		if closed { Some(last) } else { last.checked_sub(1) }
+	}
+
+	/// While the market is open bars close on a fixed grid, otherwise the
+	/// refresh policy decides when to look for new ones
+	fn next_bar_close(&self, interval: BarInterval, refresh_policy: &RefreshPolicy) -> Instant {
+		let now = unix_now();
+		let Some(metadata) = self.metadata.as_ref().filter(|metadata| market_session(metadata, now) == MarketSession::Open) else {
+			return refresh_policy.next_refresh(self.metadata.as_ref());
+		};
+
+		let close = match interval {
+			BarInterval::OneDay => match session_end(metadata, now) {
+				Some(end) => end,
+				None => return refresh_policy.next_refresh(Some(metadata)),
+			},
+			_ => {
+				let mut close = self.bars.last().map_or(now, |bar| bar.timestamp as i64) + interval.seconds();
+				while close <= now {
+					close += interval.seconds();
+				}
+				close
+			},
+		};
+
+		Instant::now() + Duration::from_secs((close - now) as u64) + BAR_CLOSE_DELAY
+	}
+}
+
+/// Parses `expression` unless it's the one parsed last time
+fn parsed<'a>(cache: &'a mut Option<(String, Result<Expr, ParseError>)>, expression: &str) -> &'a Result<Expr, ParseError> {
+	if cache.as_ref().is_none_or(|(source, _)| source != expression) {
+		*cache = Some((expression.to_string(), parse(expression)));
+	}
+	&cache.as_ref().unwrap().1
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AlertRule {
+	pub id: u64,
+	pub symbol: String,
+	pub condition: AlertCondition,
+	pub enabled: bool,
+	/// Whether the condition held at the last evaluation, so a rule only fires
+	/// when it starts to hold instead of on every refresh. `None` until evaluated.
+	active: Option<bool>,
+	#[serde(skip)]
+	feed: BarFeed,
+}
+
+/// A fired alert as shown in the log
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AlertEvent {
+	/// Unix time it fired
+	pub time: i64,
+	pub rule_id: u64,
+	pub symbol: String,
+	pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct AlertRules {
+	rules: Vec<AlertRule>,
+	next_id: u64,
+}
+
+/// Alert rules evaluated against the quote book, and the log of fired alerts
+pub struct Alerts {
+	rules: AlertRules,
+	pub log: Vec<AlertEvent>,
+	/// Alerts fired since the log was last looked at
+	pub unseen: usize,
+	sinks: AlertSinks,
+	new_symbol: String,
+	new_condition: AlertCondition,
+}
+
+impl Alerts {
+	pub fn new() -> Self {
+		Self {
+			rules: AlertRules::default(),
+			log: vec![],
+			unseen: 0,
+			sinks: AlertSinks::new(),
+			new_symbol: String::new(),
+			new_condition: AlertCondition::PriceAbove(0.),
+		}
+	}
+
+	pub fn load() -> Self {
+		Self {
+			rules: storage::load(ALERTS_FILE).unwrap_or_default(),
+			log: storage::load(ALERT_LOG_FILE).unwrap_or_default(),
+			sinks: AlertSinks::load(),
+			..Self::new()
+		}
+	}
+
+	fn save_rules(&self) {
+		storage::save(ALERTS_FILE, &self.rules);
+	}
+
+	/// Symbols of enabled rules, which need their quotes kept fresh
+	pub fn symbols(&self) -> impl Iterator<Item = &String> + Clone {
+		self.rules.rules.iter().filter(|rule| rule.enabled).map(|rule| &rule.symbol)
+	}
+
+	pub fn add_rule(&mut self, symbol: &str, condition: AlertCondition) {
+		self.rules.rules.push(AlertRule {
+			id: self.rules.next_id,
+			symbol: symbol.to_string(),
+			condition,
+			enabled: true,
+			active: None,
+			feed: BarFeed::default(),
+		});
+		self.rules.next_id += 1;
+		self.save_rules();
+	}
+
+	/// Evaluates every enabled rule against the latest quotes and returns the
+	/// alerts that fired, which are also logged and sent to the delivery targets
+	pub fn evaluate(&mut self, ctx: &Context, quote_book: &mut QuoteBook, refresh_policy: &RefreshPolicy) -> Vec<AlertEvent> {
+		let mut fired = vec![];
+		let mut changed = false;
+
+		for rule in self.rules.rules.iter_mut().filter(|rule| rule.enabled) {
+			if rule.condition.needs_details() {
+				quote_book.fetch_details(ctx, &rule.symbol);
+			}
+			let outcome = match &rule.condition {
+				AlertCondition::Expression { expression, interval } => rule.feed.poll(ctx, &rule.symbol, expression, *interval, refresh_policy),
+				condition => quote_book.get(&rule.symbol).and_then(|stock| condition.evaluate(stock)),
+			};
+			let Some((holds, message)) = outcome else {
+				continue;
+			};
+
+			let fires = holds && match rule.active {
+				Some(active) => !active,
+				None => !rule.condition.is_crossing(),
+			};
+			if fires {
+				fired.push(AlertEvent {
+					time: unix_now(),
+					rule_id: rule.id,
+					symbol: rule.symbol.clone(),
+					message,
+				});
+			}
+			if rule.active != Some(holds) {
+				rule.active = Some(holds);
+				changed = true;
+			}
+		}
+
+		if changed {
+			self.save_rules();
+		}
+		if !fired.is_empty() {
+			self.unseen += fired.len();
+			self.log.extend(fired.iter().cloned());
+			let excess = self.log.len().saturating_sub(MAX_LOG_ENTRIES);
+			self.log.drain(..excess);
+			storage::save(ALERT_LOG_FILE, &self.log);
+			self.sinks.deliver(ctx, &fired);
+		}
+
+		fired
+	}
+
+	pub fn show(&mut self, ui: &mut Ui, quote_book: &QuoteBook) {
+		self.unseen = 0;
+		let mut changed = false;
+		let mut remove = None;
+
+		ui.heading("Alert rules");
+		ui.add_space(8.);
+		Grid::new("alert_rules").striped(true).spacing([12., 6.]).show(ui, |ui| {
+			for title in ["On", "Symbol", "Condition", "Value", "State", ""] {
+				ui.label(RichText::new(title).strong());
+			}
+			ui.end_row();
+
+			for (index, rule) in self.rules.rules.iter_mut().enumerate() {
+				let mut reset = ui.checkbox(&mut rule.enabled, "").changed();
+				ui.label(RichText::new(&rule.symbol).strong());
+				reset |= condition_editor(ui, ("alert_condition", rule.id), &mut rule.condition);
+				if reset {
+					rule.active = None;
+					rule.feed = BarFeed::default();
+					changed = true;
+				}
+
+				ui.label(match rule.active {
+					Some(true) => RichText::new("Triggered").color(Color32::from_rgb(230, 170, 40)),
+					Some(false) => RichText::new("Armed").color(Color32::GREEN),
+					None => RichText::new("Waiting for data").weak(),
+				});
+				if ui.small_button("✖").on_hover_text("Delete rule").clicked() {
+					remove = Some(index);
+				}
+				ui.end_row();
+			}
+		});
+
+		ui.add_space(8.);
+		ui.horizontal(|ui| {
+			ui.add(TextEdit::singleline(&mut self.new_symbol).hint_text("Symbol").desired_width(80.));
+			condition_editor(ui, "new_alert_condition", &mut self.new_condition);
+
+			let symbol = self.new_symbol.trim().to_uppercase();
+			let valid = match &self.new_condition {
+				AlertCondition::Expression { expression, .. } => parse(expression).is_ok(),
+				_ => true,
+			};
+			if ui.add_enabled(!symbol.is_empty() && valid, Button::new("Add alert")).clicked() {
+				self.add_rule(&symbol, self.new_condition.clone());
+				self.new_symbol.clear();
+			}
+		});
+
+		if let Some(index) = remove {
+			self.rules.rules.remove(index);
+			changed = true;
+		}
+		if changed {
+			self.save_rules();
+		}
+
+		ui.add_space(12.);
+		ui.separator();
+		ui.heading("Delivery");
+		ui.add_space(8.);
+		self.sinks.show(ui);
+
+		ui.add_space(12.);
+		ui.separator();
+		ui.horizontal(|ui| {
+			ui.heading("Alert log");
+			if ui.add_enabled(!self.log.is_empty(), Button::new("Clear")).clicked() {
+				self.log.clear();
+				storage::save(ALERT_LOG_FILE, &self.log);
+			}
+		});
+		ui.add_space(8.);
+
+		if self.log.is_empty() {
+			ui.label("No alerts fired yet.");
+		}
+		let timezone = config::get().timezone;
+		Grid::new("alert_log").striped(true).spacing([12., 4.]).show(ui, |ui| {
+			for event in self.log.iter().rev() {
+				let gmtoffset = quote_book.get(&event.symbol).and_then(|stock| stock.metadata.as_ref()).map_or(0, |metadata| metadata.gmtoffset);
+				ui.label(format!("{} {}", format_local_time(event.time, timezone.offset(gmtoffset)), timezone.label()));
+				ui.label(RichText::new(&event.symbol).strong());
+				ui.label(&event.message);
+				ui.end_row();
+			}
+		});
+	}
+}
+
+impl Default for Alerts {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Kind selector and value of a condition, returns whether it changed
+fn condition_editor(ui: &mut Ui, id_salt: impl std::hash::Hash, condition: &mut AlertCondition) -> bool {
+	let id = Id::new(id_salt);
+	let mut changed = false;
+	ComboBox::from_id_salt(id)
+		.selected_text(condition.label())
+		.width(180.)
+		.show_ui(ui, |ui| {
+			for kind in AlertCondition::ALL {
+				if ui.selectable_label(condition.same_kind(&kind), kind.label()).clicked() && !condition.same_kind(&kind) {
+					*condition = kind;
+					changed = true;
+				}
+			}
+		});
+
+	match condition {
+		AlertCondition::Expression { expression, interval } => {
+			ui.vertical(|ui| {
+				let mut draft_error = None;
+				ui.horizontal(|ui| {
+					// Typed into a draft that's applied once the edit is done and parses, so a
+					// rule isn't reset and refetched on every keystroke
+					let draft_id = id.with("draft");
+					let mut draft = ui.data(|data| data.get_temp::<String>(draft_id)).unwrap_or_else(|| expression.clone());
+					let response = ui.add(TextEdit::singleline(&mut draft).hint_text("close crosses_above sma(50)").desired_width(260.).code_editor());
+					if response.lost_focus() && draft != *expression && parse(&draft).is_ok() {
+						*expression = draft.clone();
+						changed = true;
+					}
+					draft_error = (!draft.trim().is_empty()).then(|| parse(&draft).err()).flatten();
+					if response.has_focus() || draft != *expression {
+						ui.data_mut(|data| data.insert_temp(draft_id, draft));
+					} else {
+						ui.data_mut(|data| data.remove::<String>(draft_id));
+					}
+					ComboBox::from_id_salt(id.with("interval"))
+						.selected_text(interval.label())
+						.width(50.)
+						.show_ui(ui, |ui| {
+							for option in BarInterval::ALL {
+								changed |= ui.selectable_value(interval, option, option.label()).changed();
+							}
+						});
+				});
+				if let Some(err) = draft_error {
+					ui.label(RichText::new(err.to_string()).color(Color32::RED).small());
+				}
+			});
+		},
+		condition => match condition.value_mut() {
+			Some(value) => {
+				// Dragged like the expression is typed, into a draft that's applied once the drag or edit ends
+				let draft_id = id.with("value");
+				let mut draft = ui.data(|data| data.get_temp::<f64>(draft_id)).unwrap_or(*value);
+				let response = ui.add(DragValue::new(&mut draft).speed(0.1).range(0.0..=f64::MAX));
+				if (response.drag_stopped() || response.lost_focus()) && draft != *value {
+					*value = draft;
+					changed = true;
+				}
+				if response.dragged() || response.has_focus() {
+					ui.data_mut(|data| data.insert_temp(draft_id, draft));
+				} else {
+					ui.data_mut(|data| data.remove::<f64>(draft_id));
+				}
+			},
+			None => {
+				ui.label("");
+			},
+		},
+	}
+
+	changed
+}
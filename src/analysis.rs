@@ -0,0 +1,222 @@
+use std::{f64::consts::PI, ops::RangeInclusive};
+
+use eframe::egui::*;
+use egui_plot::*;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{config, ledger::format_date, seasonality::SeasonalityView, statistics::{drawdowns, is_intraday, mean, returns, skew_kurtosis, standard_deviation}, stock_graph::StockGraph};
+
+/// What the chart view shows for the current symbol
+#[derive(PartialEq, Eq, Clone, Copy, Serialize, Deserialize, Default)]
+pub enum AnalysisTab {
+	#[default]
+	Chart,
+	Returns,
+	Drawdown,
+	Seasonality,
+}
+
+/// Histogram of the bar returns, in percent
+struct ReturnsDistribution {
+	/// Bin centers and counts
+	bins: Vec<[f64; 2]>,
+	bin_width: f64,
+	count: usize,
+	mean: f64,
+	deviation: Option<f64>,
+	/// Skewness and excess kurtosis
+	moments: Option<(f64, f64)>,
+}
+
+impl ReturnsDistribution {
+	fn new(returns: &[f64]) -> Option<Self> {
+		let mean = mean(returns)?;
+		let (min, max) = returns.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| (min.min(*value), max.max(*value)));
+
+		// Square-root rule, within what still reads as a histogram
+		let bin_count = ((returns.len() as f64).sqrt().ceil() as usize).clamp(5, 60);
+		let bin_width = if max > min { (max - min) / bin_count as f64 } else { 1. };
+		let mut counts = vec![0.; bin_count];
+		for value in returns {
+			let bin = (((value - min) / bin_width) as usize).min(bin_count - 1);
+			counts[bin] += 1.;
+		}
+
+		Some(Self {
+			bins: counts.into_iter().enumerate().map(|(bin, count)| [min + (bin as f64 + 0.5) * bin_width, count]).collect(),
+			bin_width,
+			count: returns.len(),
+			mean,
+			deviation: standard_deviation(returns).filter(|deviation| *deviation > 0.),
+			moments: skew_kurtosis(returns),
+		})
+	}
+
+	/// Normal distribution with the same mean and deviation, scaled to the bin counts
+	fn normal_curve(&self) -> Option<Vec<[f64; 2]>> {
+		let deviation = self.deviation?;
+		let (start, end) = (self.mean - 4. * deviation, self.mean + 4. * deviation);
+		let scale = self.count as f64 * self.bin_width / (deviation * (2. * PI).sqrt());
+		Some((0..=200).map(|step| {
+			let x = start + (end - start) * step as f64 / 200.;
+			[x, scale * (-0.5 * ((x - self.mean) / deviation).powi(2)).exp()]
+		}).collect())
+	}
+}
+
+/// Returns histogram and underwater chart of the bars `StockGraph` holds for the selected range,
+/// and the seasonality of the symbol's whole history
+pub struct AnalysisView {
+	pub tab: AnalysisTab,
+	seasonality_view: SeasonalityView,
+	/// Data version of the chart the views were built from
+	built_version: Option<u64>,
+	distribution: Option<ReturnsDistribution>,
+	/// Percent below the running peak
+	drawdown: Vec<[f64; 2]>,
+	intraday: bool,
+}
+
+impl AnalysisView {
+	pub fn new(tab: AnalysisTab) -> Self {
+		Self {
+			tab,
+			seasonality_view: SeasonalityView::new(),
+			built_version: None,
+			distribution: None,
+			drawdown: vec![],
+			intraday: false,
+		}
+	}
+
+	pub fn tab_bar(&mut self, ui: &mut Ui) {
+		ui.horizontal(|ui| {
+			ui.selectable_value(&mut self.tab, AnalysisTab::Chart, "Chart");
+			ui.selectable_value(&mut self.tab, AnalysisTab::Returns, "Returns distribution");
+			ui.selectable_value(&mut self.tab, AnalysisTab::Drawdown, "Drawdown");
+			ui.selectable_value(&mut self.tab, AnalysisTab::Seasonality, "Seasonality");
+		});
+	}
+
+	fn rebuild(&mut self, stock_graph: &StockGraph) {
+		if self.built_version == Some(stock_graph.data_version()) {
+			return;
+		}
+
+		self.distribution = ReturnsDistribution::new(&returns(&stock_graph.price_data));
+		self.drawdown = drawdowns(&stock_graph.price_data);
+		self.intraday = is_intraday(&stock_graph.price_data);
+		self.built_version = Some(stock_graph.data_version());
+	}
+
+	/// Shows the selected analysis tab, the chart itself is left to the caller
+	pub fn show(&mut self, ui: &mut Ui, stock_graph: &mut StockGraph) {
+		if self.tab == AnalysisTab::Seasonality {
+			// Always over the whole history, the chart's range doesn't apply
+			self.seasonality_view.show(ui, stock_graph.ticker());
+			return;
+		}
+
+		self.rebuild(stock_graph);
+
+		match self.tab {
+			AnalysisTab::Chart | AnalysisTab::Seasonality => {},
+			AnalysisTab::Returns => self.show_returns(ui, stock_graph),
+			AnalysisTab::Drawdown => self.show_drawdown(ui, stock_graph),
+		}
+
+		stock_graph.range_selector(ui);
+	}
+
+	fn show_returns(&self, ui: &mut Ui, stock_graph: &StockGraph) {
+		let Some(distribution) = &self.distribution else {
+			ui.label(RichText::new("Not enough data in this range").weak());
+			return;
+		};
+
+		ui.horizontal(|ui| {
+			ui.label(format!("{} returns per {} bar", distribution.count, stock_graph.interval()));
+			ui.separator();
+			ui.label(format!("Mean {:+.3}%", distribution.mean));
+			ui.separator();
+			ui.label(distribution.deviation.map_or("Std dev -".to_string(), |deviation| format!("Std dev {deviation:.3}%")));
+			ui.separator();
+			match distribution.moments {
+				Some((skew, kurtosis)) => {
+					ui.label(format!("Skew {skew:.2}"));
+					ui.separator();
+					ui.label(format!("Excess kurtosis {kurtosis:.2}"))
+						.on_hover_text("Above 0 means fatter tails than the normal distribution");
+				},
+				None => {
+					ui.label("Skew -");
+				},
+			}
+		});
+
+		let config = config::get();
+		Plot::new("returns_distribution")
+			.legend(Legend::default())
+			.x_axis_formatter(|mark, _range| format!("{:.1}%", mark.value))
+			.label_formatter(|_name, point| format!("Return: {:.2}%\nCount: {:.0}", point.x, point.y))
+			.allow_scroll(false)
+			.show(ui, |plot_ui| {
+				let bars = distribution.bins.iter()
+					.map(|bin| {
+						let bar = Bar::new(bin[0], bin[1]).width(distribution.bin_width * 0.95);
+						match config.colors.change(bin[0]) {
+							Some(color) => bar.fill(color.gamma_multiply(0.6)),
+							None => bar,
+						}
+					})
+					.collect();
+				plot_ui.bar_chart(BarChart::new(bars).name("Returns"));
+
+				if let Some(curve) = distribution.normal_curve() {
+					plot_ui.line(Line::new(PlotPoints::from(curve)).name("Normal").style(LineStyle::dashed_loose()));
+				}
+			});
+	}
+
+	fn show_drawdown(&self, ui: &mut Ui, stock_graph: &StockGraph) {
+		let Some(current) = self.drawdown.last() else {
+			ui.label(RichText::new("Not enough data in this range").weak());
+			return;
+		};
+		let deepest = self.drawdown.iter().min_by(|a, b| a[1].total_cmp(&b[1])).unwrap_or(current);
+
+		let offset = config::get().timezone.offset(stock_graph.metadata.as_ref().map_or(0, |metadata| metadata.gmtoffset)) as i64;
+		let intraday = self.intraday;
+		let format_time = move |timestamp: f64| match OffsetDateTime::from_unix_timestamp(timestamp as i64 + offset) {
+			Ok(time) if intraday => format!("{} {:02}:{:02}", format_date(time.date()), time.hour(), time.minute()),
+			Ok(time) => format_date(time.date()),
+			Err(_) => String::new(),
+		};
+
+		ui.horizontal(|ui| {
+			ui.label(format!("Deepest {:.2}% on {}", deepest[1], format_time(deepest[0])));
+			ui.separator();
+			ui.label(format!("Now {:.2}% below the peak", -current[1]));
+		});
+
+		Plot::new("drawdown_chart")
+			.x_axis_formatter(move |mark: GridMark, _range: &RangeInclusive<f64>| format_time(mark.value))
+			.y_axis_formatter(|mark, _range| format!("{:.0}%", mark.value))
+			.label_formatter(move |_name, point| format!("{}\nDrawdown: {:.2}%", format_time(point.x), point.y))
+			.include_y(0.)
+			.allow_scroll(false)
+			.show(ui, |plot_ui| {
+				plot_ui.line(Line::new(PlotPoints::from(self.drawdown.clone()))
+					.color(config::get().colors.loss)
+					.fill(0.)
+					.name("Drawdown"));
+			});
+	}
+}
+
+impl Default for AnalysisView {
+	fn default() -> Self {
+		Self::new(AnalysisTab::default())
+	}
+}
@@ -0,0 +1,121 @@
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+use time::{Date, Duration, OffsetDateTime};
+use yahoo_finance_api::YMetaData;
+
+use crate::{ledger::{format_date, parse_date}, market_hours::day_change_basis};
+
+/// What the header's and the side panel's change is measured from
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChangeBasis {
+	/// First price of the loaded range
+	#[default]
+	RangeStart,
+	PreviousClose,
+	/// Close of this day, or of the last trading day before it
+	AnchorDate(Date),
+}
+
+impl ChangeBasis {
+	pub fn label(&self) -> String {
+		match self {
+			Self::RangeStart => "Range start".to_string(),
+			Self::PreviousClose => "Previous close".to_string(),
+			Self::AnchorDate(date) => format!("Since {}", format_date(*date)),
+		}
+	}
+
+	fn same_kind(&self, other: &Self) -> bool {
+		std::mem::discriminant(self) == std::mem::discriminant(other)
+	}
+
+	pub fn anchor_date(&self) -> Option<Date> {
+		match self {
+			Self::AnchorDate(date) => Some(*date),
+			_ => None,
+		}
+	}
+
+	/// Price the change is measured from, out of `prices` (timestamp, price) in time order.
+	/// None if there's no price for the basis, e.g. an anchor date before the first price.
+	pub fn reference(&self, metadata: &YMetaData, prices: impl IntoIterator<Item = (i64, f64)>) -> Option<f64> {
+		match self {
+			Self::RangeStart => prices.into_iter().next().map(|(_, price)| price),
+			Self::PreviousClose => day_change_basis(metadata, prices),
+			Self::AnchorDate(date) => {
+				// Dates are the exchange's, the anchor day's prices count up to its end
+				let day_end = date.next_day()?.midnight().assume_utc().unix_timestamp() - metadata.gmtoffset as i64;
+				let mut prices = prices.into_iter().peekable();
+				if prices.peek().is_none_or(|(timestamp, _)| *timestamp >= day_end) {
+					return None;
+				}
+				prices.take_while(|(timestamp, _)| *timestamp < day_end).last().map(|(_, price)| price)
+			},
+		}
+	}
+}
+
+/// Selector of the change basis with the anchor date being typed
+pub struct ChangeBasisPicker {
+	pub basis: ChangeBasis,
+	anchor_text: String,
+}
+
+impl ChangeBasisPicker {
+	pub fn new(basis: ChangeBasis) -> Self {
+		let anchor = basis.anchor_date().unwrap_or_else(|| OffsetDateTime::now_utc().date() - Duration::days(30));
+		Self {
+			basis,
+			anchor_text: format_date(anchor),
+		}
+	}
+
+	/// Returns whether the basis changed
+	pub fn ui(&mut self, ui: &mut Ui) -> bool {
+		let mut changed = false;
+		let anchor = parse_date(&self.anchor_text);
+
+		ComboBox::from_id_salt("change_basis")
+			.selected_text(match self.basis {
+				ChangeBasis::AnchorDate(_) => "Anchor date".to_string(),
+				basis => basis.label(),
+			})
+			.width(110.)
+			.show_ui(ui, |ui| {
+				let anchor = ChangeBasis::AnchorDate(anchor.unwrap_or_else(|| OffsetDateTime::now_utc().date()));
+				for (option, label) in [(ChangeBasis::RangeStart, "Range start"), (ChangeBasis::PreviousClose, "Previous close"), (anchor, "Anchor date")] {
+					if ui.selectable_label(self.basis.same_kind(&option), label).clicked() && !self.basis.same_kind(&option) {
+						self.basis = option;
+						changed = true;
+					}
+				}
+			})
+			.response
+			.on_hover_text("What the change is measured from");
+
+		if let ChangeBasis::AnchorDate(date) = &mut self.basis {
+			let response = ui.add(TextEdit::singleline(&mut self.anchor_text)
+				.desired_width(80.)
+				.hint_text("YYYY-MM-DD")
+				.text_color_opt(anchor.is_none().then_some(Color32::RED)));
+			if response.lost_focus() {
+				match parse_date(&self.anchor_text) {
+					Some(parsed) if parsed != *date => {
+						*date = parsed;
+						changed = true;
+					},
+					Some(_) => {},
+					None => self.anchor_text = format_date(*date),
+				}
+			}
+		}
+
+		changed
+	}
+}
+
+impl Default for ChangeBasisPicker {
+	fn default() -> Self {
+		Self::new(ChangeBasis::default())
+	}
+}
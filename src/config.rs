@@ -0,0 +1,476 @@
+use std::{env, fmt::Display, fs, ops::Range, path::{Path, PathBuf}, sync::OnceLock, time::Duration};
+
+use eframe::egui::Color32;
+use toml_edit::{ImDocument, Item, TableLike};
+
+use crate::{market_hours::RefreshPolicy, storage::{self, APP_NAME}, watchlist::Watchlist};
+
+const CONFIG_FILE: &str = "config.toml";
+
+pub const USAGE: &str = "\
+Usage: stock_visual [OPTIONS]
+
+Options:
+  --ticker <SYMBOL>     Symbol to chart on startup, e.g. AAPL
+  --range <RANGE>       Chart range: Regular, 1d, 5d, 1mo, 3mo, 6mo, 1y, 2y, 5y, 10y, ytd or max
+  --interval <INTERVAL> Bar interval: 1m, 2m, 5m, 15m, 30m, 60m, 90m, 1h, 1d, 5d, 1wk, 1mo or 3mo
+  --config <PATH>       Config file to read instead of the default one
+  --provider <NAME>     Market data provider: yahoo
+  -h, --help            Print this help";
+
+pub const RANGES: [&str; 12] = ["Regular", "1d", "5d", "1mo", "3mo", "6mo", "1y", "2y", "5y", "10y", "ytd", "max"];
+pub const INTERVALS: [&str; 13] = ["1m", "2m", "5m", "15m", "30m", "60m", "90m", "1h", "1d", "5d", "1wk", "1mo", "3mo"];
+
+/// Longest range Yahoo serves bars of each intraday interval for
+const INTRADAY_RANGES: [(&[&str], &[&str]); 3] = [
+	(&["1m"], &["Regular", "1d", "5d"]),
+	(&["2m", "5m", "15m", "30m", "90m"], &["Regular", "1d", "5d", "1mo"]),
+	(&["60m", "1h"], &["Regular", "1d", "5d", "1mo", "3mo", "6mo", "1y", "2y", "ytd"]),
+];
+
+/// Where market data is fetched from
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+	Yahoo,
+}
+
+impl Provider {
+	pub const ALL: [Provider; 1] = [Provider::Yahoo];
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Provider::Yahoo => "yahoo",
+		}
+	}
+
+	fn parse(name: &str) -> Result<Self, String> {
+		Self::ALL.into_iter()
+			.find(|provider| provider.name().eq_ignore_ascii_case(name))
+			.ok_or_else(|| {
+				let names: Vec<_> = Self::ALL.iter().map(Provider::name).collect();
+				format!("unknown provider '{name}', available: {}", names.join(", "))
+			})
+	}
+}
+
+/// Which clock times are shown in
+#[derive(Clone, Copy)]
+pub enum TimeZone {
+	/// The local time of the symbol's exchange
+	Exchange,
+	/// A fixed offset from UTC in seconds
+	Offset(i32),
+}
+
+impl TimeZone {
+	/// Seconds to add to a UTC timestamp of a symbol traded `gmtoffset` seconds from UTC
+	pub fn offset(&self, gmtoffset: i32) -> i32 {
+		match self {
+			TimeZone::Exchange => gmtoffset,
+			TimeZone::Offset(offset) => *offset,
+		}
+	}
+
+	/// Suffix for displayed times, e.g. "exchange time" or "UTC+02:00"
+	pub fn label(&self) -> String {
+		match self {
+			TimeZone::Exchange => "exchange time".to_string(),
+			TimeZone::Offset(0) => "UTC".to_string(),
+			TimeZone::Offset(offset) => {
+				let sign = if *offset < 0 { '-' } else { '+' };
+				format!("UTC{sign}{:02}:{:02}", offset.abs() / 3600, offset.abs() % 3600 / 60)
+			},
+		}
+	}
+
+	/// Accepts "exchange", "UTC" or an offset like "+02:00" or "-5"
+	fn parse(text: &str) -> Result<Self, String> {
+		if text.eq_ignore_ascii_case("exchange") {
+			return Ok(TimeZone::Exchange);
+		}
+		if text.eq_ignore_ascii_case("utc") {
+			return Ok(TimeZone::Offset(0));
+		}
+
+		let invalid = || format!("'{text}' is not a time zone, expected \"exchange\", \"UTC\" or an offset like \"+02:00\"");
+		let (sign, offset) = match text.split_at_checked(1) {
+			Some(("+", offset)) => (1, offset),
+			Some(("-", offset)) => (-1, offset),
+			_ => return Err(invalid()),
+		};
+		let (hours, minutes) = offset.split_once(':').unwrap_or((offset, "0"));
+		match (hours.parse::<i32>(), minutes.parse::<i32>()) {
+			(Ok(hours @ 0..=14), Ok(minutes @ 0..=59)) => Ok(TimeZone::Offset(sign * (hours * 3600 + minutes * 60))),
+			_ => Err(invalid()),
+		}
+	}
+}
+
+/// Colours of gains, losses and the price chart
+#[derive(Clone, Copy)]
+pub struct Colors {
+	pub gain: Color32,
+	pub loss: Color32,
+	/// `None` lets the plot pick the line colour
+	pub price: Option<Color32>,
+	pub volume: Color32,
+}
+
+impl Colors {
+	pub fn new() -> Self {
+		Self {
+			gain: Color32::GREEN,
+			loss: Color32::RED,
+			price: None,
+			volume: Color32::LIGHT_BLUE,
+		}
+	}
+
+	/// Gain or loss colour by the sign of `change`, `None` when unchanged
+	pub fn change(&self, change: f64) -> Option<Color32> {
+		if change > 0. {
+			Some(self.gain)
+		} else if change < 0. {
+			Some(self.loss)
+		} else {
+			None
+		}
+	}
+}
+
+impl Default for Colors {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Command-line flags, which override the config file
+#[derive(Default)]
+pub struct Args {
+	pub ticker: Option<String>,
+	pub range: Option<String>,
+	pub interval: Option<String>,
+	pub config: Option<PathBuf>,
+	pub provider: Option<String>,
+	pub help: bool,
+}
+
+impl Args {
+	/// Parses `args` without the program name, accepting both `--flag value` and `--flag=value`
+	pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+		let mut parsed = Self::default();
+
+		while let Some(arg) = args.next() {
+			if arg == "-h" || arg == "--help" {
+				parsed.help = true;
+				continue;
+			}
+
+			let (flag, value) = match arg.split_once('=') {
+				Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+				None => (arg, None),
+			};
+			let slot = match flag.as_str() {
+				"--ticker" => &mut parsed.ticker,
+				"--range" => &mut parsed.range,
+				"--interval" => &mut parsed.interval,
+				"--provider" => &mut parsed.provider,
+				"--config" => {
+					let value = value.or_else(|| args.next()).ok_or("--config needs a path")?;
+					parsed.config = Some(PathBuf::from(value));
+					continue;
+				},
+				_ => return Err(format!("unknown argument '{flag}'")),
+			};
+
+			let value = value.or_else(|| args.next()).filter(|value| !value.is_empty())
+				.ok_or_else(|| format!("{flag} needs a value"))?;
+			*slot = Some(value);
+		}
+
+		Ok(parsed)
+	}
+}
+
+/// Startup settings from `config.toml`, with command-line flags applied on top
+pub struct Config {
+	/// Symbol charted when there is no previous session to restore
+	pub ticker: String,
+	pub range: String,
+	/// `None` uses the range's default interval
+	pub interval: Option<String>,
+	pub window_size: [f32; 2],
+	/// Lists the side panel starts with before any were saved
+	pub watchlists: Vec<Watchlist>,
+	/// Only set if the file has a `[refresh]` table, which then wins over the intervals saved from the settings menu
+	pub refresh: Option<RefreshPolicy>,
+	pub colors: Colors,
+	pub timezone: TimeZone,
+	pub provider: Provider,
+	pub user_agent: Option<String>,
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// The settings the app was started with, the defaults until `set` is called
+pub fn get() -> &'static Config {
+	CONFIG.get_or_init(Config::new)
+}
+
+pub fn set(config: Config) {
+	if CONFIG.set(config).is_err() {
+		eprintln!("Error applying config: already set");
+	}
+}
+
+/// `$XDG_CONFIG_HOME/stonitor/config.toml`, or the data directory where there is no XDG config directory
+pub fn default_path() -> Option<PathBuf> {
+	if cfg!(all(unix, not(target_os = "macos"))) {
+		env::var_os("XDG_CONFIG_HOME").map(PathBuf::from).filter(|dir| dir.is_absolute())
+			.or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+			.map(|dir| dir.join(APP_NAME.to_lowercase()).join(CONFIG_FILE))
+	} else {
+		storage::data_path(CONFIG_FILE)
+	}
+}
+
+impl Config {
+	pub fn new() -> Self {
+		Self {
+			ticker: "TSLA".to_string(),
+			range: "Regular".to_string(),
+			interval: None,
+			window_size: [1920., 1200.],
+			watchlists: vec![],
+			refresh: None,
+			colors: Colors::new(),
+			timezone: TimeZone::Exchange,
+			provider: Provider::Yahoo,
+			user_agent: None,
+		}
+	}
+
+	/// Reads the file `--config` points at, or the default one if it exists, and applies `args`
+	pub fn load(args: &Args) -> Result<Self, String> {
+		let mut config = match (&args.config, default_path()) {
+			(Some(path), _) => Self::read(path)?,
+			(None, Some(path)) if path.exists() => Self::read(&path)?,
+			_ => Self::new(),
+		};
+
+		if let Some(ticker) = &args.ticker {
+			config.ticker = ticker.trim().to_uppercase();
+		}
+		if let Some(range) = &args.range {
+			config.range = check_range(range).map_err(|err| format!("--range: {err}"))?;
+		}
+		if let Some(interval) = &args.interval {
+			config.interval = Some(check_interval(interval).map_err(|err| format!("--interval: {err}"))?);
+		}
+		if let Some(provider) = &args.provider {
+			config.provider = Provider::parse(provider).map_err(|err| format!("--provider: {err}"))?;
+		}
+
+		if let Some(interval) = &config.interval {
+			check_interval_range(interval, &config.range)?;
+		}
+
+		Ok(config)
+	}
+
+	fn read(path: &Path) -> Result<Self, String> {
+		let contents = fs::read_to_string(path).map_err(|err| format!("can't read '{}': {err}", path.display()))?;
+		Self::parse(&contents).map_err(|err| format!("in '{}': {err}", path.display()))
+	}
+
+	fn parse(contents: &str) -> Result<Self, String> {
+		let document = ImDocument::parse(contents).map_err(|err| err.to_string())?;
+		let reader = Reader { contents };
+		let root = document.as_table();
+		let mut config = Self::new();
+
+		reader.check_keys(root, "", &["ticker", "range", "interval", "timezone", "window", "refresh", "colors", "provider", "watchlists"])?;
+
+		if let Some(ticker) = reader.string(root, "", "ticker")? {
+			config.ticker = reader.check(root, "", "ticker", match ticker.trim() {
+				"" => Err("can't be empty"),
+				ticker => Ok(ticker.to_uppercase()),
+			})?;
+		}
+		if let Some(range) = reader.string(root, "", "range")? {
+			config.range = reader.check(root, "", "range", check_range(range))?;
+		}
+		if let Some(interval) = reader.string(root, "", "interval")? {
+			let interval = reader.check(root, "", "interval", check_interval(interval))?;
+			reader.check(root, "", "interval", check_interval_range(&interval, &config.range))?;
+			config.interval = Some(interval);
+		}
+		if let Some(timezone) = reader.string(root, "", "timezone")? {
+			config.timezone = reader.check(root, "", "timezone", TimeZone::parse(timezone))?;
+		}
+
+		if let Some(window) = reader.table(root, "", "window")? {
+			reader.check_keys(window, "window", &["width", "height"])?;
+			for (index, key) in ["width", "height"].into_iter().enumerate() {
+				if let Some(size) = reader.integer(window, "window", key)? {
+					config.window_size[index] = reader.check(window, "window", key, match size {
+						200..=10000 => Ok(size as f32),
+						_ => Err("must be between 200 and 10000 pixels"),
+					})?;
+				}
+			}
+		}
+
+		if let Some(refresh) = reader.table(root, "", "refresh")? {
+			reader.check_keys(refresh, "refresh", &["open", "extended", "closed"])?;
+			let mut policy = RefreshPolicy::new();
+			if let Some(seconds) = reader.seconds(refresh, "refresh", "open")? {
+				policy.open_interval = seconds;
+			}
+			if let Some(seconds) = reader.seconds(refresh, "refresh", "extended")? {
+				policy.extended_interval = seconds;
+			}
+			match refresh.get("closed") {
+				Some(item) if item.as_bool() == Some(false) => policy.closed_interval = None,
+				Some(_) => policy.closed_interval = reader.seconds(refresh, "refresh", "closed")?,
+				None => {},
+			}
+			config.refresh = Some(policy);
+		}
+
+		if let Some(colors) = reader.table(root, "", "colors")? {
+			reader.check_keys(colors, "colors", &["gain", "loss", "price", "volume"])?;
+			let color = |key| -> Result<Option<Color32>, String> {
+				let Some(hex) = reader.string(colors, "colors", key)? else {
+					return Ok(None);
+				};
+				reader.check(colors, "colors", key, Color32::from_hex(hex).map(Some).map_err(|_| format!("'{hex}' is not a colour, expected \"#rrggbb\"")))
+			};
+			config.colors = Colors {
+				gain: color("gain")?.unwrap_or(config.colors.gain),
+				loss: color("loss")?.unwrap_or(config.colors.loss),
+				price: color("price")?.or(config.colors.price),
+				volume: color("volume")?.unwrap_or(config.colors.volume),
+			};
+		}
+
+		if let Some(provider) = reader.table(root, "", "provider")? {
+			reader.check_keys(provider, "provider", &["name", "user_agent"])?;
+			if let Some(name) = reader.string(provider, "provider", "name")? {
+				config.provider = reader.check(provider, "provider", "name", Provider::parse(name))?;
+			}
+			config.user_agent = reader.string(provider, "provider", "user_agent")?.map(str::to_string);
+		}
+
+		if let Some(watchlists) = reader.table(root, "", "watchlists")? {
+			for (name, item) in watchlists.iter() {
+				let tickers = item.as_array()
+					.map(|array| array.iter().map(|ticker| ticker.as_str().map(|ticker| ticker.trim().to_uppercase())).collect::<Option<Vec<_>>>())
+					.and_then(|tickers| tickers)
+					.ok_or_else(|| reader.error(item.span(), &format!("watchlists.{name}"), "must be a list of symbols, e.g. [\"AAPL\", \"MSFT\"]"))?;
+				config.watchlists.push(Watchlist { name: name.to_string(), tickers });
+			}
+		}
+
+		Ok(config)
+	}
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+fn check_range(range: &str) -> Result<String, String> {
+	RANGES.iter()
+		.find(|known| known.eq_ignore_ascii_case(range))
+		.map(|known| known.to_string())
+		.ok_or_else(|| format!("unknown range '{range}', expected one of {}", RANGES.join(", ")))
+}
+
+fn check_interval(interval: &str) -> Result<String, String> {
+	INTERVALS.iter()
+		.find(|known| **known == interval)
+		.map(|known| known.to_string())
+		.ok_or_else(|| format!("unknown interval '{interval}', expected one of {}", INTERVALS.join(", ")))
+}
+
+/// Yahoo only keeps intraday bars for a limited time, and the "Regular" range shows a single day
+pub fn check_interval_range(interval: &str, range: &str) -> Result<(), String> {
+	match INTRADAY_RANGES.iter().find(|(intervals, _)| intervals.contains(&interval)) {
+		Some((_, ranges)) if !ranges.contains(&range) => Err(format!("{interval} bars are only available for the ranges {}, not {range}", ranges.join(", "))),
+		None if range == "Regular" => Err(format!("the Regular range needs an intraday interval, not {interval}")),
+		_ => Ok(()),
+	}
+}
+
+/// Looks up settings in the parsed file and points errors at the offending line
+struct Reader<'a> {
+	contents: &'a str,
+}
+
+impl Reader<'_> {
+	fn error(&self, span: Option<Range<usize>>, key: &str, message: impl Display) -> String {
+		match span {
+			Some(span) => {
+				let line = self.contents[..span.start].matches('\n').count() + 1;
+				format!("line {line}: `{key}` {message}")
+			},
+			None => format!("`{key}` {message}"),
+		}
+	}
+
+	fn path(prefix: &str, key: &str) -> String {
+		if prefix.is_empty() { key.to_string() } else { format!("{prefix}.{key}") }
+	}
+
+	/// Attaches the location of `table[key]` to a validation error
+	fn check<T, E: Display>(&self, table: &dyn TableLike, prefix: &str, key: &str, result: Result<T, E>) -> Result<T, String> {
+		result.map_err(|err| self.error(table.get(key).and_then(Item::span), &Self::path(prefix, key), err))
+	}
+
+	fn check_keys(&self, table: &dyn TableLike, prefix: &str, known: &[&str]) -> Result<(), String> {
+		for (key, _) in table.iter() {
+			if !known.contains(&key) {
+				let span = table.key(key).and_then(|key| key.span());
+				return Err(self.error(span, &Self::path(prefix, key), format!("is not a setting, expected one of {}", known.join(", "))));
+			}
+		}
+		Ok(())
+	}
+
+	fn string<'t>(&self, table: &'t dyn TableLike, prefix: &str, key: &str) -> Result<Option<&'t str>, String> {
+		match table.get(key) {
+			Some(item) => item.as_str().map(Some)
+				.ok_or_else(|| self.error(item.span(), &Self::path(prefix, key), format!("must be a string, not {}", item.type_name()))),
+			None => Ok(None),
+		}
+	}
+
+	fn integer(&self, table: &dyn TableLike, prefix: &str, key: &str) -> Result<Option<i64>, String> {
+		match table.get(key) {
+			Some(item) => item.as_integer().map(Some)
+				.ok_or_else(|| self.error(item.span(), &Self::path(prefix, key), format!("must be a whole number, not {}", item.type_name()))),
+			None => Ok(None),
+		}
+	}
+
+	fn seconds(&self, table: &dyn TableLike, prefix: &str, key: &str) -> Result<Option<Duration>, String> {
+		match self.integer(table, prefix, key)? {
+			Some(seconds) => self.check(table, prefix, key, match seconds {
+				1..=86400 => Ok(Some(Duration::from_secs(seconds as u64))),
+				_ => Err("must be between 1 and 86400 seconds"),
+			}),
+			None => Ok(None),
+		}
+	}
+
+	fn table<'t>(&self, table: &'t dyn TableLike, prefix: &str, key: &str) -> Result<Option<&'t dyn TableLike>, String> {
+		match table.get(key) {
+			Some(item) => item.as_table_like().map(Some)
+				.ok_or_else(|| self.error(item.span(), &Self::path(prefix, key), format!("must be a table, not {}", item.type_name()))),
+			None => Ok(None),
+		}
+	}
+}
@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+
+use eframe::egui::*;
+use egui_plot::*;
+use time::{Duration, OffsetDateTime};
+
+use crate::{ledger::format_date, statistics::{correlation, paired_returns}, yahoo_api_helper::{fetch_daily_closes, DailyClosesHandle}};
+
+/// Windows the correlations can be computed over, with their length in days
+const WINDOWS: [(&str, i64); 6] = [("1 month", 30), ("3 months", 91), ("6 months", 182), ("1 year", 365), ("2 years", 730), ("5 years", 1826)];
+const DEFAULT_WINDOW: usize = 3;
+const MIN_CELL_SIZE: f32 = 36.;
+const MAX_CELL_SIZE: f32 = 64.;
+
+/// Pairwise correlations of daily returns, in clustering order
+struct CorrelationMatrix {
+	symbols: Vec<String>,
+	/// `None` where two symbols have too few trading days in common
+	values: Vec<Vec<Option<f64>>>,
+	/// Returns each correlation is over
+	overlaps: Vec<Vec<usize>>,
+}
+
+impl CorrelationMatrix {
+	fn new(symbols: &[String], closes: &HashMap<String, Vec<[f64; 2]>>) -> Self {
+		let empty = vec![];
+		let series: Vec<&Vec<[f64; 2]>> = symbols.iter().map(|symbol| closes.get(symbol).unwrap_or(&empty)).collect();
+
+		let count = symbols.len();
+		let mut values = vec![vec![None; count]; count];
+		let mut overlaps = vec![vec![0; count]; count];
+		for a in 0..count {
+			values[a][a] = Some(1.);
+			overlaps[a][a] = series[a].len().saturating_sub(1);
+			for b in a + 1..count {
+				// Each pair is matched on the days both traded, exchanges have different holidays
+				let (returns_a, returns_b) = paired_returns(series[a], series[b], false);
+				let value = (returns_a.len() >= 3).then(|| correlation(&returns_a, &returns_b)).flatten();
+				(values[a][b], values[b][a]) = (value, value);
+				(overlaps[a][b], overlaps[b][a]) = (returns_a.len(), returns_a.len());
+			}
+		}
+
+		let order = cluster_order(&values);
+		Self {
+			symbols: order.iter().map(|index| symbols[*index].clone()).collect(),
+			values: order.iter().map(|a| order.iter().map(|b| values[*a][*b]).collect()).collect(),
+			overlaps: order.iter().map(|a| order.iter().map(|b| overlaps[*a][*b]).collect()).collect(),
+		}
+	}
+}
+
+/// Leaf order of an average-linkage hierarchical clustering on 1 - correlation,
+/// so symbols moving together end up next to each other
+fn cluster_order(values: &[Vec<Option<f64>>]) -> Vec<usize> {
+	let distance = |a: usize, b: usize| 1. - values[a][b].unwrap_or(0.);
+	let mut clusters: Vec<Vec<usize>> = (0..values.len()).map(|index| vec![index]).collect();
+
+	while clusters.len() > 1 {
+		let mut closest = (0, 1, f64::INFINITY);
+		for a in 0..clusters.len() {
+			for b in a + 1..clusters.len() {
+				let total: f64 = clusters[a].iter().flat_map(|x| clusters[b].iter().map(move |y| distance(*x, *y))).sum();
+				let average = total / (clusters[a].len() * clusters[b].len()) as f64;
+				if average < closest.2 {
+					closest = (a, b, average);
+				}
+			}
+		}
+
+		let merged = clusters.remove(closest.1);
+		clusters[closest.0].extend(merged);
+	}
+
+	clusters.pop().unwrap_or_default()
+}
+
+/// From red for -1 over the background for 0 to blue for +1
+fn heat_color(value: f64, background: Color32) -> Color32 {
+	let target = if value >= 0. { Color32::from_rgb(40, 110, 220) } else { Color32::from_rgb(220, 60, 50) };
+	let t = value.abs().clamp(0., 1.) as f32;
+	let mix = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * t).round() as u8;
+	Color32::from_rgb(mix(background.r(), target.r()), mix(background.g(), target.g()), mix(background.b(), target.b()))
+}
+
+/// Correlation heatmap of the watchlist's daily returns
+pub struct CorrelationView {
+	/// Index into `WINDOWS`
+	window: usize,
+	fetch_handle: DailyClosesHandle,
+	/// Window and symbols `closes` were fetched for
+	fetched: Option<(usize, Vec<String>)>,
+	closes: HashMap<String, Vec<[f64; 2]>>,
+	matrix: Option<CorrelationMatrix>,
+	/// Pair shown in the overlay chart
+	selected: Option<(String, String)>,
+}
+
+impl CorrelationView {
+	pub fn new() -> Self {
+		Self {
+			window: DEFAULT_WINDOW,
+			fetch_handle: None,
+			fetched: None,
+			closes: HashMap::new(),
+			matrix: None,
+			selected: None,
+		}
+	}
+
+	/// Fetches the daily closes of `tickers` whenever they or the window changed
+	fn update(&mut self, ctx: &Context, tickers: &[String]) {
+		if self.fetch_handle.is_none() {
+			let mut tickers = tickers.to_vec();
+			tickers.sort();
+			tickers.dedup();
+			let wanted = (self.window, tickers);
+			if self.fetched.as_ref() == Some(&wanted) {
+				return;
+			}
+			self.fetched = Some(wanted);
+		}
+
+		if let Some((window, tickers)) = &self.fetched {
+			let start = OffsetDateTime::now_utc() - Duration::days(WINDOWS[*window].1);
+			if fetch_daily_closes(ctx, &mut self.fetch_handle, tickers, start, &mut self.closes) {
+				self.matrix = Some(CorrelationMatrix::new(tickers, &self.closes));
+			}
+		}
+	}
+
+	pub fn show(&mut self, ui: &mut Ui, tickers: &[String]) {
+		self.update(ui.ctx(), tickers);
+
+		ui.horizontal(|ui| {
+			ui.heading("Correlation");
+			ui.add_space(16.);
+			ui.label("Window:");
+			ComboBox::from_id_salt("correlation_window")
+				.selected_text(WINDOWS[self.window].0)
+				.show_ui(ui, |ui| {
+					for (index, (label, _)) in WINDOWS.iter().enumerate() {
+						ui.selectable_value(&mut self.window, index, *label);
+					}
+				});
+			if self.fetch_handle.is_some() {
+				ui.spinner();
+			}
+		});
+		ui.label(RichText::new("Daily returns of the watchlist, similar symbols are grouped together. Click a cell to compare the two.").weak());
+		ui.add_space(8.);
+
+		match &self.matrix {
+			Some(matrix) if matrix.symbols.len() >= 2 => {
+				if let Some(pair) = heatmap(ui, matrix) {
+					self.selected = Some(pair);
+				}
+			},
+			Some(_) => {
+				ui.label("Add at least two symbols to the watchlist to compare them.");
+			},
+			None => {},
+		}
+
+		self.show_overlay(ui.ctx());
+	}
+
+	/// Both symbols of the selected pair rebased to 100 on their first common day
+	fn show_overlay(&mut self, ctx: &Context) {
+		let Some((a, b)) = self.selected.clone() else {
+			return;
+		};
+
+		let empty = vec![];
+		let (closes_a, closes_b) = (self.closes.get(&a).unwrap_or(&empty), self.closes.get(&b).unwrap_or(&empty));
+		let day = |timestamp: f64| (timestamp as i64).div_euclid(86400);
+		let start = closes_a.first().zip(closes_b.first()).map(|(first_a, first_b)| day(first_a[0]).max(day(first_b[0])));
+		let rebased = |closes: &Vec<[f64; 2]>| -> Vec<[f64; 2]> {
+			let Some(start) = start else {
+				return vec![];
+			};
+			let mut points = closes.iter().skip_while(|close| day(close[0]) < start).peekable();
+			let Some(base) = points.peek().map(|close| close[1]).filter(|base| *base > 0.) else {
+				return vec![];
+			};
+			points.map(|close| [close[0], close[1] / base * 100.]).collect()
+		};
+		let (line_a, line_b) = (rebased(closes_a), rebased(closes_b));
+
+		let mut open = true;
+		Window::new(format!("{a} vs {b}"))
+			.id(Id::new("correlation_overlay"))
+			.open(&mut open)
+			.default_size([560., 320.])
+			.show(ctx, |ui| {
+				let date = |timestamp: f64| OffsetDateTime::from_unix_timestamp(timestamp as i64).map_or(String::new(), |time| format_date(time.date()));
+				Plot::new("correlation_overlay_plot")
+					.legend(Legend::default())
+					.x_axis_formatter(move |mark, _range| date(mark.value))
+					.label_formatter(move |name, point| format!("{name}\n{}: {:.2}", date(point.x), point.y))
+					.allow_scroll(false)
+					.show(ui, |plot_ui| {
+						plot_ui.line(Line::new(PlotPoints::from(line_a)).name(&a));
+						plot_ui.line(Line::new(PlotPoints::from(line_b)).name(&b));
+					});
+			});
+
+		if !open {
+			self.selected = None;
+		}
+	}
+}
+
+impl Default for CorrelationView {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Draws the matrix, returns the pair of the clicked cell
+fn heatmap(ui: &mut Ui, matrix: &CorrelationMatrix) -> Option<(String, String)> {
+	let count = matrix.symbols.len();
+	let font = FontId::proportional(12.);
+	let label_width = matrix.symbols.iter()
+		.map(|symbol| ui.painter().layout_no_wrap(symbol.clone(), font.clone(), Color32::WHITE).size().x)
+		.fold(0., f32::max) + 8.;
+	let label_height = 20.;
+	let cell = ((ui.available_width() - label_width) / count as f32).clamp(MIN_CELL_SIZE, MAX_CELL_SIZE);
+
+	let size = vec2(label_width + cell * count as f32, label_height + cell * count as f32);
+	let (rect, response) = ui.allocate_exact_size(size, Sense::click());
+	let painter = ui.painter_at(rect);
+	let visuals = ui.visuals();
+	let origin = rect.min + vec2(label_width, label_height);
+
+	for (index, symbol) in matrix.symbols.iter().enumerate() {
+		let offset = (index as f32 + 0.5) * cell;
+		painter.text(pos2(origin.x - 6., origin.y + offset), Align2::RIGHT_CENTER, symbol, font.clone(), visuals.text_color());
+		// Column labels are cut to the cell width
+		let column_label: String = symbol.chars().take((cell / 7.) as usize).collect();
+		painter.text(pos2(origin.x + offset, origin.y - 4.), Align2::CENTER_BOTTOM, column_label, font.clone(), visuals.text_color());
+	}
+
+	let hovered = response.hover_pos()
+		.map(|pos| (pos - origin) / cell)
+		.filter(|cell| cell.x >= 0. && cell.y >= 0. && cell.x < count as f32 && cell.y < count as f32)
+		.map(|cell| (cell.y as usize, cell.x as usize));
+
+	for (row, values) in matrix.values.iter().enumerate() {
+		for (column, value) in values.iter().enumerate() {
+			let cell_rect = Rect::from_min_size(origin + vec2(column as f32, row as f32) * cell, Vec2::splat(cell)).shrink(1.);
+			let fill = value.map_or(visuals.faint_bg_color, |value| heat_color(value, visuals.extreme_bg_color));
+			painter.rect_filled(cell_rect, 2., fill);
+			if hovered == Some((row, column)) {
+				painter.rect_stroke(cell_rect, 2., visuals.selection.stroke);
+			}
+
+			let text = value.map_or("-".to_string(), |value| format!("{value:.2}"));
+			let text_color = if value.is_some_and(|value| value.abs() > 0.6) { Color32::WHITE } else { visuals.text_color() };
+			painter.text(cell_rect.center(), Align2::CENTER_CENTER, text, FontId::proportional(11.), text_color);
+		}
+	}
+
+	let (row, column) = hovered?;
+	let (a, b) = (&matrix.symbols[row], &matrix.symbols[column]);
+	let response = response.on_hover_text_at_pointer(match matrix.values[row][column] {
+		Some(value) => format!("{a} / {b}: {value:.3} over {} days", matrix.overlaps[row][column]),
+		None => format!("{a} / {b}: not enough common trading days"),
+	});
+
+	(response.clicked() && row != column).then(|| (a.clone(), b.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::market_hours::DAY;
+
+	/// Daily closes from 100 with the given percent returns, starting on `first_day`
+	fn closes(first_day: i64, returns: &[f64]) -> Vec<[f64; 2]> {
+		let mut close = 100.;
+		let mut closes = vec![[(first_day * DAY) as f64, close]];
+		for (day, change) in (first_day + 1..).zip(returns) {
+			close *= 1. + change / 100.;
+			closes.push([(day * DAY) as f64, close]);
+		}
+		closes
+	}
+
+	fn position(matrix: &CorrelationMatrix, symbol: &str) -> usize {
+		matrix.symbols.iter().position(|other| other == symbol).unwrap()
+	}
+
+	#[test]
+	fn correlated_pairs_end_up_adjacent() {
+		let swings = [1., -1., 1., -1., 1., -1., 1., -1.];
+		let trend = [1., 1., -1., -1., 1., 1., -1., -1.];
+		let double = |returns: &[f64]| returns.iter().map(|change| change * 2.).collect::<Vec<_>>();
+		let closes = HashMap::from([
+			("A".to_string(), closes(0, &swings)),
+			("X".to_string(), closes(0, &trend)),
+			("B".to_string(), closes(0, &double(&swings))),
+			("Y".to_string(), closes(0, &double(&trend))),
+		]);
+		let symbols = ["A", "X", "B", "Y"].map(String::from);
+
+		let matrix = CorrelationMatrix::new(&symbols, &closes);
+		assert_eq!(position(&matrix, "A").abs_diff(position(&matrix, "B")), 1);
+		assert_eq!(position(&matrix, "X").abs_diff(position(&matrix, "Y")), 1);
+		let (a, b) = (position(&matrix, "A"), position(&matrix, "B"));
+		assert!((matrix.values[a][b].unwrap() - 1.).abs() < 1e-9);
+	}
+
+	#[test]
+	fn short_overlaps_have_no_correlation() {
+		let closes = HashMap::from([
+			("A".to_string(), closes(0, &[1., -2., 3., -1., 2.])),
+			// Only days 4 and 5 are in common, a single return
+			("B".to_string(), closes(4, &[1., -2., 3.])),
+		]);
+		let symbols = ["A", "B"].map(String::from);
+
+		let matrix = CorrelationMatrix::new(&symbols, &closes);
+		assert_eq!(matrix.values[0][1], None);
+		assert_eq!(matrix.overlaps[0][1], 1);
+		assert_eq!(matrix.values[0][0], Some(1.));
+	}
+}
@@ -0,0 +1,506 @@
+use std::{fmt, fs};
+
+use eframe::egui::*;
+use time::{Date, Month};
+
+use crate::ledger::{Transaction, TransactionKind};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DateFormat {
+	/// 2024-03-31, anything after the date (e.g. a time) is ignored
+	Iso,
+	/// 03/31/2024
+	MonthDayYear,
+	/// 31/03/2024
+	DayMonthYear,
+	/// 31.03.2024
+	DayMonthYearDots,
+}
+
+impl DateFormat {
+	pub const ALL: [DateFormat; 4] = [DateFormat::Iso, DateFormat::MonthDayYear, DateFormat::DayMonthYear, DateFormat::DayMonthYearDots];
+
+	pub fn label(&self) -> &'static str {
+		match self {
+			DateFormat::Iso => "YYYY-MM-DD",
+			DateFormat::MonthDayYear => "MM/DD/YYYY",
+			DateFormat::DayMonthYear => "DD/MM/YYYY",
+			DateFormat::DayMonthYearDots => "DD.MM.YYYY",
+		}
+	}
+
+	pub fn parse(&self, text: &str) -> Option<Date> {
+		let text = text.trim();
+		let (separator, order) = match self {
+			DateFormat::Iso => ('-', [0, 1, 2]),
+			DateFormat::MonthDayYear => ('/', [2, 0, 1]),
+			DateFormat::DayMonthYear => ('/', [2, 1, 0]),
+			DateFormat::DayMonthYearDots => ('.', [2, 1, 0]),
+		};
+
+		// Drop a trailing time such as "2024-03-31, 09:30:00" or "03/31/2024 09:30"
+		let date = text.split([' ', ',', 'T']).next()?;
+		let parts: Vec<&str> = date.split(separator).collect();
+		if parts.len() != 3 {
+			return None;
+		}
+
+		let year: i32 = parts[order[0]].parse().ok()?;
+		let month: u8 = parts[order[1]].parse().ok()?;
+		let day: u8 = parts[order[2]].parse().ok()?;
+
+		Date::from_calendar_date(year, Month::try_from(month).ok()?, day).ok()
+	}
+}
+
+/// Which CSV header holds each transaction field. Optional columns may be
+/// left empty: without a type column buys and sells are told apart by the
+/// sign of the quantity, without an amount column dividends and fees are
+/// read from the price column.
+#[derive(Clone)]
+pub struct ColumnMapping {
+	pub date: String,
+	pub kind: String,
+	pub symbol: String,
+	pub quantity: String,
+	pub price: String,
+	/// Cash amount of dividend and fee rows
+	pub amount: String,
+	pub fees: String,
+	pub currency: String,
+	pub date_format: DateFormat,
+	pub delimiter: char,
+	/// Currency used when there is no currency column or it is empty
+	pub default_currency: String,
+}
+
+pub struct Preset {
+	pub name: &'static str,
+	pub mapping: fn() -> ColumnMapping,
+}
+
+/// Builds a mapping from the date, type, symbol, quantity, price, amount, fees and currency headers
+fn mapping(columns: [&str; 8], date_format: DateFormat) -> ColumnMapping {
+	let [date, kind, symbol, quantity, price, amount, fees, currency] = columns;
+	ColumnMapping {
+		date: date.to_string(),
+		kind: kind.to_string(),
+		symbol: symbol.to_string(),
+		quantity: quantity.to_string(),
+		price: price.to_string(),
+		amount: amount.to_string(),
+		fees: fees.to_string(),
+		currency: currency.to_string(),
+		date_format,
+		delimiter: ',',
+		default_currency: "USD".to_string(),
+	}
+}
+
+/// Column layouts of common broker exports
+pub const PRESETS: [Preset; 5] = [
+	Preset {
+		name: "Generic",
+		mapping: || mapping(["Date", "Type", "Symbol", "Quantity", "Price", "Amount", "Fees", "Currency"], DateFormat::Iso),
+	},
+	Preset {
+		name: "Interactive Brokers (trades)",
+		mapping: || mapping(["Date/Time", "", "Symbol", "Quantity", "T. Price", "", "Comm/Fee", "Currency"], DateFormat::Iso),
+	},
+	Preset {
+		name: "Trading 212",
+		mapping: || mapping(["Time", "Action", "Ticker", "No. of shares", "Price / share", "Total", "", "Currency (Price / share)"], DateFormat::Iso),
+	},
+	Preset {
+		name: "Charles Schwab",
+		mapping: || mapping(["Date", "Action", "Symbol", "Quantity", "Price", "Amount", "Fees & Comm", ""], DateFormat::MonthDayYear),
+	},
+	Preset {
+		name: "Fidelity",
+		mapping: || mapping(["Run Date", "Action", "Symbol", "Quantity", "Price ($)", "Amount ($)", "Commission ($)", ""], DateFormat::MonthDayYear),
+	},
+];
+
+pub struct ImportError {
+	pub line: u64,
+	pub message: String,
+}
+
+impl fmt::Display for ImportError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "line {}: {}", self.line, self.message)
+	}
+}
+
+/// Transactions read from a CSV export and the rows that couldn't be used
+pub struct ImportResult {
+	pub transactions: Vec<Transaction>,
+	pub errors: Vec<ImportError>,
+}
+
+/// Parses broker CSV `contents` with `mapping`. Rows that fail are reported
+/// and skipped, a missing required header fails the whole import.
+pub fn import_csv(contents: &str, mapping: &ColumnMapping) -> Result<ImportResult, String> {
+	let mut reader = csv::ReaderBuilder::new()
+		.delimiter(mapping.delimiter as u8)
+		.flexible(true)
+		.trim(csv::Trim::All)
+		.from_reader(contents.as_bytes());
+
+	let headers = reader.headers().map_err(|err| format!("Can't read the header row: {err}"))?.clone();
+	let column = |name: &str, required: bool| -> Result<Option<usize>, String> {
+		if name.is_empty() {
+			return if required { Err("A required column is not mapped".to_string()) } else { Ok(None) };
+		}
+		match headers.iter().position(|header| header.eq_ignore_ascii_case(name)) {
+			Some(index) => Ok(Some(index)),
+			None if required => Err(format!("Column '{name}' not found, the file has: {}", headers.iter().collect::<Vec<_>>().join(", "))),
+			None => Ok(None),
+		}
+	};
+
+	let date_column = column(&mapping.date, true)?.unwrap_or_default();
+	let symbol_column = column(&mapping.symbol, true)?.unwrap_or_default();
+	let quantity_column = column(&mapping.quantity, true)?.unwrap_or_default();
+	let price_column = column(&mapping.price, true)?.unwrap_or_default();
+	let kind_column = column(&mapping.kind, false)?;
+	let amount_column = column(&mapping.amount, false)?;
+	let fees_column = column(&mapping.fees, false)?;
+	let currency_column = column(&mapping.currency, false)?;
+
+	let mut result = ImportResult {
+		transactions: vec![],
+		errors: vec![],
+	};
+
+	for record in reader.records() {
+		let record = match record {
+			Ok(record) => record,
+			Err(err) => {
+				let line = err.position().map_or(0, |position| position.line());
+				result.errors.push(ImportError { line, message: err.to_string() });
+				continue;
+			},
+		};
+		let line = record.position().map_or(0, |position| position.line());
+		let field = |index: usize| record.get(index).unwrap_or("");
+
+		// Summary and blank rows that many exports append
+		if field(symbol_column).is_empty() && field(date_column).is_empty() {
+			continue;
+		}
+
+		let mut error = |message: String| result.errors.push(ImportError { line, message });
+
+		let Some(date) = mapping.date_format.parse(field(date_column)) else {
+			error(format!("invalid date '{}', expected {}", field(date_column), mapping.date_format.label()));
+			continue;
+		};
+		// Optional columns default to 0 when blank, required ones are checked per row type below
+		let quantity = parse_number(field(quantity_column));
+		let fees = fees_column.and_then(|index| parse_number(field(index))).unwrap_or(0.).abs();
+
+		let kind = match kind_column {
+			Some(index) => match parse_kind(field(index)) {
+				Some(kind) => kind,
+				None => {
+					error(format!("unknown transaction type '{}'", field(index)));
+					continue;
+				},
+			},
+			None => match quantity {
+				Some(quantity) if quantity < 0. => TransactionKind::Sell,
+				_ => TransactionKind::Buy,
+			},
+		};
+
+		let (quantity, price) = match kind {
+			TransactionKind::Buy | TransactionKind::Sell => {
+				let Some(quantity) = quantity.filter(|quantity| *quantity != 0.) else {
+					error(format!("invalid quantity '{}'", field(quantity_column)));
+					continue;
+				};
+				let Some(price) = parse_number(field(price_column)) else {
+					error(format!("invalid price '{}'", field(price_column)));
+					continue;
+				};
+				(quantity, price)
+			},
+			TransactionKind::Dividend | TransactionKind::Fee => {
+				// The ledger keeps the cash of dividends and fees in the price
+				let (column, text) = match amount_column {
+					Some(index) if !field(index).is_empty() => (&mapping.amount, field(index)),
+					_ => (&mapping.price, field(price_column)),
+				};
+				let Some(amount) = parse_number(text) else {
+					error(format!("invalid amount '{text}' in column '{column}'"));
+					continue;
+				};
+				(quantity.unwrap_or(0.), amount)
+			},
+			TransactionKind::Split => {
+				// Exports list the shares received, the ledger needs the split ratio
+				error("stock splits have to be entered by hand as new shares per old share".to_string());
+				continue;
+			},
+		};
+
+		let currency = currency_column.map(field).filter(|currency| !currency.is_empty()).unwrap_or(&mapping.default_currency);
+
+		let mut transaction = Transaction::new(kind, &field(symbol_column).to_uppercase());
+		transaction.date = date;
+		transaction.quantity = quantity.abs();
+		transaction.price = price.abs();
+		transaction.fees = fees;
+		transaction.currency = currency.to_uppercase();
+		result.transactions.push(transaction);
+	}
+
+	Ok(result)
+}
+
+/// Reads numbers as brokers write them: "$1,234.50", "(12.00)", "-3". Blank fields are `None`.
+fn parse_number(text: &str) -> Option<f64> {
+	let text = text.trim();
+	if text.is_empty() {
+		return None;
+	}
+
+	let negative = text.starts_with('(') && text.ends_with(')');
+	let cleaned: String = text.chars().filter(|c| !matches!(c, '$' | '€' | '£' | ',' | '(' | ')' | ' ')).collect();
+	let value: f64 = cleaned.parse().ok()?;
+
+	Some(if negative { -value } else { value })
+}
+
+/// Matches the wording brokers use, e.g. "Market buy", "Reinvest Dividend", "Stock Split"
+fn parse_kind(text: &str) -> Option<TransactionKind> {
+	let text = text.to_lowercase();
+	if text.contains("split") {
+		Some(TransactionKind::Split)
+	} else if text.contains("div") {
+		Some(TransactionKind::Dividend)
+	} else if text.contains("sell") || text.contains("sold") {
+		Some(TransactionKind::Sell)
+	} else if text.contains("buy") || text.contains("bought") {
+		Some(TransactionKind::Buy)
+	} else if text.contains("fee") || text.contains("commission") {
+		Some(TransactionKind::Fee)
+	} else {
+		None
+	}
+}
+
+/// File picker, column mapping and results of a CSV import
+pub struct ImportView {
+	path: String,
+	preset: usize,
+	mapping: ColumnMapping,
+	status: Option<Result<String, String>>,
+	errors: Vec<String>,
+}
+
+impl ImportView {
+	pub fn new() -> Self {
+		Self {
+			path: String::new(),
+			preset: 0,
+			mapping: (PRESETS[0].mapping)(),
+			status: None,
+			errors: vec![],
+		}
+	}
+
+	/// Returns the transactions to add once the user ran an import
+	pub fn show(&mut self, ui: &mut Ui) -> Option<Vec<Transaction>> {
+		let mut imported = None;
+
+		Grid::new("csv_import").num_columns(2).spacing([12., 6.]).show(ui, |ui| {
+			ui.label("File:");
+			ui.add(TextEdit::singleline(&mut self.path).hint_text("/path/to/export.csv").desired_width(400.));
+			ui.end_row();
+
+			ui.label("Preset:");
+			ComboBox::from_id_salt("csv_preset")
+				.selected_text(PRESETS[self.preset].name)
+				.show_ui(ui, |ui| {
+					for (index, preset) in PRESETS.iter().enumerate() {
+						if ui.selectable_label(index == self.preset, preset.name).clicked() {
+							self.preset = index;
+							self.mapping = (preset.mapping)();
+						}
+					}
+				});
+			ui.end_row();
+
+			let mapping = &mut self.mapping;
+			for (label, column) in [
+				("Date column:", &mut mapping.date),
+				("Type column:", &mut mapping.kind),
+				("Symbol column:", &mut mapping.symbol),
+				("Quantity column:", &mut mapping.quantity),
+				("Price column:", &mut mapping.price),
+				("Amount column:", &mut mapping.amount),
+				("Fees column:", &mut mapping.fees),
+				("Currency column:", &mut mapping.currency),
+			] {
+				ui.label(label);
+				ui.add(TextEdit::singleline(column).hint_text("not in file").desired_width(200.));
+				ui.end_row();
+			}
+
+			ui.label("Date format:");
+			ComboBox::from_id_salt("csv_date_format")
+				.selected_text(mapping.date_format.label())
+				.show_ui(ui, |ui| {
+					for format in DateFormat::ALL {
+						ui.selectable_value(&mut mapping.date_format, format, format.label());
+					}
+				});
+			ui.end_row();
+
+			ui.label("Delimiter:");
+			ui.horizontal(|ui| {
+				ui.selectable_value(&mut mapping.delimiter, ',', "Comma");
+				ui.selectable_value(&mut mapping.delimiter, ';', "Semicolon");
+				ui.selectable_value(&mut mapping.delimiter, '\t', "Tab");
+			});
+			ui.end_row();
+
+			ui.label("Default currency:");
+			ui.add(TextEdit::singleline(&mut mapping.default_currency).desired_width(50.));
+			ui.end_row();
+		});
+
+		ui.add_space(8.);
+		if ui.add_enabled(!self.path.trim().is_empty(), Button::new("Import")).clicked() {
+			self.errors.clear();
+			let result = fs::read_to_string(self.path.trim())
+				.map_err(|err| format!("Can't read '{}': {err}", self.path.trim()))
+				.and_then(|contents| import_csv(&contents, &self.mapping));
+
+			self.status = Some(match result {
+				Ok(result) => {
+					self.errors = result.errors.iter().map(ImportError::to_string).collect();
+					let message = format!("Imported {} transactions, skipped {} rows", result.transactions.len(), result.errors.len());
+					imported = Some(result.transactions);
+					Ok(message)
+				},
+				Err(err) => Err(err),
+			});
+		}
+
+		match &self.status {
+			Some(Ok(message)) => {
+				ui.label(RichText::new(message).color(Color32::GREEN));
+			},
+			Some(Err(message)) => {
+				ui.label(RichText::new(message).color(Color32::RED));
+			},
+			None => {},
+		}
+		for error in &self.errors {
+			ui.label(RichText::new(error).color(Color32::LIGHT_RED));
+		}
+
+		imported
+	}
+}
+
+impl Default for ImportView {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn generic() -> ColumnMapping {
+		(PRESETS[0].mapping)()
+	}
+
+	#[test]
+	fn parse_number_formats() {
+		assert_eq!(parse_number("$1,234.50"), Some(1234.5));
+		assert_eq!(parse_number("(12.00)"), Some(-12.));
+		assert_eq!(parse_number(" -3 "), Some(-3.));
+		assert_eq!(parse_number(""), None);
+		assert_eq!(parse_number("  "), None);
+		assert_eq!(parse_number("n/a"), None);
+	}
+
+	#[test]
+	fn blank_quantity_or_price_is_an_error() {
+		let csv = "Date,Type,Symbol,Quantity,Price,Amount,Fees,Currency\n\
+			2024-01-02,Buy,AAPL,,185.5,,,USD\n\
+			2024-01-03,Buy,AAPL,10,,,,USD\n\
+			2024-01-04,Buy,AAPL,10,185.5,,,USD\n";
+		let result = import_csv(csv, &generic()).unwrap();
+
+		assert_eq!(result.transactions.len(), 1);
+		assert_eq!(result.transactions[0].fees, 0.);
+		assert_eq!(result.errors.len(), 2);
+		assert_eq!(result.errors[0].line, 2);
+		assert!(result.errors[0].message.contains("quantity"));
+		assert!(result.errors[1].message.contains("price"));
+	}
+
+	#[test]
+	fn parenthesised_negatives_without_type_column() {
+		let mut mapping = generic();
+		mapping.kind.clear();
+		let csv = "Date,Symbol,Quantity,Price,Fees\n\
+			2024-01-02,MSFT,(5),400.00,(1.00)\n\
+			2024-01-03,MSFT,5,\"$1,010.00\",\n";
+		let result = import_csv(csv, &mapping).unwrap();
+
+		assert!(result.errors.is_empty());
+		let [sell, buy] = &result.transactions[..] else {
+			panic!("expected two transactions");
+		};
+		assert_eq!(sell.kind, TransactionKind::Sell);
+		assert_eq!(sell.quantity, 5.);
+		assert_eq!(sell.fees, 1.);
+		assert_eq!(buy.kind, TransactionKind::Buy);
+		assert_eq!(buy.price, 1010.);
+	}
+
+	#[test]
+	fn dividends_read_the_amount_column() {
+		let csv = "Date,Action,Symbol,Quantity,Price,Fees & Comm,Amount\n\
+			03/15/2024,Qualified Dividend,KO,,,,$46.00\n\
+			03/18/2024,Buy,KO,10,$60.00,,-$600.00\n";
+		let result = import_csv(csv, &(PRESETS[3].mapping)()).unwrap();
+
+		assert!(result.errors.is_empty());
+		let dividend = &result.transactions[0];
+		assert_eq!(dividend.kind, TransactionKind::Dividend);
+		assert_eq!(dividend.price, 46.);
+		assert_eq!(result.transactions[1].price, 60.);
+	}
+
+	#[test]
+	fn dividends_fall_back_to_the_price_column() {
+		let mut mapping = generic();
+		mapping.amount.clear();
+		let csv = "Date,Type,Symbol,Quantity,Price\n2024-03-15,Dividend,KO,,46\n2024-03-16,Dividend,KO,,\n";
+		let result = import_csv(csv, &mapping).unwrap();
+
+		assert_eq!(result.transactions.len(), 1);
+		assert_eq!(result.transactions[0].price, 46.);
+		assert_eq!(result.errors.len(), 1);
+		assert!(result.errors[0].message.contains("amount"));
+	}
+
+	#[test]
+	fn split_rows_need_manual_entry() {
+		let csv = "Date,Type,Symbol,Quantity,Price,Amount,Fees,Currency\n2024-06-10,Stock Split,NVDA,270,,,,USD\n";
+		let result = import_csv(csv, &generic()).unwrap();
+
+		assert!(result.transactions.is_empty());
+		assert_eq!(result.errors.len(), 1);
+		assert!(result.errors[0].message.contains("split"));
+	}
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{quote_book::{QuoteBook, StockInfo}, storage};
+
+const CURRENCY_FILE: &str = "currency.json";
+
+pub const COMMON_CURRENCIES: [&str; 12] = ["USD", "EUR", "GBP", "CHF", "JPY", "CAD", "AUD", "SEK", "NOK", "DKK", "HKD", "CNY"];
+
+/// Yahoo quotes some exchanges in minor units, e.g. LSE listings in pence ("GBp")
+pub fn major_unit(currency: &str) -> (&str, f64) {
+	match currency {
+		"GBp" | "GBX" => ("GBP", 0.01),
+		"ZAc" | "ZAC" => ("ZAR", 0.01),
+		"ILA" => ("ILS", 0.01),
+		_ => (currency, 1.),
+	}
+}
+
+/// Data provider symbol of the rate from `from` to `to`, e.g. EURUSD=X
+pub fn fx_symbol(from: &str, to: &str) -> String {
+	format!("{from}{to}=X")
+}
+
+/// Base currency setting and the FX rates needed to convert into it
+#[derive(Serialize, Deserialize)]
+pub struct Currencies {
+	pub base: String,
+	/// Whether the watchlist and portfolio show values converted into `base`
+	pub convert: bool,
+	/// Last known rate from each currency into `base`, kept so values can be
+	/// converted right after start-up
+	rates: HashMap<String, f64>,
+	#[serde(skip)]
+	fx_symbols: Vec<String>,
+}
+
+impl Currencies {
+	pub fn new() -> Self {
+		Self {
+			base: "USD".to_string(),
+			convert: false,
+			rates: HashMap::new(),
+			fx_symbols: vec![],
+		}
+	}
+
+	pub fn load() -> Self {
+		storage::load(CURRENCY_FILE).unwrap_or_default()
+	}
+
+	pub fn save(&self) {
+		storage::save(CURRENCY_FILE, self);
+	}
+
+	/// FX symbols to keep refreshed so the quotes of `tickers` and amounts in `currencies` can be converted
+	pub fn fx_symbols<'a>(&mut self, quote_book: &QuoteBook, tickers: impl IntoIterator<Item = &'a String>, currencies: impl IntoIterator<Item = &'a str>) -> &[String] {
+		self.fx_symbols.clear();
+		let mut add = |currency: &str| {
+			let (currency, _) = major_unit(currency);
+			let symbol = fx_symbol(currency, &self.base);
+			if !currency.is_empty() && currency != self.base && !self.fx_symbols.contains(&symbol) {
+				self.fx_symbols.push(symbol);
+			}
+		};
+		tickers.into_iter().filter_map(|ticker| quote_book.get(ticker)).for_each(|stock| add(stock.currency()));
+		currencies.into_iter().for_each(add);
+
+		&self.fx_symbols
+	}
+
+	/// Takes the latest rates from the quote book, saving them if they changed
+	pub fn update(&mut self, quote_book: &QuoteBook) {
+		let mut changed = false;
+		for symbol in &self.fx_symbols {
+			let Some(stock) = quote_book.get(symbol).filter(|stock| stock.metadata.is_some() && stock.price > 0.) else {
+				continue;
+			};
+
+			let Some(currency) = symbol.strip_suffix("=X").and_then(|pair| pair.strip_suffix(self.base.as_str())) else {
+				continue;
+			};
+			if self.rates.insert(currency.to_string(), stock.price) != Some(stock.price) {
+				changed = true;
+			}
+		}
+
+		if changed {
+			self.save();
+		}
+	}
+
+	/// Rate from `currency` into the base currency, if known
+	pub fn rate(&self, currency: &str) -> Option<f64> {
+		let (major, factor) = major_unit(currency);
+		if major == self.base {
+			Some(factor)
+		} else {
+			self.rates.get(major).map(|rate| rate * factor)
+		}
+	}
+
+	pub fn convert(&self, amount: f64, currency: &str) -> Option<f64> {
+		self.rate(currency).map(|rate| amount * rate)
+	}
+
+	/// Rate of `stock`'s currency when converted values are shown
+	pub fn display_rate(&self, stock: &StockInfo) -> Option<f64> {
+		if self.convert { self.rate(stock.currency()) } else { None }
+	}
+
+	/// Settings menu entries, returns whether anything changed
+	pub fn ui(&mut self, ui: &mut Ui) -> bool {
+		let old_base = self.base.clone();
+		let old_convert = self.convert;
+
+		ui.horizontal(|ui| {
+			ui.label("Base currency:");
+			ComboBox::from_id_salt("base_currency")
+				.selected_text(&self.base)
+				.show_ui(ui, |ui| {
+					for currency in COMMON_CURRENCIES {
+						ui.selectable_value(&mut self.base, currency.to_string(), currency);
+					}
+				});
+		});
+		ui.checkbox(&mut self.convert, "Show values in base currency");
+
+		if self.base != old_base {
+			// Rates into the old base are useless now
+			self.rates.clear();
+		}
+		let changed = self.base != old_base || self.convert != old_convert;
+		if changed {
+			self.save();
+		}
+
+		changed
+	}
+}
+
+impl Default for Currencies {
+	fn default() -> Self {
+		Self::new()
+	}
+}
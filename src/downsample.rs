@@ -0,0 +1,222 @@
+use std::ops::Range;
+
+/// Downsampled copy of a price and volume series, rebuilt only when the
+/// underlying data, the visible x range or the plot width changes.
+pub struct SeriesCache {
+	key: Option<CacheKey>,
+	bounds_version: Option<u64>,
+	pub price_points: Vec<[f64; 2]>,
+	pub volume_points: Vec<[f64; 2]>,
+	pub volume_width: f64,
+	pub min_price: f64,
+	pub max_price: f64,
+	pub min_volume: f64,
+	pub max_volume: f64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct CacheKey {
+	data_version: u64,
+	view: Option<(f64, f64)>,
+	pixel_width: u32,
+}
+
+impl SeriesCache {
+	pub fn new() -> Self {
+		Self {
+			key: None,
+			bounds_version: None,
+			price_points: vec![],
+			volume_points: vec![],
+			volume_width: 0.,
+			min_price: 0.,
+			max_price: 0.,
+			min_volume: 0.,
+			max_volume: 0.,
+		}
+	}
+
+	/// Rebuilds the cached points if needed. `view` is the visible x range,
+	/// or `None` to downsample the whole series (e.g. while auto-bounding).
+	pub fn update(&mut self, price_data: &[[f64; 2]], volume_data: &[[f64; 2]], data_version: u64, view: Option<(f64, f64)>, pixel_width: f32) {
+		if self.bounds_version != Some(data_version) {
+			(self.min_price, self.max_price) = y_bounds(price_data);
+			(self.min_volume, self.max_volume) = y_bounds(volume_data);
+			self.bounds_version = Some(data_version);
+		}
+
+		let key = CacheKey {
+			data_version,
+			view,
+			pixel_width: pixel_width.max(1.) as u32,
+		};
+		if self.key == Some(key) {
+			return;
+		}
+		self.key = Some(key);
+
+		let threshold = key.pixel_width as usize;
+
+		let price_range = match view {
+			Some((min_x, max_x)) => visible_range(price_data, min_x, max_x),
+			None => 0..price_data.len(),
+		};
+		self.price_points = lttb(&price_data[price_range], threshold);
+
+		let volume_range = match view {
+			Some((min_x, max_x)) => visible_range(volume_data, min_x, max_x),
+			None => 0..volume_data.len(),
+		};
+		(self.volume_points, self.volume_width) = max_buckets(&volume_data[volume_range], threshold);
+	}
+}
+
+impl Default for SeriesCache {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Index range of the points of a series sorted by x that lie within
+/// `[min_x, max_x]`, padded with one point on each side so lines reach the
+/// plot edges.
+pub fn visible_range(data: &[[f64; 2]], min_x: f64, max_x: f64) -> Range<usize> {
+	let start = data.partition_point(|p| p[0] < min_x).saturating_sub(1);
+	let end = (data.partition_point(|p| p[0] <= max_x) + 1).min(data.len());
+
+	start..end.max(start)
+}
+
+/// Largest-Triangle-Three-Buckets downsampling. Keeps the first and last
+/// points and picks, for every bucket in between, the point forming the
+/// largest triangle with its neighbours, which preserves the visual shape.
+pub fn lttb(data: &[[f64; 2]], threshold: usize) -> Vec<[f64; 2]> {
+	if threshold < 3 || data.len() <= threshold {
+		return data.to_vec();
+	}
+
+	let mut sampled = Vec::with_capacity(threshold);
+	let bucket_size = (data.len() - 2) as f64 / (threshold - 2) as f64;
+
+	let mut a = 0;
+	sampled.push(data[a]);
+
+	for i in 0..threshold - 2 {
+		// Average of the next bucket, used as the third triangle vertex
+		let next_start = ((i + 1) as f64 * bucket_size) as usize + 1;
+		let next_end = (((i + 2) as f64 * bucket_size) as usize + 1).min(data.len());
+		let next = &data[next_start..next_end];
+		let avg_x = next.iter().map(|p| p[0]).sum::<f64>() / next.len() as f64;
+		let avg_y = next.iter().map(|p| p[1]).sum::<f64>() / next.len() as f64;
+
+		let start = (i as f64 * bucket_size) as usize + 1;
+		let end = next_start;
+
+		let [ax, ay] = data[a];
+		let mut max_area = -1.;
+		let mut max_index = start;
+		for (j, p) in data[start..end].iter().enumerate() {
+			let area = ((ax - avg_x) * (p[1] - ay) - (ax - p[0]) * (avg_y - ay)).abs();
+			if area > max_area {
+				max_area = area;
+				max_index = start + j;
+			}
+		}
+
+		sampled.push(data[max_index]);
+		a = max_index;
+	}
+
+	sampled.push(data[data.len() - 1]);
+	sampled
+}
+
+/// Splits the x span of `data` into `buckets` equal buckets and keeps the
+/// largest value of each, placed at the bucket centre. Used for volume bars,
+/// where a spike must stay visible however far the chart is zoomed out.
+/// Returns the points and the bar width to draw them with.
+pub fn max_buckets(data: &[[f64; 2]], buckets: usize) -> (Vec<[f64; 2]>, f64) {
+	let native_width = match data {
+		[first, second, ..] => second[0] - first[0],
+		_ => 0.,
+	};
+
+	if buckets == 0 || data.len() <= buckets {
+		return (data.to_vec(), native_width);
+	}
+
+	let min_x = data[0][0];
+	let span = data[data.len() - 1][0] - min_x;
+	let width = span / buckets as f64;
+	if width <= 0. {
+		return (data.to_vec(), native_width);
+	}
+
+	let mut sampled: Vec<[f64; 2]> = Vec::with_capacity(buckets);
+	let mut current_bucket = None;
+	for p in data {
+		let bucket = (((p[0] - min_x) / width) as usize).min(buckets - 1);
+		if current_bucket == Some(bucket) {
+			let last = sampled.len() - 1;
+			if p[1] > sampled[last][1] {
+				sampled[last][1] = p[1];
+			}
+		} else {
+			sampled.push([min_x + (bucket as f64 + 0.5) * width, p[1]]);
+			current_bucket = Some(bucket);
+		}
+	}
+
+	(sampled, width)
+}
+
+fn y_bounds(data: &[[f64; 2]]) -> (f64, f64) {
+	if data.is_empty() {
+		return (0., 0.);
+	}
+
+	data.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), p| (min.min(p[1]), max.max(p[1])))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn series(count: usize) -> Vec<[f64; 2]> {
+		(0..count).map(|i| [i as f64, ((i * 37) % 101) as f64]).collect()
+	}
+
+	#[test]
+	fn lttb_keeps_the_first_and_last_points() {
+		let data = series(1000);
+		let sampled = lttb(&data, 50);
+		assert_eq!(sampled.len(), 50);
+		assert_eq!(sampled.first(), data.first());
+		assert_eq!(sampled.last(), data.last());
+		assert!(sampled.windows(2).all(|pair| pair[0][0] < pair[1][0]));
+	}
+
+	#[test]
+	fn volume_buckets_keep_their_maximum() {
+		// x from 0 to 11 in three buckets of 11/3: 0-3, 4-7 and 8-11
+		let volumes = [1., 9., 2., 3., 4., 5., 8., 1., 7., 1., 1., 2.];
+		let data: Vec<[f64; 2]> = volumes.iter().enumerate().map(|(i, volume)| [i as f64, *volume]).collect();
+
+		let (sampled, width) = max_buckets(&data, 3);
+		assert_eq!(sampled.iter().map(|point| point[1]).collect::<Vec<_>>(), [9., 8., 7.]);
+		assert!((width - 11. / 3.).abs() < 1e-9);
+	}
+
+	#[test]
+	fn short_series_pass_through() {
+		let prices = series(100);
+		let volumes = series(100);
+		let mut cache = SeriesCache::new();
+		cache.update(&prices, &volumes, 1, None, 200.);
+
+		assert_eq!(cache.price_points, prices);
+		assert_eq!(cache.volume_points, volumes);
+		assert_eq!(cache.volume_width, 1.);
+		assert_eq!((cache.min_price, cache.max_price), (0., 100.));
+	}
+}
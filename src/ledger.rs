@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+use time::{macros::format_description, Date, OffsetDateTime};
+
+use crate::storage;
+
+const LEDGER_FILE: &str = "ledger.json";
+/// Quantities below this are treated as a closed position
+pub const QUANTITY_EPSILON: f64 = 1e-9;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TransactionKind {
+	Buy,
+	Sell,
+	Dividend,
+	Split,
+	Fee,
+}
+
+impl TransactionKind {
+	pub const ALL: [TransactionKind; 5] = [
+		TransactionKind::Buy,
+		TransactionKind::Sell,
+		TransactionKind::Dividend,
+		TransactionKind::Split,
+		TransactionKind::Fee,
+	];
+
+	pub fn label(&self) -> &'static str {
+		match self {
+			TransactionKind::Buy => "Buy",
+			TransactionKind::Sell => "Sell",
+			TransactionKind::Dividend => "Dividend",
+			TransactionKind::Split => "Split",
+			TransactionKind::Fee => "Fee",
+		}
+	}
+}
+
+/// One ledger entry. For buys and sells `quantity` shares are traded at
+/// `price` each; for dividends and fees `price` is the total cash amount;
+/// for splits `quantity` is the number of new shares per old share.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Transaction {
+	/// Unique within the ledger, 0 until `Ledger::add` assigns one
+	pub id: u64,
+	pub date: Date,
+	pub kind: TransactionKind,
+	pub symbol: String,
+	pub quantity: f64,
+	pub price: f64,
+	pub fees: f64,
+	pub currency: String,
+	/// Id of the buy whose lot a sell closes first with specific-lot matching
+	pub lot_id: Option<u64>,
+}
+
+impl Transaction {
+	pub fn new(kind: TransactionKind, symbol: &str) -> Self {
+		Self {
+			id: 0,
+			date: OffsetDateTime::now_utc().date(),
+			kind,
+			symbol: symbol.to_string(),
+			quantity: 0.,
+			price: 0.,
+			fees: 0.,
+			currency: String::new(),
+			lot_id: None,
+		}
+	}
+}
+
+/// Position and realized results of one symbol, derived from its transactions
+/// using the average cost of the shares held
+#[derive(Clone)]
+pub struct Holding {
+	pub symbol: String,
+	pub currency: String,
+	pub quantity: f64,
+	pub average_cost: f64,
+	pub realized_pnl: f64,
+	pub dividends: f64,
+	pub fees: f64,
+}
+
+impl Holding {
+	fn new(symbol: &str, currency: &str) -> Self {
+		Self {
+			symbol: symbol.to_string(),
+			currency: currency.to_string(),
+			quantity: 0.,
+			average_cost: 0.,
+			realized_pnl: 0.,
+			dividends: 0.,
+			fees: 0.,
+		}
+	}
+
+	pub fn is_open(&self) -> bool {
+		self.quantity.abs() > QUANTITY_EPSILON
+	}
+
+	fn apply(&mut self, transaction: &Transaction) {
+		if self.currency.is_empty() {
+			self.currency = transaction.currency.clone();
+		}
+
+		match transaction.kind {
+			TransactionKind::Buy => {
+				let cost = self.quantity * self.average_cost + transaction.quantity * transaction.price + transaction.fees;
+				self.quantity += transaction.quantity;
+				self.average_cost = if self.is_open() { cost / self.quantity } else { 0. };
+				self.fees += transaction.fees;
+			},
+			TransactionKind::Sell => {
+				let proceeds = transaction.quantity * transaction.price - transaction.fees;
+				self.realized_pnl += proceeds - transaction.quantity * self.average_cost;
+				self.quantity -= transaction.quantity;
+				self.fees += transaction.fees;
+				if !self.is_open() {
+					self.quantity = 0.;
+					self.average_cost = 0.;
+				}
+			},
+			TransactionKind::Dividend => {
+				self.dividends += transaction.price - transaction.fees;
+				self.fees += transaction.fees;
+			},
+			TransactionKind::Split => {
+				if transaction.quantity > 0. {
+					self.quantity *= transaction.quantity;
+					self.average_cost /= transaction.quantity;
+				}
+			},
+			TransactionKind::Fee => {
+				self.realized_pnl -= transaction.price + transaction.fees;
+				self.fees += transaction.price + transaction.fees;
+			},
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Ledger {
+	pub transactions: Vec<Transaction>,
+}
+
+impl Ledger {
+	pub fn load() -> Self {
+		storage::load(LEDGER_FILE).unwrap_or_default()
+	}
+
+	pub fn save(&self) {
+		storage::save(LEDGER_FILE, self);
+	}
+
+	/// Appends `transaction` under a new id
+	pub fn add(&mut self, mut transaction: Transaction) {
+		transaction.id = self.transactions.iter().map(|transaction| transaction.id).max().unwrap_or(0) + 1;
+		self.transactions.push(transaction);
+	}
+
+	/// Keeps transactions in date order, same-day ones stay in insertion order
+	pub fn sort(&mut self) {
+		self.transactions.sort_by_key(|transaction| transaction.date);
+	}
+
+	/// Replays the ledger in date order into one holding per symbol
+	pub fn holdings(&self) -> Vec<Holding> {
+		let mut order: Vec<&Transaction> = self.transactions.iter().collect();
+		order.sort_by_key(|transaction| transaction.date);
+
+		let mut holdings: Vec<Holding> = vec![];
+		let mut index: HashMap<&str, usize> = HashMap::new();
+		for transaction in order {
+			let i = *index.entry(&transaction.symbol).or_insert_with(|| {
+				holdings.push(Holding::new(&transaction.symbol, &transaction.currency));
+				holdings.len() - 1
+			});
+			holdings[i].apply(transaction);
+		}
+
+		holdings
+	}
+}
+
+pub fn format_date(date: Date) -> String {
+	date.format(format_description!("[year]-[month]-[day]")).unwrap_or_default()
+}
+
+pub fn parse_date(text: &str) -> Option<Date> {
+	Date::parse(text.trim(), format_description!("[year]-[month]-[day]")).ok()
+}
+
+/// Whether editing a field is done: drags once released, typing once the field is left,
+/// so the ledger isn't replayed and saved on every frame of a drag or every keystroke
+fn edit_finished(response: &Response) -> bool {
+	response.drag_stopped() || response.lost_focus()
+}
+
+/// Editable list of the ledger's transactions
+pub struct LedgerView {
+	/// Row whose date is being edited and the text typed so far
+	editing_date: Option<(usize, String)>,
+}
+
+impl LedgerView {
+	pub fn new() -> Self {
+		Self {
+			editing_date: None,
+		}
+	}
+
+	/// Returns whether the ledger was changed
+	pub fn show(&mut self, ui: &mut Ui, ledger: &mut Ledger) -> bool {
+		let mut changed = false;
+		let mut remove = None;
+
+		if ui.button("➕ Add transaction").clicked() {
+			ledger.add(Transaction::new(TransactionKind::Buy, ""));
+			changed = true;
+		}
+		ui.add_space(8.);
+
+		// Lots a sell can name for specific-lot matching, by buy id
+		let mut buys: HashMap<String, Vec<(u64, Date, String)>> = HashMap::new();
+		for transaction in ledger.transactions.iter().filter(|transaction| transaction.kind == TransactionKind::Buy) {
+			let label = format!("{} · {} @ {:.2}", format_date(transaction.date), transaction.quantity, transaction.price);
+			buys.entry(transaction.symbol.clone()).or_default().push((transaction.id, transaction.date, label));
+		}
+
+		Grid::new("ledger_transactions").striped(true).spacing([12., 6.]).show(ui, |ui| {
+			for title in ["Date", "Type", "Symbol", "Quantity", "Price / amount", "Fees", "Currency", "Lot", ""] {
+				ui.label(RichText::new(title).strong());
+			}
+			ui.end_row();
+
+			for (index, transaction) in ledger.transactions.iter_mut().enumerate() {
+				changed |= self.date_cell(ui, index, &mut transaction.date);
+
+				ComboBox::from_id_salt(("transaction_kind", index))
+					.selected_text(transaction.kind.label())
+					.show_ui(ui, |ui| {
+						for kind in TransactionKind::ALL {
+							changed |= ui.selectable_value(&mut transaction.kind, kind, kind.label()).changed();
+						}
+					});
+
+				changed |= edit_finished(&ui.add(TextEdit::singleline(&mut transaction.symbol).desired_width(70.)));
+				changed |= edit_finished(&ui.add(DragValue::new(&mut transaction.quantity).speed(1.)));
+				changed |= edit_finished(&ui.add(DragValue::new(&mut transaction.price).speed(0.01)));
+				changed |= edit_finished(&ui.add(DragValue::new(&mut transaction.fees).speed(0.01).range(0.0..=f64::MAX)));
+				changed |= edit_finished(&ui.add(TextEdit::singleline(&mut transaction.currency).desired_width(40.)));
+
+				if transaction.kind == TransactionKind::Sell {
+					let lots = buys.get(&transaction.symbol).map_or(&[][..], Vec::as_slice);
+					let selected = match transaction.lot_id {
+						Some(id) => lots.iter().find(|(lot, _, _)| *lot == id).map_or("Deleted buy", |(_, _, label)| label.as_str()),
+						None => "Auto",
+					};
+					ComboBox::from_id_salt(("transaction_lot", index))
+						.selected_text(selected)
+						.show_ui(ui, |ui| {
+							changed |= ui.selectable_value(&mut transaction.lot_id, None, "Auto").changed();
+							for (id, _, label) in lots.iter().filter(|(_, acquired, _)| *acquired <= transaction.date) {
+								changed |= ui.selectable_value(&mut transaction.lot_id, Some(*id), label).changed();
+							}
+						});
+				} else {
+					ui.label("");
+				}
+
+				if ui.small_button("✖").on_hover_text("Delete transaction").clicked() {
+					remove = Some(index);
+				}
+				ui.end_row();
+			}
+		});
+
+		if let Some(index) = remove {
+			ledger.transactions.remove(index);
+			self.editing_date = None;
+			changed = true;
+		}
+
+		changed
+	}
+
+	fn date_cell(&mut self, ui: &mut Ui, index: usize, date: &mut Date) -> bool {
+		match &mut self.editing_date {
+			Some((row, text)) if *row == index => {
+				let valid = parse_date(text).is_some();
+				let response = ui.add(TextEdit::singleline(text).desired_width(80.).text_color_opt((!valid).then_some(Color32::RED)));
+				response.request_focus();
+
+				if response.lost_focus() {
+					let parsed = parse_date(text);
+					self.editing_date = None;
+					if let Some(parsed) = parsed {
+						*date = parsed;
+						return true;
+					}
+				}
+			},
+			_ => {
+				if ui.add(Label::new(format_date(*date)).sense(Sense::click())).on_hover_text("Click to edit").clicked() {
+					self.editing_date = Some((index, format_date(*date)));
+				}
+			},
+		}
+
+		false
+	}
+}
+
+impl Default for LedgerView {
+	fn default() -> Self {
+		Self::new()
+	}
+}
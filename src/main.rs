@@ -1,69 +1,275 @@
 pub mod stock_graph;
+pub mod downsample;
+pub mod market_hours;
 pub mod yahoo_api_helper;
 pub mod side_panel;
 pub mod search_bar;
+pub mod storage;
+pub mod watchlist;
+pub mod watchlist_table;
+pub mod quote_book;
+pub mod portfolio;
+pub mod ledger;
+pub mod csv_import;
+pub mod tax_lots;
+pub mod currency;
+pub mod performance;
+pub mod alerts;
+pub mod alert_sinks;
+pub mod rule_language;
+pub mod config;
+pub mod symbol_directory;
+pub mod change_basis;
+pub mod statistics;
+pub mod analysis;
+pub mod seasonality;
+pub mod correlation;
 
-use eframe::{egui::{self, RichText}, epaint::Color32};
+use std::{env, process};
+
+use alerts::Alerts;
+use analysis::{AnalysisTab, AnalysisView};
+use change_basis::{ChangeBasis, ChangeBasisPicker};
+use config::{Args, Config};
+use correlation::CorrelationView;
+use currency::Currencies;
+use eframe::egui::{self, RichText};
+use market_hours::RefreshPolicy;
+use portfolio::PortfolioView;
+use quote_book::QuoteBook;
 use search_bar::SearchBar;
-use side_panel::StockSidePanel;
+use serde::{Deserialize, Serialize};
+use side_panel::{SidePanelState, StockSidePanel};
+use statistics::{StatisticsPanel, StatisticsSettings};
 use stock_graph::StockGraph;
 
 fn main() -> eframe::Result {
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("Error: {err}\n\n{}", config::USAGE);
+            process::exit(2);
+        }
+    };
+    if args.help {
+        println!("{}", config::USAGE);
+        return Ok(());
+    }
+
+    match Config::load(&args) {
+        Ok(config) => config::set(config),
+        Err(err) => {
+            eprintln!("Error: {err}");
+            process::exit(2);
+        }
+    }
+
     let options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default().with_inner_size([1920.0, 1200.0]),
-        centered: true,
+        viewport: egui::ViewportBuilder::default().with_inner_size(config::get().window_size),
+        // Centering would override the window position restored from the last session
+        centered: eframe::storage_dir(storage::APP_NAME).is_none_or(|dir| !dir.join("app.ron").exists()),
+        persist_window: true,
         ..Default::default()
     };
     eframe::run_native(
-        "Stonitor",
+        storage::APP_NAME,
         options,
-        Box::new(|_cc| Ok(Box::<MyApp>::default())),
+        Box::new(move |cc| Ok(Box::new(MyApp::new(cc, &args)))),
     )
 }
 
+#[derive(PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+enum View {
+    Chart,
+    Portfolio,
+    Alerts,
+    Correlation,
+}
+
 struct MyApp {
     stock_graph: StockGraph,
     stock_side_panel: StockSidePanel,
-    search_bar: SearchBar
+    search_bar: SearchBar,
+    refresh_policy: RefreshPolicy,
+    quote_book: QuoteBook,
+    currencies: Currencies,
+    portfolio_view: PortfolioView,
+    alerts: Alerts,
+    change_basis: ChangeBasisPicker,
+    statistics_panel: StatisticsPanel,
+    analysis_view: AnalysisView,
+    correlation_view: CorrelationView,
+    view: View
+}
+
+/// What is restored on the next launch, window size and panel widths are kept by eframe itself
+#[derive(Serialize, Deserialize)]
+struct AppState {
+    ticker: String,
+    range: String,
+    interval: Option<String>,
+    view: View,
+    search: String,
+    side_panel: SidePanelState,
+    refresh_policy: RefreshPolicy,
+    change_basis: ChangeBasis,
+    statistics: StatisticsSettings,
+    analysis_tab: AnalysisTab,
+}
+
+impl MyApp {
+    fn new(cc: &eframe::CreationContext<'_>, args: &Args) -> Self {
+        let mut app = Self::default();
+        let config = config::get();
+
+        if let Some(state) = cc.storage.and_then(|storage| eframe::get_value::<AppState>(storage, eframe::APP_KEY)) {
+            app.stock_graph.change_ticker(&state.ticker);
+            app.stock_graph.change_range_interval(&state.range, state.interval.as_deref());
+            app.view = state.view;
+            app.search_bar.set_search_text(&state.search);
+            app.stock_side_panel.restore(state.side_panel);
+            app.refresh_policy = state.refresh_policy;
+            app.change_basis = ChangeBasisPicker::new(state.change_basis);
+            app.statistics_panel = StatisticsPanel::new(state.statistics);
+            app.analysis_view.tab = state.analysis_tab;
+        }
+
+        // The config file's refresh intervals and the command-line flags win over the last session
+        if let Some(refresh_policy) = &config.refresh {
+            app.refresh_policy = refresh_policy.clone();
+        }
+        if args.ticker.is_some() {
+            app.stock_graph.change_ticker(&config.ticker);
+        }
+        if args.range.is_some() || args.interval.is_some() {
+            app.stock_graph.change_range_interval(&config.range, config.interval.as_deref());
+        }
+
+        app
+    }
 }
 
 impl Default for MyApp {
     fn default() -> Self {
+        let config = config::get();
+        let mut stock_graph = StockGraph::new(&config.ticker);
+        stock_graph.change_range_interval(&config.range, config.interval.as_deref());
+
         Self {
-            stock_graph: StockGraph::new("TSLA"),
+            stock_graph,
             stock_side_panel: StockSidePanel::new(),
-            search_bar: SearchBar::new()
+            search_bar: SearchBar::load(),
+            refresh_policy: config.refresh.clone().unwrap_or_default(),
+            quote_book: QuoteBook::new(),
+            currencies: Currencies::load(),
+            portfolio_view: PortfolioView::new(),
+            alerts: Alerts::load(),
+            change_basis: ChangeBasisPicker::default(),
+            statistics_panel: StatisticsPanel::default(),
+            analysis_view: AnalysisView::default(),
+            correlation_view: CorrelationView::new(),
+            view: View::Chart
         }
     }
 }
 
 impl eframe::App for MyApp {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        let state = AppState {
+            ticker: self.stock_graph.ticker().to_string(),
+            range: self.stock_graph.data_range.clone(),
+            interval: self.stock_graph.data_interval.clone(),
+            view: self.view,
+            search: self.search_bar.search_text().to_string(),
+            side_panel: self.stock_side_panel.state(),
+            refresh_policy: self.refresh_policy.clone(),
+            change_basis: self.change_basis.basis,
+            statistics: self.statistics_panel.settings.clone(),
+            analysis_tab: self.analysis_view.tab,
+        };
+        eframe::set_value(storage, eframe::APP_KEY, &state);
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        ctx.request_repaint();
-        self.stock_graph.update_data();
+        egui::TopBottomPanel::top("menu_bar").show(ctx, |ui| {
+            egui::menu::bar(ui, |ui| {
+                ui.menu_button("Settings", |ui| {
+                    ui.label(RichText::new("Refresh intervals").strong());
+                    if self.refresh_policy.ui(ui) {
+                        self.stock_graph.reschedule();
+                        self.quote_book.reschedule();
+                    }
+                    ui.separator();
+                    ui.label(RichText::new("Currency").strong());
+                    self.currencies.ui(ui);
+                });
+                ui.separator();
+                ui.selectable_value(&mut self.view, View::Chart, "Chart");
+                ui.selectable_value(&mut self.view, View::Portfolio, "Portfolio");
+                let alerts_label = match self.alerts.unseen {
+                    0 => "Alerts".to_string(),
+                    unseen => format!("Alerts ({unseen})"),
+                };
+                ui.selectable_value(&mut self.view, View::Alerts, alerts_label);
+                ui.selectable_value(&mut self.view, View::Correlation, "Correlation");
+            });
+        });
+
+        self.stock_graph.update_data(ctx, &self.refresh_policy);
+        let tickers = self.stock_side_panel.tickers().iter().chain(self.portfolio_view.symbols());
+        let fx_symbols = self.currencies.fx_symbols(&self.quote_book, tickers.clone(), self.portfolio_view.currencies());
+        let tickers = tickers.chain(fx_symbols).chain(self.alerts.symbols()).map(String::as_str);
+        // The charted symbol too, the header's change comes from the quote book like the side panel's
+        self.quote_book.refresh(ctx, &self.refresh_policy, tickers.chain(std::iter::once(self.stock_graph.ticker())));
+        self.quote_book.fetch_anchor_closes(ctx, self.change_basis.basis.anchor_date());
+        self.currencies.update(&self.quote_book);
+        self.alerts.evaluate(ctx, &mut self.quote_book, &self.refresh_policy);
 
         egui::SidePanel::left("left_panel").show(ctx, |ui| {
-            self.search_bar.show(ui, &mut self.stock_graph);
+            let mut add_ticker = None;
+            self.search_bar.show(ui, &mut self.stock_graph, &mut add_ticker);
+            if let Some(ticker) = add_ticker {
+                self.stock_side_panel.add_ticker(&ticker);
+            }
 
             if !self.search_bar.searching {
                 let mut change_ticker = None;
-                self.stock_side_panel.show(ui, &mut change_ticker);
+                self.stock_side_panel.show(ui, &mut self.quote_book, &self.currencies, self.change_basis.basis, &mut change_ticker);
                 if let Some(ticker) = change_ticker {
                     self.stock_graph.change_ticker(&ticker);
+                    self.view = View::Chart;
                 }
             }
         });
         
         
         egui::CentralPanel::default().show(ctx, |ui| {
+            if self.view == View::Alerts {
+                egui::ScrollArea::vertical().show(ui, |ui| self.alerts.show(ui, &self.quote_book));
+                return;
+            }
+            if self.view == View::Correlation {
+                egui::ScrollArea::both().show(ui, |ui| self.correlation_view.show(ui, self.stock_side_panel.tickers()));
+                return;
+            }
+            if self.view == View::Portfolio {
+                let mut change_ticker = None;
+                egui::ScrollArea::both().show(ui, |ui| {
+                    self.portfolio_view.show(ui, &self.quote_book, &self.currencies, &mut change_ticker);
+                });
+                if let Some(ticker) = change_ticker {
+                    self.stock_graph.change_ticker(&ticker);
+                    self.view = View::Chart;
+                }
+                return;
+            }
+
             egui::ScrollArea::vertical().show(ui, |ui| {
                 if let Some(metadata) = &self.stock_graph.metadata {
                     let latest_price = self.stock_graph.price_data[self.stock_graph.price_data.len() - 1][1];
-                    let start_price = match metadata.previous_close {
-                        Some(pc) => pc,
-                        None => self.stock_graph.price_data[0][1]
-                    };
-                    let p_change = (latest_price - start_price) / start_price * 100.;
+                    let colors = &config::get().colors;
+                    let stock = self.quote_book.get(self.stock_graph.ticker()).filter(|stock| stock.metadata.is_some());
+                    let start_price = stock.and_then(|stock| self.quote_book.change_reference(stock, self.change_basis.basis, Some(&self.stock_graph.price_data)));
 
                     let currency = match metadata.currency.as_ref() {
                         Some(currency) => currency,
@@ -73,25 +279,49 @@ impl eframe::App for MyApp {
                     ui.horizontal(|ui| {
                         ui.label(RichText::new(metadata.symbol.clone()).size(30.).strong());
 
-                        if p_change > 0. {
-                            ui.label(RichText::new(format!("+{:.4}%", p_change)).heading().color(Color32::GREEN));
-                            ui.label(RichText::new(format!("+{:.4} {}", start_price * (p_change / 100.), currency)).heading().color(Color32::GREEN));
-                        } else if p_change < 0. {
-                            ui.label(RichText::new(format!("{:.4}%", p_change)).heading().color(Color32::RED));
-                            ui.label(RichText::new(format!("{:.4} {}", start_price * (p_change / 100.), currency)).heading().color(Color32::RED));
-                        } else {
-                            ui.label(format!("+{:.2}%", p_change));
+                        match start_price {
+                            Some(start_price) => {
+                                let p_change = (latest_price - start_price) / start_price * 100.;
+                                if p_change > 0. {
+                                    ui.label(RichText::new(format!("+{:.4}%", p_change)).heading().color(colors.gain));
+                                    ui.label(RichText::new(format!("+{:.4} {}", start_price * (p_change / 100.), currency)).heading().color(colors.gain));
+                                } else if p_change < 0. {
+                                    ui.label(RichText::new(format!("{:.4}%", p_change)).heading().color(colors.loss));
+                                    ui.label(RichText::new(format!("{:.4} {}", start_price * (p_change / 100.), currency)).heading().color(colors.loss));
+                                } else {
+                                    ui.label(format!("+{:.2}%", p_change));
+                                }
+                            }
+                            None => {
+                                let reason = match self.change_basis.basis {
+                                    _ if stock.is_none() => "Loading quote",
+                                    ChangeBasis::AnchorDate(_) => "No close at the anchor date",
+                                    _ => "No previous close"
+                                };
+                                ui.label(RichText::new(reason).weak());
+                            }
                         }
+
+                        ui.label("vs");
+                        self.change_basis.ui(ui);
                     });
                     ui.label(RichText::new(format!("Current price: {:.2} {}", latest_price, currency)).strong().heading());
                     
                     ui.horizontal(|ui| {
                         ui.label(metadata.exchange_name.clone());
                         ui.label(metadata.instrument_type.clone());
+                        ui.separator();
+                        market_hours::market_status_badge(ui, metadata);
                     });
                 }
                 
-                self.stock_graph.show(ui);
+                self.analysis_view.tab_bar(ui);
+                if self.analysis_view.tab == AnalysisTab::Chart {
+                    self.stock_graph.show(ui);
+                    self.statistics_panel.show(ui, &self.stock_graph);
+                } else {
+                    self.analysis_view.show(ui, &mut self.stock_graph);
+                }
             });
         });
     }
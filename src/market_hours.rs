@@ -0,0 +1,448 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+use yahoo_finance_api::{time::{OffsetDateTime, Weekday}, YMetaData};
+
+use crate::config;
+
+const HOUR: i64 = 60 * 60;
+pub const DAY: i64 = 24 * HOUR;
+const WEEK: i64 = 7 * DAY;
+const DEFAULT_CLOSED_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// Longest gap between the last quote and now that is still expected while the market is open
+const STALE_WHILE_OPEN: i64 = 15 * 60;
+/// Longest gap before a session (weekends and long holiday weekends included)
+const STALE_BETWEEN_SESSIONS: i64 = 4 * DAY;
+
+/// Closed spans of the trading week as seconds since Monday 00:00 in the exchange's time zone
+type WeeklyClosures = &'static [(i64, i64)];
+
+/// FX trades from Sunday 17:00 to Friday 17:00 New York time, which is 22:00 in London where Yahoo lists it
+const FX_CLOSURES: WeeklyClosures = &[(4 * DAY + 22 * HOUR, 6 * DAY + 22 * HOUR)];
+/// CME Globex hours in New York time, where Yahoo lists the futures: Sunday 18:00 to
+/// Friday 17:00 with an hour's maintenance break every evening. Other futures exchanges are approximated by it.
+const FUTURES_CLOSURES: WeeklyClosures = &[
+	(17 * HOUR, 18 * HOUR),
+	(DAY + 17 * HOUR, DAY + 18 * HOUR),
+	(2 * DAY + 17 * HOUR, 2 * DAY + 18 * HOUR),
+	(3 * DAY + 17 * HOUR, 3 * DAY + 18 * HOUR),
+	(4 * DAY + 17 * HOUR, 6 * DAY + 18 * HOUR),
+];
+
+/// How a symbol trades, from the instrument type in its metadata
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AssetClass {
+	/// Stocks, ETFs and funds, traded in daily exchange sessions
+	Equity,
+	/// Follows the exchange sessions of its constituents but has no volume
+	Index,
+	Currency,
+	Crypto,
+	Future,
+}
+
+impl AssetClass {
+	pub fn of(metadata: &YMetaData) -> Self {
+		match metadata.instrument_type.as_str() {
+			"INDEX" => AssetClass::Index,
+			"CURRENCY" => AssetClass::Currency,
+			"CRYPTOCURRENCY" => AssetClass::Crypto,
+			"FUTURE" => AssetClass::Future,
+			_ => AssetClass::Equity,
+		}
+	}
+
+	/// Yahoo reports zero volume for indices and FX pairs
+	pub fn has_volume(&self) -> bool {
+		!matches!(self, AssetClass::Index | AssetClass::Currency)
+	}
+
+	/// Days a year the market trades, to annualize daily statistics
+	pub fn trading_days_per_year(&self) -> f64 {
+		match self {
+			AssetClass::Crypto => 365.,
+			AssetClass::Currency => 260.,
+			AssetClass::Equity | AssetClass::Index | AssetClass::Future => 252.,
+		}
+	}
+
+	/// Weekly schedule of markets trading around the clock, `None` for ones whose
+	/// sessions come from the metadata's trading periods
+	fn weekly_closures(&self) -> Option<WeeklyClosures> {
+		match self {
+			AssetClass::Crypto => Some(&[]),
+			AssetClass::Currency => Some(FX_CLOSURES),
+			AssetClass::Future => Some(FUTURES_CLOSURES),
+			AssetClass::Equity | AssetClass::Index => None,
+		}
+	}
+}
+
+/// Whether a market with `closures` is open at `now` and when that changes next, `None` if never
+fn weekly_session(closures: WeeklyClosures, gmtoffset: i32, now: i64) -> (bool, Option<i64>) {
+	// The unix epoch was a Thursday, three days into its week
+	let week_time = (now + gmtoffset as i64 + 3 * DAY).rem_euclid(WEEK);
+
+	if let Some((_, end)) = closures.iter().find(|(start, end)| (*start..*end).contains(&week_time)) {
+		return (false, Some(now + end - week_time));
+	}
+
+	let next_close = closures.iter().map(|(start, _)| (start - week_time).rem_euclid(WEEK)).min();
+	(true, next_close.map(|until| now + until))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MarketSession {
+	PreMarket,
+	Open,
+	AfterHours,
+	Closed,
+}
+
+pub fn unix_now() -> i64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+/// Session of the symbol's exchange at `now` (unix seconds), taken from the
+/// current trading period in the chart metadata
+pub fn market_session(metadata: &YMetaData, now: i64) -> MarketSession {
+	if let Some(closures) = AssetClass::of(metadata).weekly_closures() {
+		return match weekly_session(closures, metadata.gmtoffset, now) {
+			(true, _) => MarketSession::Open,
+			(false, _) => MarketSession::Closed,
+		};
+	}
+
+	let period = &metadata.current_trading_period;
+	let within = |start: u32, end: u32| start < end && (start as i64..end as i64).contains(&now);
+
+	if within(period.regular.start, period.regular.end) {
+		MarketSession::Open
+	} else if within(period.pre.start, period.pre.end) {
+		MarketSession::PreMarket
+	} else if within(period.post.start, period.post.end) {
+		MarketSession::AfterHours
+	} else {
+		MarketSession::Closed
+	}
+}
+
+/// Estimated start of the next trading day (pre-market included) after `now`.
+/// The metadata only describes the current trading day, so later days are
+/// extrapolated by skipping weekends in the exchange's local time; holidays
+/// are picked up once a fetch at the estimated open reports the market still
+/// closed.
+pub fn next_session_start(metadata: &YMetaData, now: i64) -> i64 {
+	if let Some(closures) = AssetClass::of(metadata).weekly_closures() {
+		return match weekly_session(closures, metadata.gmtoffset, now) {
+			(false, Some(open)) => open,
+			_ => now,
+		};
+	}
+
+	let period = &metadata.current_trading_period;
+	let mut start = period.regular.start as i64;
+	if period.pre.start < period.pre.end && (period.pre.start as i64) < start {
+		start = period.pre.start as i64;
+	}
+
+	while start <= now || !is_weekday(start + metadata.gmtoffset as i64) {
+		start += DAY;
+	}
+
+	start
+}
+
+/// End of the session the exchange is currently in, if it is trading at all
+pub fn session_end(metadata: &YMetaData, now: i64) -> Option<i64> {
+	if let Some(closures) = AssetClass::of(metadata).weekly_closures() {
+		return match weekly_session(closures, metadata.gmtoffset, now) {
+			(true, close) => close,
+			(false, _) => None,
+		};
+	}
+
+	let period = &metadata.current_trading_period;
+	match market_session(metadata, now) {
+		MarketSession::PreMarket => Some(period.pre.end as i64),
+		MarketSession::Open => Some(period.regular.end as i64),
+		MarketSession::AfterHours => Some(period.post.end as i64),
+		MarketSession::Closed => None,
+	}
+}
+
+fn is_weekday(local_timestamp: i64) -> bool {
+	match OffsetDateTime::from_unix_timestamp(local_timestamp) {
+		Ok(time) => !matches!(time.weekday(), Weekday::Saturday | Weekday::Sunday),
+		Err(_) => true,
+	}
+}
+
+/// How often quotes are re-fetched depending on the session of the symbol's exchange
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RefreshPolicy {
+	pub open_interval: Duration,
+	pub extended_interval: Duration,
+	/// `None` stops polling while closed until the next session is expected to start
+	pub closed_interval: Option<Duration>,
+}
+
+impl RefreshPolicy {
+	pub fn new() -> Self {
+		Self {
+			open_interval: Duration::from_secs(2),
+			extended_interval: Duration::from_secs(10),
+			closed_interval: Some(DEFAULT_CLOSED_INTERVAL),
+		}
+	}
+
+	/// When a symbol whose last fetch returned `metadata` should be fetched again
+	pub fn next_refresh(&self, metadata: Option<&YMetaData>) -> Instant {
+		let Some(metadata) = metadata else {
+			return Instant::now() + self.open_interval;
+		};
+
+		let now = unix_now();
+		let interval = match market_session(metadata, now) {
+			MarketSession::Open => self.open_interval,
+			MarketSession::PreMarket | MarketSession::AfterHours => self.extended_interval,
+			MarketSession::Closed => {
+				let until_open = Duration::from_secs((next_session_start(metadata, now) - now).max(0) as u64);
+				match self.closed_interval {
+					Some(interval) => interval.min(until_open),
+					None => until_open,
+				}
+			},
+		};
+
+		// Don't sleep through a session change, the interval may need to change with it
+		let interval = match session_end(metadata, now) {
+			Some(end) => interval.min(Duration::from_secs((end - now).max(1) as u64)),
+			None => interval,
+		};
+
+		Instant::now() + interval
+	}
+
+	/// Returns whether any interval was changed
+	pub fn ui(&mut self, ui: &mut Ui) -> bool {
+		let old = (self.open_interval, self.extended_interval, self.closed_interval);
+
+		Grid::new("refresh_policy").num_columns(2).show(ui, |ui| {
+			ui.label("Market open:");
+			duration_drag(ui, &mut self.open_interval);
+			ui.end_row();
+
+			ui.label("Pre/after-hours:");
+			duration_drag(ui, &mut self.extended_interval);
+			ui.end_row();
+
+			let mut poll_closed = self.closed_interval.is_some();
+			if ui.checkbox(&mut poll_closed, "Market closed:").changed() {
+				self.closed_interval = poll_closed.then_some(DEFAULT_CLOSED_INTERVAL);
+			}
+			match &mut self.closed_interval {
+				Some(interval) => duration_drag(ui, interval),
+				None => {
+					ui.label("until next open");
+				},
+			}
+			ui.end_row();
+		});
+
+		old != (self.open_interval, self.extended_interval, self.closed_interval)
+	}
+}
+
+impl Default for RefreshPolicy {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+fn duration_drag(ui: &mut Ui, duration: &mut Duration) {
+	let mut secs = duration.as_secs();
+	ui.add(DragValue::new(&mut secs).range(1..=24 * 60 * 60).suffix(" s"));
+	*duration = Duration::from_secs(secs);
+}
+
+impl MarketSession {
+	pub fn label(&self) -> &'static str {
+		match self {
+			MarketSession::PreMarket => "Pre-market",
+			MarketSession::Open => "Open",
+			MarketSession::AfterHours => "After-hours",
+			MarketSession::Closed => "Closed",
+		}
+	}
+
+	pub fn color(&self) -> Color32 {
+		match self {
+			MarketSession::PreMarket | MarketSession::AfterHours => Color32::from_rgb(230, 170, 40),
+			MarketSession::Open => Color32::GREEN,
+			MarketSession::Closed => Color32::GRAY,
+		}
+	}
+}
+
+/// Whether the latest quote is older than the session at `now` would allow
+pub fn is_stale(metadata: &YMetaData, now: i64) -> bool {
+	let quote_time = metadata.regular_market_time as i64;
+	let regular = &metadata.current_trading_period.regular;
+	let around_the_clock = AssetClass::of(metadata).weekly_closures().is_some();
+
+	match market_session(metadata, now) {
+		MarketSession::Open => now - quote_time > STALE_WHILE_OPEN,
+		_ if around_the_clock => now - quote_time > STALE_BETWEEN_SESSIONS,
+		// Today's regular session is over, the last quote should be from its close
+		_ if (regular.end as i64) <= now => quote_time < regular.end as i64 - STALE_WHILE_OPEN,
+		_ => now - quote_time > STALE_BETWEEN_SESSIONS,
+	}
+}
+
+/// Badge with the exchange's session, a countdown to the next open or close
+/// and a warning if the quote is stale
+pub fn market_status_badge(ui: &mut Ui, metadata: &YMetaData) {
+	let now = unix_now();
+	let session = market_session(metadata, now);
+
+	// Crypto never closes, so there is nothing to count down to
+	let countdown = match session_end(metadata, now) {
+		Some(end) if session == MarketSession::Open => Some(("closes", end)),
+		Some(end) => Some(("ends", end)),
+		None if session == MarketSession::Closed => Some(("opens", next_session_start(metadata, now))),
+		None => None,
+	};
+
+	ui.horizontal(|ui| {
+		ui.label(RichText::new(format!("● {}", session.label())).color(session.color()).strong());
+		match countdown {
+			Some((event, at)) => ui.label(RichText::new(format!("{event} in {}", format_countdown((at - now).max(0)))).weak()),
+			None => ui.label(RichText::new("trades 24/7").weak()),
+		};
+
+		if is_stale(metadata, now) {
+			let timezone = config::get().timezone;
+			let quote_time = format_local_time(metadata.regular_market_time as i64, timezone.offset(metadata.gmtoffset));
+			ui.label(RichText::new("⚠ Stale").color(Color32::RED))
+				.on_hover_text(format!("Last quote at {quote_time} {}", timezone.label()));
+		}
+	});
+
+	// The countdown only shows minutes, so repaint when the next one ticks over
+	if let Some((_, at)) = countdown {
+		ui.ctx().request_repaint_after(Duration::from_secs(((at - now).max(0) % 60 + 1) as u64));
+	}
+}
+
+/// Price the day's change is measured from: the previous close, or for crypto and FX, which
+/// never close, the first of `prices` (timestamp, price) in the current trading day
+pub fn day_change_basis(metadata: &YMetaData, prices: impl IntoIterator<Item = (i64, f64)>) -> Option<f64> {
+	match AssetClass::of(metadata) {
+		AssetClass::Crypto | AssetClass::Currency => {
+			let day_start = metadata.current_trading_period.regular.start as i64;
+			prices.into_iter()
+				.find(|(timestamp, _)| *timestamp >= day_start)
+				.map(|(_, price)| price)
+				.or(metadata.previous_close)
+		},
+		AssetClass::Equity | AssetClass::Index | AssetClass::Future => metadata.previous_close,
+	}
+}
+
+fn format_countdown(secs: i64) -> String {
+	let (days, hours, minutes) = (secs / DAY, secs % DAY / 3600, secs % 3600 / 60);
+	if days > 0 {
+		format!("{days}d {hours}h")
+	} else if hours > 0 {
+		format!("{hours}h {minutes}m")
+	} else if minutes > 0 {
+		format!("{minutes}m")
+	} else {
+		"<1m".to_string()
+	}
+}
+
+pub fn format_local_time(timestamp: i64, gmtoffset: i32) -> String {
+	match OffsetDateTime::from_unix_timestamp(timestamp + gmtoffset as i64) {
+		Ok(time) => format!("{:04}-{:02}-{:02} {:02}:{:02}", time.year(), time.month() as u8, time.day(), time.hour(), time.minute()),
+		Err(_) => "unknown".to_string(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use time::macros::datetime;
+
+	use super::*;
+
+	fn metadata(instrument_type: &str, day_start: u32, previous_close: f64) -> YMetaData {
+		let period = serde_json::json!({ "timezone": "UTC", "start": day_start, "end": day_start + DAY as u32, "gmtoffset": 0 });
+		serde_json::from_value(serde_json::json!({
+			"symbol": "TEST",
+			"exchangeName": "TEST",
+			"instrumentType": instrument_type,
+			"regularMarketTime": day_start,
+			"gmtoffset": 0,
+			"timezone": "UTC",
+			"exchangeTimezoneName": "UTC",
+			"regularMarketPrice": previous_close,
+			"chartPreviousClose": previous_close,
+			"previousClose": previous_close,
+			"priceHint": 2,
+			"currentTradingPeriod": { "pre": period, "regular": period, "post": period },
+			"dataGranularity": "1m",
+			"range": "1d",
+			"validRanges": [],
+		})).unwrap()
+	}
+
+	#[test]
+	fn fx_closes_on_friday_at_five_in_new_york() {
+		// London is on GMT in January, so 17:00 in New York is 22:00 there
+		let close = datetime!(2024-01-05 22:00 UTC).unix_timestamp();
+		let reopen = datetime!(2024-01-07 22:00 UTC).unix_timestamp();
+		assert_eq!(weekly_session(FX_CLOSURES, 0, close - 60), (true, Some(close)));
+		assert_eq!(weekly_session(FX_CLOSURES, 0, close + 60), (false, Some(reopen)));
+	}
+
+	#[test]
+	fn fx_reopens_on_sunday_evening() {
+		let reopen = datetime!(2024-01-07 22:00 UTC).unix_timestamp();
+		let next_close = datetime!(2024-01-12 22:00 UTC).unix_timestamp();
+		assert_eq!(weekly_session(FX_CLOSURES, 0, reopen - 60), (false, Some(reopen)));
+		assert_eq!(weekly_session(FX_CLOSURES, 0, reopen), (true, Some(next_close)));
+	}
+
+	#[test]
+	fn futures_pause_for_daily_maintenance() {
+		let new_york = -5 * HOUR as i32;
+		let break_start = datetime!(2024-01-09 17:00 -5).unix_timestamp();
+		let break_end = datetime!(2024-01-09 18:00 -5).unix_timestamp();
+		assert_eq!(weekly_session(FUTURES_CLOSURES, new_york, break_start - 60), (true, Some(break_start)));
+		assert_eq!(weekly_session(FUTURES_CLOSURES, new_york, break_start + 30 * 60), (false, Some(break_end)));
+		assert!(weekly_session(FUTURES_CLOSURES, new_york, break_end).0);
+	}
+
+	#[test]
+	fn crypto_trades_on_weekends() {
+		let saturday = datetime!(2024-01-06 12:00 UTC).unix_timestamp();
+		let crypto = metadata("CRYPTOCURRENCY", saturday as u32, 40000.);
+		assert_eq!(market_session(&crypto, saturday), MarketSession::Open);
+		assert_eq!(session_end(&crypto, saturday), None);
+	}
+
+	#[test]
+	fn day_change_basis_of_markets_that_never_close() {
+		let day_start = datetime!(2024-01-08 00:00 UTC).unix_timestamp();
+		let prices = [(day_start - 60, 1.09), (day_start + 60, 1.1), (day_start + 120, 1.11)];
+
+		let fx = metadata("CURRENCY", day_start as u32, 1.08);
+		assert_eq!(day_change_basis(&fx, prices), Some(1.1));
+		assert_eq!(day_change_basis(&fx, []), Some(1.08));
+
+		let equity = metadata("EQUITY", day_start as u32, 1.08);
+		assert_eq!(day_change_basis(&equity, prices), Some(1.08));
+	}
+}
@@ -0,0 +1,448 @@
+use std::{collections::{BTreeMap, BTreeSet, HashMap}, ops::RangeInclusive};
+
+use eframe::egui::*;
+use egui_plot::*;
+use time::{Date, Duration, OffsetDateTime};
+
+use crate::{config, currency::{fx_symbol, major_unit, Currencies}, ledger::{format_date, Ledger, TransactionKind}, yahoo_api_helper::{fetch_daily_closes, DailyClosesHandle}};
+
+const DEFAULT_BENCHMARK: &str = "^GSPC";
+
+/// Portfolio value and returns per trading day, in the base currency.
+/// Points are [unix time, value].
+pub struct PerformanceSeries {
+	pub value: Vec<[f64; 2]>,
+	/// Buys and fees paid in minus sells and dividends paid out
+	pub invested: Vec<[f64; 2]>,
+	/// Cumulative time-weighted return in percent
+	pub twr: Vec<[f64; 2]>,
+	/// Percent below the previous time-weighted high
+	pub drawdown: Vec<[f64; 2]>,
+	/// Cumulative price return of the benchmark in percent
+	pub benchmark: Vec<[f64; 2]>,
+	pub twr_total: f64,
+	/// Only for histories longer than a year
+	pub twr_annualized: Option<f64>,
+	/// Annualized money-weighted return
+	pub irr: Option<f64>,
+	pub max_drawdown: f64,
+}
+
+fn date_of(timestamp: f64) -> Option<Date> {
+	OffsetDateTime::from_unix_timestamp(timestamp as i64).ok().map(|time| time.date())
+}
+
+fn timestamp_of(date: Date) -> f64 {
+	date.midnight().assume_utc().unix_timestamp() as f64
+}
+
+/// Latest close on or before `day`
+fn close_on(series: Option<&BTreeMap<Date, f64>>, day: Date) -> Option<f64> {
+	series?.range(..=day).next_back().map(|(_, close)| *close)
+}
+
+impl PerformanceSeries {
+	/// Replays `ledger` over the trading days in `closes`, which holds the
+	/// daily closes of its symbols, the FX rates into the base currency and
+	/// the benchmark
+	pub fn new(ledger: &Ledger, closes: &HashMap<String, Vec<[f64; 2]>>, benchmark: &str, currencies: &Currencies) -> Option<Self> {
+		let mut transactions: Vec<_> = ledger.transactions.iter().filter(|transaction| !transaction.symbol.is_empty()).collect();
+		transactions.sort_by_key(|transaction| transaction.date);
+		let first_date = transactions.first()?.date;
+
+		let series: HashMap<&str, BTreeMap<Date, f64>> = closes.iter()
+			.map(|(ticker, closes)| (ticker.as_str(), closes.iter().filter_map(|[timestamp, close]| Some((date_of(*timestamp)?, *close))).collect()))
+			.collect();
+
+		let mut days: BTreeSet<Date> = transactions.iter()
+			.filter_map(|transaction| series.get(transaction.symbol.as_str()))
+			.flat_map(|closes| closes.keys().copied())
+			.filter(|day| *day >= first_date)
+			.collect();
+		// Trades before the first close are booked on their own day
+		days.insert(first_date);
+
+		let fx = |currency: &str, day: Date| -> f64 {
+			let (major, factor) = major_unit(currency);
+			if major.is_empty() || major == currencies.base {
+				return factor;
+			}
+			close_on(series.get(fx_symbol(major, &currencies.base).as_str()), day)
+				.map(|rate| rate * factor)
+				.or(currencies.rate(currency))
+				.unwrap_or(factor)
+		};
+
+		// Yahoo's closes are split-adjusted, so quantities before a split are scaled to today's shares
+		let split_factor = |symbol: &str, day: Date| -> f64 {
+			transactions.iter()
+				.filter(|transaction| transaction.kind == TransactionKind::Split && transaction.symbol == symbol && transaction.date > day && transaction.quantity > 0.)
+				.map(|transaction| transaction.quantity)
+				.product()
+		};
+
+		let mut quantities: HashMap<&str, f64> = HashMap::new();
+		let mut currency_of: HashMap<&str, &str> = HashMap::new();
+		let mut last_trade: HashMap<&str, f64> = HashMap::new();
+		let mut next = 0;
+
+		let mut result = Self {
+			value: vec![],
+			invested: vec![],
+			twr: vec![],
+			drawdown: vec![],
+			benchmark: vec![],
+			twr_total: 0.,
+			twr_annualized: None,
+			irr: None,
+			max_drawdown: 0.,
+		};
+		let mut cash_flows: Vec<(Date, f64)> = vec![];
+		let mut invested = 0.;
+		let mut previous_value = 0.;
+		let mut index = 1.;
+		let mut peak = 1.;
+		let mut benchmark_start = None;
+
+		for day in days {
+			let mut flow = 0.;
+			while let Some(transaction) = transactions.get(next).filter(|transaction| transaction.date <= day) {
+				next += 1;
+				let symbol = transaction.symbol.as_str();
+				currency_of.entry(symbol).or_insert(&transaction.currency);
+				let rate = fx(&transaction.currency, day);
+				let quantity = quantities.entry(symbol).or_default();
+
+				match transaction.kind {
+					TransactionKind::Buy => {
+						*quantity += transaction.quantity;
+						flow += (transaction.quantity * transaction.price + transaction.fees) * rate;
+						last_trade.insert(symbol, transaction.price);
+					},
+					TransactionKind::Sell => {
+						*quantity -= transaction.quantity;
+						flow -= (transaction.quantity * transaction.price - transaction.fees) * rate;
+						last_trade.insert(symbol, transaction.price);
+					},
+					TransactionKind::Dividend => flow -= (transaction.price - transaction.fees) * rate,
+					TransactionKind::Fee => flow += (transaction.price + transaction.fees) * rate,
+					TransactionKind::Split if transaction.quantity > 0. => {
+						*quantity *= transaction.quantity;
+						if let Some(price) = last_trade.get_mut(symbol) {
+							*price /= transaction.quantity;
+						}
+					},
+					TransactionKind::Split => {},
+				}
+			}
+
+			let value: f64 = quantities.iter().map(|(symbol, quantity)| {
+				let currency = currency_of.get(symbol).copied().unwrap_or("");
+				let price = match close_on(series.get(symbol), day) {
+					Some(close) => close * split_factor(symbol, day),
+					None => last_trade.get(symbol).copied().unwrap_or(0.),
+				};
+				quantity * price * fx(currency, day)
+			}).sum();
+
+			// Flows are assumed to happen at the end of the day
+			if previous_value > 0. {
+				index *= (value - flow) / previous_value;
+			}
+			peak = f64::max(peak, index);
+			invested += flow;
+			previous_value = value;
+			if flow != 0. {
+				cash_flows.push((day, -flow));
+			}
+
+			let x = timestamp_of(day);
+			result.value.push([x, value]);
+			result.invested.push([x, invested]);
+			result.twr.push([x, (index - 1.) * 100.]);
+			result.drawdown.push([x, (index / peak - 1.) * 100.]);
+			result.max_drawdown = result.max_drawdown.min((index / peak - 1.) * 100.);
+
+			if let Some(close) = close_on(series.get(benchmark), day) {
+				let start = *benchmark_start.get_or_insert(close);
+				result.benchmark.push([x, (close / start - 1.) * 100.]);
+			}
+		}
+
+		let last_day = date_of(result.value.last()?[0])?;
+		result.twr_total = (index - 1.) * 100.;
+		let years = (last_day - first_date).whole_days() as f64 / 365.25;
+		if years >= 1. {
+			result.twr_annualized = Some((index.powf(1. / years) - 1.) * 100.);
+		}
+
+		cash_flows.push((last_day, previous_value));
+		result.irr = irr(&cash_flows).map(|rate| rate * 100.);
+
+		Some(result)
+	}
+}
+
+/// Annual rate at which the cash flows' net present value is zero, found by bisection
+fn irr(cash_flows: &[(Date, f64)]) -> Option<f64> {
+	let start = cash_flows.first()?.0;
+	let npv = |rate: f64| -> f64 {
+		cash_flows.iter().map(|(date, amount)| amount / (1. + rate).powf((*date - start).whole_days() as f64 / 365.25)).sum()
+	};
+
+	let (mut low, mut high) = (-0.9999, 100.);
+	let (mut npv_low, npv_high) = (npv(low), npv(high));
+	if !npv_low.is_finite() || !npv_high.is_finite() || npv_low.signum() == npv_high.signum() {
+		return None;
+	}
+
+	for _ in 0..200 {
+		let mid = (low + high) / 2.;
+		let npv_mid = npv(mid);
+		if npv_mid.abs() < 1e-9 || high - low < 1e-10 {
+			return Some(mid);
+		}
+		if npv_mid.signum() == npv_low.signum() {
+			low = mid;
+			npv_low = npv_mid;
+		} else {
+			high = mid;
+		}
+	}
+
+	Some((low + high) / 2.)
+}
+
+/// Portfolio value, returns against a benchmark and drawdowns over time
+pub struct PerformanceView {
+	benchmark: String,
+	benchmark_input: String,
+	fetch_handle: DailyClosesHandle,
+	closes: HashMap<String, Vec<[f64; 2]>>,
+	/// Tickers `closes` was fetched for, a different set fetches again
+	fetched: Vec<String>,
+	series: Option<PerformanceSeries>,
+	/// Base currency `series` was computed in
+	series_base: String,
+	dirty: bool,
+}
+
+impl PerformanceView {
+	pub fn new() -> Self {
+		Self {
+			benchmark: DEFAULT_BENCHMARK.to_string(),
+			benchmark_input: DEFAULT_BENCHMARK.to_string(),
+			fetch_handle: None,
+			closes: HashMap::new(),
+			fetched: vec![],
+			series: None,
+			series_base: String::new(),
+			dirty: true,
+		}
+	}
+
+	/// Recomputes the series, e.g. after the ledger changed
+	pub fn invalidate(&mut self) {
+		self.dirty = true;
+	}
+
+	fn tickers(&self, ledger: &Ledger, currencies: &Currencies) -> Vec<String> {
+		let mut tickers: Vec<String> = vec![];
+		for transaction in ledger.transactions.iter().filter(|transaction| !transaction.symbol.is_empty()) {
+			tickers.push(transaction.symbol.clone());
+			let (currency, _) = major_unit(&transaction.currency);
+			if !currency.is_empty() && currency != currencies.base {
+				tickers.push(fx_symbol(currency, &currencies.base));
+			}
+		}
+		if !self.benchmark.is_empty() {
+			tickers.push(self.benchmark.clone());
+		}
+
+		tickers.sort();
+		tickers.dedup();
+		tickers
+	}
+
+	pub fn show(&mut self, ui: &mut Ui, ledger: &Ledger, currencies: &Currencies) {
+		let tickers = self.tickers(ledger, currencies);
+		if self.fetch_handle.is_some() || tickers != self.fetched {
+			if self.fetch_handle.is_none() {
+				self.fetched = tickers;
+			}
+			// A week of margin so there's a close to value the first trades at
+			let start = ledger.transactions.iter().map(|transaction| transaction.date).min()
+				.map_or(OffsetDateTime::now_utc(), |date| date.midnight().assume_utc()) - Duration::days(7);
+			if fetch_daily_closes(ui.ctx(), &mut self.fetch_handle, &self.fetched, start, &mut self.closes) {
+				self.dirty = true;
+			}
+		}
+
+		if self.series_base != currencies.base {
+			self.series_base = currencies.base.clone();
+			self.dirty = true;
+		}
+		if self.dirty && self.fetch_handle.is_none() {
+			self.series = PerformanceSeries::new(ledger, &self.closes, &self.benchmark, currencies);
+			self.dirty = false;
+		}
+
+		ui.horizontal(|ui| {
+			ui.label("Benchmark:");
+			let response = ui.add(TextEdit::singleline(&mut self.benchmark_input).desired_width(80.));
+			let submitted = response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter));
+			if submitted || ui.button("Compare").clicked() {
+				self.benchmark = self.benchmark_input.trim().to_uppercase();
+				self.benchmark_input = self.benchmark.clone();
+			}
+			if ui.button("⟳ Reload").on_hover_text("Fetch the price history again").clicked() {
+				self.fetched.clear();
+			}
+			if self.fetch_handle.is_some() {
+				ui.spinner();
+				ui.label("Loading price history...");
+			}
+		});
+
+		let Some(series) = &self.series else {
+			if self.fetch_handle.is_none() {
+				ui.label("No transactions to chart yet.");
+			}
+			return;
+		};
+
+		let base = &currencies.base;
+		let colors = &config::get().colors;
+		ui.horizontal(|ui| {
+			let last = |points: &[[f64; 2]]| points.last().map_or(0., |point| point[1]);
+			ui.label(RichText::new(format!("Value: {:.2} {base}", last(&series.value))).strong());
+			ui.separator();
+			ui.label(format!("Net invested: {:.2} {base}", last(&series.invested)));
+			ui.separator();
+			ui.label(format!("TWR: {:+.2}%", series.twr_total));
+			if let Some(annualized) = series.twr_annualized {
+				ui.label(format!("({annualized:+.2}% p.a.)"));
+			}
+			ui.separator();
+			ui.label(series.irr.map_or("IRR: -".to_string(), |irr| format!("IRR: {irr:+.2}% p.a.")));
+			ui.separator();
+			ui.label(RichText::new(format!("Max drawdown: {:.2}%", series.max_drawdown)).color(colors.loss));
+			if !series.benchmark.is_empty() {
+				ui.separator();
+				ui.label(format!("{}: {:+.2}%", self.benchmark, last(&series.benchmark)));
+			}
+		});
+		ui.add_space(8.);
+
+		let date_formatter = |mark: GridMark, _range: &RangeInclusive<f64>| date_of(mark.value).map_or(String::new(), format_date);
+		let label_formatter = |name: &str, point: &PlotPoint| {
+			format!("{name}\nDate: {}\n{:.2}", date_of(point.x).map_or(String::new(), format_date), point.y)
+		};
+		let link_group_id = ui.id().with("performance_link");
+		let plot = |id: &str, height: f32| {
+			Plot::new(id.to_string())
+				.link_axis(link_group_id, [true, false])
+				.link_cursor(link_group_id, Vec2b::new(true, false))
+				.legend(Legend::default())
+				.custom_x_axes(vec![AxisHints::new_x().formatter(date_formatter)])
+				.label_formatter(label_formatter)
+				.allow_scroll(false)
+				.height(height)
+		};
+
+		ui.label(RichText::new(format!("Value ({base})")).strong());
+		plot("performance_value", 260.).show(ui, |plot_ui| {
+			plot_ui.line(Line::new(PlotPoints::from(series.value.clone())).name("Value"));
+			plot_ui.line(Line::new(PlotPoints::from(series.invested.clone())).name("Net invested").style(LineStyle::dashed_loose()));
+		});
+
+		ui.label(RichText::new("Return (%)").strong());
+		plot("performance_return", 200.).show(ui, |plot_ui| {
+			plot_ui.line(Line::new(PlotPoints::from(series.twr.clone())).name("Time-weighted return"));
+			if !series.benchmark.is_empty() {
+				plot_ui.line(Line::new(PlotPoints::from(series.benchmark.clone())).name(&self.benchmark).color(Color32::GRAY));
+			}
+		});
+
+		ui.label(RichText::new("Drawdown (%)").strong());
+		plot("performance_drawdown", 150.).show(ui, |plot_ui| {
+			plot_ui.line(Line::new(PlotPoints::from(series.drawdown.clone())).name("Drawdown").color(colors.loss).fill(0.));
+		});
+	}
+}
+
+impl Default for PerformanceView {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use time::macros::date;
+
+	use super::*;
+	use crate::ledger::Transaction;
+
+	fn trade(ledger: &mut Ledger, kind: TransactionKind, date: Date, quantity: f64, price: f64) {
+		let mut transaction = Transaction::new(kind, "AAPL");
+		transaction.date = date;
+		transaction.quantity = quantity;
+		transaction.price = price;
+		ledger.add(transaction);
+	}
+
+	fn closes(closes: &[(Date, f64)]) -> HashMap<String, Vec<[f64; 2]>> {
+		let closes = closes.iter().map(|(date, close)| [timestamp_of(*date), *close]).collect();
+		HashMap::from([("AAPL".to_string(), closes)])
+	}
+
+	#[test]
+	fn cash_flows_do_not_move_the_time_weighted_return() {
+		let mut ledger = Ledger::default();
+		trade(&mut ledger, TransactionKind::Buy, date!(2024-03-04), 10., 10.);
+		trade(&mut ledger, TransactionKind::Buy, date!(2024-03-06), 10., 11.);
+		let closes = closes(&[
+			(date!(2024-03-04), 10.),
+			(date!(2024-03-05), 11.),
+			(date!(2024-03-06), 11.),
+			(date!(2024-03-07), 12.1),
+		]);
+
+		let performance = PerformanceSeries::new(&ledger, &closes, DEFAULT_BENCHMARK, &Currencies::new()).unwrap();
+		// Same as holding the first 10 shares from 10 to 12.1
+		assert!((performance.twr_total - 21.).abs() < 1e-9);
+		assert_eq!(performance.value.last().map(|point| point[1]), Some(242.));
+	}
+
+	#[test]
+	fn irr_of_known_cash_flows() {
+		let cash_flows = [(date!(2023-01-01), -100.), (date!(2024-01-01), 110.)];
+		let expected = 1.1f64.powf(365.25 / 365.) - 1.;
+		assert!((irr(&cash_flows).unwrap() - expected).abs() < 1e-6);
+	}
+
+	#[test]
+	fn irr_needs_flows_of_both_signs() {
+		let cash_flows = [(date!(2023-01-01), -100.), (date!(2023-06-01), -50.)];
+		assert_eq!(irr(&cash_flows), None);
+	}
+
+	#[test]
+	fn splits_keep_the_value_unchanged() {
+		let mut ledger = Ledger::default();
+		trade(&mut ledger, TransactionKind::Buy, date!(2024-03-04), 10., 100.);
+		trade(&mut ledger, TransactionKind::Split, date!(2024-03-06), 2., 0.);
+		// Split-adjusted like Yahoo's closes
+		let closes = closes(&[
+			(date!(2024-03-04), 50.),
+			(date!(2024-03-05), 50.),
+			(date!(2024-03-06), 50.),
+			(date!(2024-03-07), 50.),
+		]);
+
+		let performance = PerformanceSeries::new(&ledger, &closes, DEFAULT_BENCHMARK, &Currencies::new()).unwrap();
+		assert!(performance.value.iter().all(|point| point[1] == 1000.));
+		assert_eq!(performance.twr_total, 0.);
+	}
+}
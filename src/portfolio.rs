@@ -0,0 +1,290 @@
+use eframe::egui::*;
+
+use crate::{config, csv_import::ImportView, currency::Currencies, ledger::{Holding, Ledger, LedgerView}, performance::PerformanceView, quote_book::{QuoteBook, StockInfo}, tax_lots::TaxLotView};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Tab {
+	Positions,
+	Performance,
+	Transactions,
+	TaxLots,
+	Import,
+}
+
+/// A holding valued at its latest quote
+pub struct PositionValue {
+	pub last: f64,
+	pub market_value: f64,
+	pub cost_basis: f64,
+	pub unrealized_pnl: f64,
+	pub unrealized_percent: f64,
+	/// Change over the trading day, if the quote has a basis for it
+	pub day_pnl: Option<f64>,
+}
+
+impl PositionValue {
+	pub fn new(holding: &Holding, stock: &StockInfo) -> Option<Self> {
+		// Nothing to price until the first quote arrived
+		stock.metadata.as_ref()?;
+		let market_value = holding.quantity * stock.price;
+		let cost_basis = holding.quantity * holding.average_cost;
+		let unrealized_pnl = market_value - cost_basis;
+
+		Some(Self {
+			last: stock.price,
+			market_value,
+			cost_basis,
+			unrealized_pnl,
+			unrealized_percent: if cost_basis != 0. { unrealized_pnl / cost_basis.abs() * 100. } else { 0. },
+			day_pnl: stock.change_basis().map(|basis| holding.quantity * (stock.price - basis)),
+		})
+	}
+}
+
+/// Holdings derived from the transaction ledger, with their market value and
+/// P&L priced from the shared quote book
+pub struct PortfolioView {
+	ledger: Ledger,
+	/// Replayed from the ledger whenever it changes
+	holdings: Vec<Holding>,
+	tab: Tab,
+	performance_view: PerformanceView,
+	ledger_view: LedgerView,
+	tax_lot_view: TaxLotView,
+	import_view: ImportView,
+}
+
+impl PortfolioView {
+	pub fn new() -> Self {
+		let ledger = Ledger::load();
+		Self {
+			holdings: ledger.holdings(),
+			ledger,
+			tab: Tab::Positions,
+			performance_view: PerformanceView::new(),
+			ledger_view: LedgerView::new(),
+			tax_lot_view: TaxLotView::new(),
+			import_view: ImportView::new(),
+		}
+	}
+
+	/// Symbols whose quotes the portfolio needs: the open positions, and closed ones
+	/// whose currency is only known from their quote
+	pub fn symbols(&self) -> impl Iterator<Item = &String> + Clone {
+		self.holdings.iter().filter(|holding| holding.is_open() || holding.currency.is_empty()).map(|holding| &holding.symbol)
+	}
+
+	/// Currencies of all holdings, closed ones included for their realized results
+	pub fn currencies(&self) -> impl Iterator<Item = &str> {
+		self.holdings.iter().map(|holding| holding.currency.as_str()).filter(|currency| !currency.is_empty())
+	}
+
+	pub fn show(&mut self, ui: &mut Ui, quote_book: &QuoteBook, currencies: &Currencies, change_ticker: &mut Option<String>) {
+		ui.horizontal(|ui| {
+			ui.heading("Portfolio");
+			ui.add_space(16.);
+			ui.selectable_value(&mut self.tab, Tab::Positions, "Positions");
+			ui.selectable_value(&mut self.tab, Tab::Performance, "Performance");
+			ui.selectable_value(&mut self.tab, Tab::Transactions, "Transactions");
+			ui.selectable_value(&mut self.tab, Tab::TaxLots, "Tax lots");
+			ui.selectable_value(&mut self.tab, Tab::Import, "Import CSV");
+		});
+		ui.add_space(8.);
+
+		let changed = match self.tab {
+			Tab::Positions => {
+				self.show_positions(ui, quote_book, currencies, change_ticker);
+				false
+			},
+			Tab::Performance => {
+				self.performance_view.show(ui, &self.ledger, currencies);
+				false
+			},
+			Tab::Transactions => self.ledger_view.show(ui, &mut self.ledger),
+			Tab::TaxLots => {
+				self.tax_lot_view.show(ui, &self.ledger);
+				false
+			},
+			Tab::Import => match self.import_view.show(ui) {
+				Some(transactions) if !transactions.is_empty() => {
+					for transaction in transactions {
+						self.ledger.add(transaction);
+					}
+					self.ledger.sort();
+					true
+				},
+				_ => false,
+			},
+		};
+
+		if changed {
+			self.holdings = self.ledger.holdings();
+			self.performance_view.invalidate();
+			self.tax_lot_view.invalidate();
+			self.ledger.save();
+		}
+	}
+
+	fn show_positions(&self, ui: &mut Ui, quote_book: &QuoteBook, currencies: &Currencies, change_ticker: &mut Option<String>) {
+		if self.holdings.is_empty() {
+			ui.label("No transactions yet. Add them in the Transactions tab or import a broker export.");
+			return;
+		}
+
+		let open: Vec<&Holding> = self.holdings.iter().filter(|holding| holding.is_open()).collect();
+		let values: Vec<Option<PositionValue>> = open.iter()
+			.map(|holding| quote_book.get(&holding.symbol).and_then(|stock| PositionValue::new(holding, stock)))
+			.collect();
+
+		// Totals and weights only add up in one currency
+		let base = currencies.base.as_str();
+		let rates: Vec<Option<f64>> = open.iter().map(|holding| currencies.rate(currency(holding, quote_book))).collect();
+		let converted = |value: fn(&PositionValue) -> Option<f64>| -> f64 {
+			values.iter().zip(&rates).filter_map(|(position, rate)| Some(value(position.as_ref()?)? * (*rate)?)).sum()
+		};
+		let total_value = converted(|value| Some(value.market_value));
+		let total_cost = converted(|value| Some(value.cost_basis));
+		let total_day = converted(|value| value.day_pnl);
+		let missing_rates = values.iter().zip(&rates).any(|(value, rate)| value.is_some() && rate.is_none());
+
+		Grid::new("portfolio_positions").striped(true).spacing([16., 6.]).show(ui, |ui| {
+			let base_title = format!("Value ({base})");
+			let mut titles = vec!["Symbol", "Quantity", "Avg cost", "Last", "Market value", "Unrealized P&L", "", "Day P&L"];
+			if currencies.convert {
+				titles.push(&base_title);
+			}
+			titles.push("Weight");
+			for title in titles {
+				ui.label(RichText::new(title).strong());
+			}
+			ui.end_row();
+
+			for ((holding, value), rate) in open.iter().zip(&values).zip(&rates) {
+				let currency = currency(holding, quote_book);
+				if ui.add(Label::new(RichText::new(&holding.symbol).strong()).sense(Sense::click())).clicked() {
+					*change_ticker = Some(holding.symbol.clone());
+				}
+				ui.label(format!("{}", holding.quantity));
+				ui.label(format!("{:.2} {currency}", holding.average_cost));
+
+				match value {
+					Some(value) => {
+						ui.label(format!("{:.2}", value.last));
+						ui.label(format!("{:.2} {currency}", value.market_value));
+						pnl_label(ui, value.unrealized_pnl, currency);
+						percent_label(ui, value.unrealized_percent);
+						match value.day_pnl {
+							Some(day_pnl) => pnl_label(ui, day_pnl, currency),
+							None => {
+								ui.label("-");
+							},
+						}
+						let base_value = rate.map(|rate| value.market_value * rate);
+						if currencies.convert {
+							ui.label(base_value.map_or("-".to_string(), |base_value| format!("{base_value:.2} {base}")));
+						}
+						ui.label(match base_value {
+							Some(base_value) if total_value != 0. => format!("{:.1}%", base_value / total_value * 100.),
+							_ => "-".to_string(),
+						});
+					},
+					None => {
+						for _ in 0..if currencies.convert { 7 } else { 6 } {
+							ui.label("-");
+						}
+					},
+				}
+				ui.end_row();
+			}
+
+			ui.label(RichText::new("Total").strong());
+			ui.label("");
+			ui.label("");
+			ui.label("");
+			ui.label(RichText::new(format!("{total_value:.2} {base}")).strong());
+			pnl_label(ui, total_value - total_cost, base);
+			percent_label(ui, if total_cost != 0. { (total_value - total_cost) / total_cost.abs() * 100. } else { 0. });
+			pnl_label(ui, total_day, base);
+			if currencies.convert {
+				ui.label(RichText::new(format!("{total_value:.2} {base}")).strong());
+			}
+			ui.label(if total_value != 0. { "100%" } else { "-" });
+			ui.end_row();
+		});
+
+		if missing_rates {
+			ui.label(missing_rates_note(base));
+		}
+
+		ui.add_space(12.);
+		ui.separator();
+		ui.label(RichText::new("Realized").strong());
+		Grid::new("portfolio_realized").striped(true).spacing([16., 6.]).show(ui, |ui| {
+			for title in ["Symbol", "Realized P&L", "Dividends", "Fees"] {
+				ui.label(RichText::new(title).strong());
+			}
+			ui.end_row();
+
+			for holding in &self.holdings {
+				let currency = currency(holding, quote_book);
+				ui.label(&holding.symbol);
+				pnl_label(ui, holding.realized_pnl, currency);
+				pnl_label(ui, holding.dividends, currency);
+				ui.label(format!("{:.2} {currency}", holding.fees));
+				ui.end_row();
+			}
+
+			let total = |value: fn(&Holding) -> f64| -> f64 {
+				self.holdings.iter().filter_map(|holding| currencies.convert(value(holding), currency(holding, quote_book))).sum()
+			};
+			ui.label(RichText::new("Total").strong());
+			pnl_label(ui, total(|holding| holding.realized_pnl), base);
+			pnl_label(ui, total(|holding| holding.dividends), base);
+			ui.label(format!("{:.2} {base}", total(|holding| holding.fees)));
+			ui.end_row();
+		});
+
+		if self.holdings.iter().any(|holding| currencies.rate(currency(holding, quote_book)).is_none()) {
+			ui.label(missing_rates_note(base));
+		}
+	}
+}
+
+impl Default for PortfolioView {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+fn missing_rates_note(base: &str) -> RichText {
+	RichText::new(format!("Totals leave out positions without an exchange rate to {base} yet")).weak()
+}
+
+/// Transactions entered without a currency take the quote currency
+fn currency<'a>(holding: &'a Holding, quote_book: &'a QuoteBook) -> &'a str {
+	if holding.currency.is_empty() {
+		quote_book.get(&holding.symbol).map_or("", StockInfo::currency)
+	} else {
+		&holding.currency
+	}
+}
+
+fn pnl_color(value: f64) -> Option<Color32> {
+	config::get().colors.change(value)
+}
+
+fn pnl_label(ui: &mut Ui, value: f64, currency: &str) {
+	let text = RichText::new(format!("{value:+.2} {currency}"));
+	ui.label(match pnl_color(value) {
+		Some(color) => text.color(color),
+		None => text,
+	});
+}
+
+fn percent_label(ui: &mut Ui, percent: f64) {
+	let text = RichText::new(format!("{percent:+.2}%"));
+	ui.label(match pnl_color(percent) {
+		Some(color) => text.color(color),
+		None => text,
+	});
+}
@@ -0,0 +1,164 @@
+use std::{collections::HashMap, time::Instant};
+
+use eframe::egui::Context;
+use time::{Date, Duration};
+use yahoo_finance_api::{Quote, YMetaData};
+
+use crate::{change_basis::ChangeBasis, market_hours::{day_change_basis, RefreshPolicy}, yahoo_api_helper::{DailyClosesHandle, SymbolDetails, SymbolDetailsHandle, YahooFetchHandle, fetch_daily_closes, fetch_now_data, fetch_symbol_details}};
+
+/// Wait before fetching the details of a symbol again after a failed fetch
+const DETAILS_RETRY: std::time::Duration = std::time::Duration::from_secs(300);
+
+/// Latest quote of one symbol
+pub struct StockInfo {
+	pub ticker: String,
+	pub price: f64,
+	fetch_handle: YahooFetchHandle,
+	pub metadata: Option<YMetaData>,
+	next_fetch: Option<Instant>,
+	/// Quotes of the latest trading day
+	pub intraday: Vec<Quote>,
+	details_handle: SymbolDetailsHandle,
+	pub details: Option<SymbolDetails>,
+	/// When the details may be fetched again after a failed fetch
+	details_retry: Option<Instant>,
+}
+
+impl StockInfo {
+	pub fn new(ticker: &str) -> Self {
+		Self {
+			ticker: ticker.to_string(),
+			price: 0.,
+			fetch_handle: None,
+			metadata: None,
+			next_fetch: None,
+			intraday: vec![],
+			details_handle: None,
+			details: None,
+			details_retry: None,
+		}
+	}
+
+	pub fn currency(&self) -> &str {
+		self.metadata.as_ref().and_then(|metadata| metadata.currency.as_deref()).unwrap_or("")
+	}
+
+	/// Price today's change is measured from, see `day_change_basis`
+	pub fn change_basis(&self) -> Option<f64> {
+		let metadata = self.metadata.as_ref()?;
+		day_change_basis(metadata, self.intraday.iter().map(|quote| (quote.timestamp as i64, quote.open)))
+	}
+}
+
+/// Live quotes shared by everything that shows current prices (watchlist,
+/// portfolio, ...), so each symbol is only fetched once per refresh
+pub struct QuoteBook {
+	stocks: HashMap<String, StockInfo>,
+	/// Daily closes of every symbol since shortly before the change basis' anchor date
+	anchor_closes: HashMap<String, Vec<[f64; 2]>>,
+	anchor_handle: DailyClosesHandle,
+	/// Anchor date and symbols `anchor_closes` was fetched for
+	anchor_fetched: Option<(Date, Vec<String>)>,
+}
+
+impl QuoteBook {
+	pub fn new() -> Self {
+		Self {
+			stocks: HashMap::new(),
+			anchor_closes: HashMap::new(),
+			anchor_handle: None,
+			anchor_fetched: None,
+		}
+	}
+
+	pub fn get(&self, ticker: &str) -> Option<&StockInfo> {
+		self.stocks.get(ticker)
+	}
+
+	/// Fetches the quotes of `tickers` that are due according to `refresh_policy`
+	/// and polls the ones in flight. Symbols that aren't passed in anymore keep
+	/// their last quote but stop refreshing.
+	pub fn refresh<'a>(&mut self, ctx: &Context, refresh_policy: &RefreshPolicy, tickers: impl IntoIterator<Item = &'a str>) {
+		// Finished fetches wake the UI themselves, so in-flight ones are polled every frame
+		// while new ones are only started once the ticker's next refresh is due
+		let now = Instant::now();
+		let mut next_wake: Option<Instant> = None;
+		for ticker in tickers {
+			let stock = self.stocks.entry(ticker.to_string()).or_insert_with(|| StockInfo::new(ticker));
+
+			let due = stock.next_fetch.is_none_or(|next_fetch| next_fetch <= now);
+			if (due || stock.fetch_handle.is_some()) && fetch_now_data(ctx, &mut stock.fetch_handle, &stock.ticker, &mut stock.price, &mut stock.intraday, &mut stock.metadata) {
+				stock.next_fetch = Some(refresh_policy.next_refresh(stock.metadata.as_ref()));
+			}
+
+			if let (None, Some(next_fetch)) = (&stock.fetch_handle, stock.next_fetch) {
+				next_wake = Some(next_wake.map_or(next_fetch, |wake| wake.min(next_fetch)));
+			}
+		}
+
+		if let Some(next_wake) = next_wake {
+			ctx.request_repaint_after(next_wake.saturating_duration_since(now));
+		}
+	}
+
+	/// Fetches the name and 52-week range of `ticker` if they aren't known yet
+	pub fn fetch_details(&mut self, ctx: &Context, ticker: &str) {
+		if let Some(stock) = self.stocks.get_mut(ticker) {
+			let now = Instant::now();
+			if stock.details.is_none() && stock.details_retry.is_none_or(|retry| retry <= now)
+				&& fetch_symbol_details(ctx, &mut stock.details_handle, &stock.ticker, &mut stock.details) {
+				stock.details_retry = stock.details.is_none().then(|| now + DETAILS_RETRY);
+			}
+		}
+	}
+
+	/// Fetches the daily closes around `anchor` of every symbol, once per anchor date and set of symbols
+	pub fn fetch_anchor_closes(&mut self, ctx: &Context, anchor: Option<Date>) {
+		let Some(anchor) = anchor else {
+			return;
+		};
+
+		if self.anchor_handle.is_none() {
+			let mut tickers: Vec<String> = self.stocks.keys().cloned().collect();
+			tickers.sort();
+			let wanted = (anchor, tickers);
+			if self.anchor_fetched.as_ref() == Some(&wanted) {
+				return;
+			}
+			self.anchor_fetched = Some(wanted);
+		}
+
+		if let Some((anchor, tickers)) = &self.anchor_fetched {
+			// A week of margin so there's a close on or before anchor dates on weekends and holidays
+			let start = anchor.midnight().assume_utc() - Duration::days(7);
+			fetch_daily_closes(ctx, &mut self.anchor_handle, tickers, start, &mut self.anchor_closes);
+		}
+	}
+
+	/// Price the change of `stock` is measured from. Its range is `range` [unix time, price]
+	/// where a chart shows it, else the latest trading day; anchor dates are looked up in the daily closes.
+	/// The previous close always comes from the intraday opens, a chart of daily bars
+	/// would give 24h markets the open of their last bar.
+	pub fn change_reference(&self, stock: &StockInfo, basis: ChangeBasis, range: Option<&[[f64; 2]]>) -> Option<f64> {
+		let metadata = stock.metadata.as_ref()?;
+		match (basis, range) {
+			(ChangeBasis::AnchorDate(_), _) => basis.reference(metadata, self.anchor_closes.get(&stock.ticker)?.iter().map(|close| (close[0] as i64, close[1]))),
+			(ChangeBasis::RangeStart, Some(range)) => basis.reference(metadata, range.iter().map(|point| (point[0] as i64, point[1]))),
+			(ChangeBasis::PreviousClose, _) => stock.change_basis(),
+			(ChangeBasis::RangeStart, None) => basis.reference(metadata, stock.intraday.iter().map(|quote| (quote.timestamp as i64, quote.open))),
+		}
+	}
+
+	/// Re-fetches every symbol right away, e.g. after the refresh policy changed
+	pub fn reschedule(&mut self) {
+		for stock in self.stocks.values_mut() {
+			stock.next_fetch = None;
+		}
+	}
+}
+
+impl Default for QuoteBook {
+	fn default() -> Self {
+		Self::new()
+	}
+}
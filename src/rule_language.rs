@@ -0,0 +1,649 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use yahoo_finance_api::Quote;
+
+/// Bar size an expression rule is evaluated on
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BarInterval {
+	OneMinute,
+	FiveMinutes,
+	FifteenMinutes,
+	OneHour,
+	OneDay,
+}
+
+impl BarInterval {
+	pub const ALL: [BarInterval; 5] = [BarInterval::OneMinute, BarInterval::FiveMinutes, BarInterval::FifteenMinutes, BarInterval::OneHour, BarInterval::OneDay];
+
+	pub fn label(&self) -> &'static str {
+		match self {
+			BarInterval::OneMinute => "1m",
+			BarInterval::FiveMinutes => "5m",
+			BarInterval::FifteenMinutes => "15m",
+			BarInterval::OneHour => "1h",
+			BarInterval::OneDay => "1d",
+		}
+	}
+
+	/// Interval parameter of the data provider
+	pub fn api_interval(&self) -> &'static str {
+		match self {
+			BarInterval::OneHour => "60m",
+			interval => interval.label(),
+		}
+	}
+
+	/// History to fetch, enough to warm up long indicators within the provider's limits
+	pub fn api_range(&self) -> &'static str {
+		match self {
+			BarInterval::OneMinute => "5d",
+			BarInterval::FiveMinutes | BarInterval::FifteenMinutes => "1mo",
+			BarInterval::OneHour => "6mo",
+			BarInterval::OneDay => "2y",
+		}
+	}
+
+	pub fn seconds(&self) -> i64 {
+		match self {
+			BarInterval::OneMinute => 60,
+			BarInterval::FiveMinutes => 5 * 60,
+			BarInterval::FifteenMinutes => 15 * 60,
+			BarInterval::OneHour => 60 * 60,
+			BarInterval::OneDay => 24 * 60 * 60,
+		}
+	}
+}
+
+/// Where and why an expression didn't parse
+#[derive(Clone, Debug)]
+pub struct ParseError {
+	/// Character offset into the source
+	pub position: usize,
+	pub message: String,
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "column {}: {}", self.position + 1, self.message)
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Field {
+	Open,
+	High,
+	Low,
+	Close,
+	Volume,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Function {
+	Sma,
+	Ema,
+	Rsi,
+	Highest,
+	Lowest,
+	AvgVolume,
+}
+
+const FUNCTIONS: [(&str, Function); 6] = [
+	("sma", Function::Sma),
+	("ema", Function::Ema),
+	("rsi", Function::Rsi),
+	("highest", Function::Highest),
+	("lowest", Function::Lowest),
+	("avg_volume", Function::AvgVolume),
+];
+
+const FIELDS: [(&str, Field); 5] = [
+	("open", Field::Open),
+	("high", Field::High),
+	("low", Field::Low),
+	("close", Field::Close),
+	("volume", Field::Volume),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Arithmetic {
+	Add,
+	Subtract,
+	Multiply,
+	Divide,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Comparison {
+	Less,
+	LessOrEqual,
+	Greater,
+	GreaterOrEqual,
+	Equal,
+	NotEqual,
+	CrossesAbove,
+	CrossesBelow,
+}
+
+/// Parsed rule. Numbers and series evaluate to a value per bar, comparisons
+/// and logic to whether the rule holds on a bar.
+#[derive(Clone, Debug)]
+pub enum Expr {
+	Number(f64),
+	Field(Field),
+	Indicator { function: Function, period: usize, source: Box<Expr> },
+	Negate(Box<Expr>),
+	Arithmetic(Arithmetic, Box<Expr>, Box<Expr>),
+	Compare(Comparison, Box<Expr>, Box<Expr>),
+	And(Box<Expr>, Box<Expr>),
+	Or(Box<Expr>, Box<Expr>),
+	Not(Box<Expr>),
+}
+
+impl Expr {
+	fn is_condition(&self) -> bool {
+		matches!(self, Expr::Compare(..) | Expr::And(..) | Expr::Or(..) | Expr::Not(_))
+	}
+
+	/// Value on bar `index`, `None` if an indicator hasn't got enough history
+	pub fn value(&self, bars: &[Quote], index: usize) -> Option<f64> {
+		let bar = bars.get(index)?;
+		match self {
+			Expr::Number(number) => Some(*number),
+			Expr::Field(field) => Some(match field {
+				Field::Open => bar.open,
+				Field::High => bar.high,
+				Field::Low => bar.low,
+				Field::Close => bar.close,
+				Field::Volume => bar.volume as f64,
+			}),
+			Expr::Indicator { function, period, source } => indicator(*function, *period, source, bars, index),
+			Expr::Negate(expr) => expr.value(bars, index).map(|value| -value),
+			Expr::Arithmetic(op, left, right) => {
+				let (left, right) = (left.value(bars, index)?, right.value(bars, index)?);
+				match op {
+					Arithmetic::Add => Some(left + right),
+					Arithmetic::Subtract => Some(left - right),
+					Arithmetic::Multiply => Some(left * right),
+					Arithmetic::Divide if right != 0. => Some(left / right),
+					Arithmetic::Divide => None,
+				}
+			},
+			Expr::Compare(..) | Expr::And(..) | Expr::Or(..) | Expr::Not(_) => None,
+		}
+	}
+
+	/// Whether the rule holds on bar `index`, `None` without enough history
+	pub fn holds(&self, bars: &[Quote], index: usize) -> Option<bool> {
+		match self {
+			Expr::Compare(op, left, right) => {
+				let (a, b) = (left.value(bars, index)?, right.value(bars, index)?);
+				Some(match op {
+					Comparison::Less => a < b,
+					Comparison::LessOrEqual => a <= b,
+					Comparison::Greater => a > b,
+					Comparison::GreaterOrEqual => a >= b,
+					Comparison::Equal => a == b,
+					Comparison::NotEqual => a != b,
+					Comparison::CrossesAbove | Comparison::CrossesBelow => {
+						let previous = index.checked_sub(1)?;
+						let (previous_a, previous_b) = (left.value(bars, previous)?, right.value(bars, previous)?);
+						match op {
+							Comparison::CrossesAbove => previous_a <= previous_b && a > b,
+							_ => previous_a >= previous_b && a < b,
+						}
+					},
+				})
+			},
+			Expr::And(left, right) => Some(left.holds(bars, index)? && right.holds(bars, index)?),
+			Expr::Or(left, right) => Some(left.holds(bars, index)? || right.holds(bars, index)?),
+			Expr::Not(expr) => expr.holds(bars, index).map(|holds| !holds),
+			_ => None,
+		}
+	}
+}
+
+fn indicator(function: Function, period: usize, source: &Expr, bars: &[Quote], index: usize) -> Option<f64> {
+	let start = (index + 1).checked_sub(period)?;
+	let window = || (start..=index).map(|i| source.value(bars, i));
+
+	match function {
+		Function::Sma | Function::AvgVolume => Some(window().sum::<Option<f64>>()? / period as f64),
+		Function::Highest => window().try_fold(f64::NEG_INFINITY, |high, value| Some(high.max(value?))),
+		Function::Lowest => window().try_fold(f64::INFINITY, |low, value| Some(low.min(value?))),
+		// Seeded with the SMA of the first `period` values of the source, which for
+		// a nested indicator starts once that one has enough history
+		Function::Ema => {
+			let first = first_value(source, bars, index)?;
+			let seed_end = first + period;
+			if seed_end > index + 1 {
+				return None;
+			}
+			let alpha = 2. / (period as f64 + 1.);
+			let mut ema = (first..seed_end).map(|i| source.value(bars, i)).sum::<Option<f64>>()? / period as f64;
+			for i in seed_end..=index {
+				ema += alpha * (source.value(bars, i)? - ema);
+			}
+			Some(ema)
+		},
+		// Wilder's smoothing over the whole history the source has values for
+		Function::Rsi => {
+			let first = first_value(source, bars, index)?;
+			if index < first + period {
+				return None;
+			}
+			let change = |i: usize| Some(source.value(bars, i)? - source.value(bars, i - 1)?);
+			let (mut gain, mut loss) = (0., 0.);
+			for i in first + 1..=first + period {
+				let change = change(i)?;
+				gain += change.max(0.) / period as f64;
+				loss += (-change).max(0.) / period as f64;
+			}
+			for i in first + period + 1..=index {
+				let change = change(i)?;
+				gain = (gain * (period - 1) as f64 + change.max(0.)) / period as f64;
+				loss = (loss * (period - 1) as f64 + (-change).max(0.)) / period as f64;
+			}
+			Some(if loss == 0. { 100. } else { 100. - 100. / (1. + gain / loss) })
+		},
+	}
+}
+
+/// First bar up to `index` on which `source` has a value
+fn first_value(source: &Expr, bars: &[Quote], index: usize) -> Option<usize> {
+	(0..=index).find(|i| source.value(bars, *i).is_some())
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+	Number(f64),
+	Identifier(String),
+	Operator(&'static str),
+	OpenParen,
+	CloseParen,
+	Comma,
+	End,
+}
+
+impl fmt::Display for Token {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Token::Number(number) => write!(f, "`{number}`"),
+			Token::Identifier(name) => write!(f, "`{name}`"),
+			Token::Operator(op) => write!(f, "`{op}`"),
+			Token::OpenParen => write!(f, "`(`"),
+			Token::CloseParen => write!(f, "`)`"),
+			Token::Comma => write!(f, "`,`"),
+			Token::End => write!(f, "the end of the rule"),
+		}
+	}
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+	const OPERATORS: [&str; 12] = ["<=", ">=", "==", "!=", "<", ">", "+", "-", "*", "/", "&&", "||"];
+
+	let chars: Vec<char> = source.chars().collect();
+	let mut tokens = vec![];
+	let mut i = 0;
+	while i < chars.len() {
+		let c = chars[i];
+		if c.is_whitespace() {
+			i += 1;
+			continue;
+		}
+
+		let start = i;
+		if c.is_ascii_digit() || c == '.' {
+			while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+				i += 1;
+			}
+			let text: String = chars[start..i].iter().collect();
+			let number = text.parse().map_err(|_| ParseError { position: start, message: format!("`{text}` is not a number") })?;
+			tokens.push((Token::Number(number), start));
+		} else if c.is_alphabetic() || c == '_' {
+			while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+				i += 1;
+			}
+			tokens.push((Token::Identifier(chars[start..i].iter().collect::<String>().to_lowercase()), start));
+		} else if c == '(' {
+			tokens.push((Token::OpenParen, start));
+			i += 1;
+		} else if c == ')' {
+			tokens.push((Token::CloseParen, start));
+			i += 1;
+		} else if c == ',' {
+			tokens.push((Token::Comma, start));
+			i += 1;
+		} else {
+			let rest: String = chars[i..].iter().take(2).collect();
+			let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) else {
+				return Err(ParseError { position: start, message: format!("unexpected character `{c}`") });
+			};
+			tokens.push((Token::Operator(op), start));
+			i += op.chars().count();
+		}
+	}
+
+	tokens.push((Token::End, chars.len()));
+	Ok(tokens)
+}
+
+struct Parser {
+	tokens: Vec<(Token, usize)>,
+	next: usize,
+}
+
+impl Parser {
+	fn peek(&self) -> &Token {
+		&self.tokens[self.next].0
+	}
+
+	fn position(&self) -> usize {
+		self.tokens[self.next].1
+	}
+
+	fn advance(&mut self) -> Token {
+		let token = self.tokens[self.next].0.clone();
+		if token != Token::End {
+			self.next += 1;
+		}
+		token
+	}
+
+	fn error<T>(&self, message: String) -> Result<T, ParseError> {
+		Err(ParseError { position: self.position(), message })
+	}
+
+	fn is_keyword(&self, keyword: &str) -> bool {
+		matches!(self.peek(), Token::Identifier(name) if name == keyword)
+	}
+
+	fn expect_close(&mut self) -> Result<(), ParseError> {
+		if *self.peek() != Token::CloseParen {
+			return self.error(format!("expected `)`, found {}", self.peek()));
+		}
+		self.advance();
+		Ok(())
+	}
+
+	/// Parses an operand of `what` and checks that it's a condition (or a number)
+	fn operand(&mut self, condition: bool, what: &str, parse: fn(&mut Self) -> Result<Expr, ParseError>) -> Result<Expr, ParseError> {
+		let position = self.position();
+		let expr = parse(self)?;
+		match (condition, expr.is_condition()) {
+			(true, false) => Err(ParseError { position, message: format!("{what} needs a condition here, e.g. `close > 10`, not a number") }),
+			(false, true) => Err(ParseError { position, message: format!("{what} needs a number here, not a condition") }),
+			_ => Ok(expr),
+		}
+	}
+
+	fn or(&mut self) -> Result<Expr, ParseError> {
+		let mut expr = self.and()?;
+		while self.is_keyword("or") || *self.peek() == Token::Operator("||") {
+			if !expr.is_condition() {
+				return self.error("`or` needs a condition on its left, not a number".to_string());
+			}
+			self.advance();
+			expr = Expr::Or(Box::new(expr), Box::new(self.operand(true, "`or`", Self::and)?));
+		}
+		Ok(expr)
+	}
+
+	fn and(&mut self) -> Result<Expr, ParseError> {
+		let mut expr = self.not()?;
+		while self.is_keyword("and") || *self.peek() == Token::Operator("&&") {
+			if !expr.is_condition() {
+				return self.error("`and` needs a condition on its left, not a number".to_string());
+			}
+			self.advance();
+			expr = Expr::And(Box::new(expr), Box::new(self.operand(true, "`and`", Self::not)?));
+		}
+		Ok(expr)
+	}
+
+	fn not(&mut self) -> Result<Expr, ParseError> {
+		if self.is_keyword("not") {
+			self.advance();
+			return Ok(Expr::Not(Box::new(self.operand(true, "`not`", Self::not)?)));
+		}
+		self.comparison()
+	}
+
+	fn comparison(&mut self) -> Result<Expr, ParseError> {
+		let position = self.position();
+		let left = self.additive()?;
+
+		let op = match self.peek() {
+			Token::Operator("<") => Comparison::Less,
+			Token::Operator("<=") => Comparison::LessOrEqual,
+			Token::Operator(">") => Comparison::Greater,
+			Token::Operator(">=") => Comparison::GreaterOrEqual,
+			Token::Operator("==") => Comparison::Equal,
+			Token::Operator("!=") => Comparison::NotEqual,
+			Token::Identifier(name) if name == "crosses_above" => Comparison::CrossesAbove,
+			Token::Identifier(name) if name == "crosses_below" => Comparison::CrossesBelow,
+			_ => return Ok(left),
+		};
+		let what = format!("{}", self.peek());
+		if left.is_condition() {
+			return Err(ParseError { position, message: format!("{what} compares numbers, but its left side is a condition") });
+		}
+		self.advance();
+
+		let right = self.operand(false, &what, Self::additive)?;
+		Ok(Expr::Compare(op, Box::new(left), Box::new(right)))
+	}
+
+	fn additive(&mut self) -> Result<Expr, ParseError> {
+		let mut expr = self.term()?;
+		loop {
+			let op = match self.peek() {
+				Token::Operator("+") => Arithmetic::Add,
+				Token::Operator("-") => Arithmetic::Subtract,
+				_ => return Ok(expr),
+			};
+			let what = format!("{}", self.peek());
+			if expr.is_condition() {
+				return self.error(format!("{what} needs a number on its left, not a condition"));
+			}
+			self.advance();
+			expr = Expr::Arithmetic(op, Box::new(expr), Box::new(self.operand(false, &what, Self::term)?));
+		}
+	}
+
+	fn term(&mut self) -> Result<Expr, ParseError> {
+		let mut expr = self.unary()?;
+		loop {
+			let op = match self.peek() {
+				Token::Operator("*") => Arithmetic::Multiply,
+				Token::Operator("/") => Arithmetic::Divide,
+				_ => return Ok(expr),
+			};
+			let what = format!("{}", self.peek());
+			if expr.is_condition() {
+				return self.error(format!("{what} needs a number on its left, not a condition"));
+			}
+			self.advance();
+			expr = Expr::Arithmetic(op, Box::new(expr), Box::new(self.operand(false, &what, Self::unary)?));
+		}
+	}
+
+	fn unary(&mut self) -> Result<Expr, ParseError> {
+		if *self.peek() == Token::Operator("-") {
+			self.advance();
+			return Ok(Expr::Negate(Box::new(self.operand(false, "`-`", Self::unary)?)));
+		}
+		self.primary()
+	}
+
+	fn primary(&mut self) -> Result<Expr, ParseError> {
+		let position = self.position();
+		match self.advance() {
+			Token::Number(number) => Ok(Expr::Number(number)),
+			Token::OpenParen => {
+				let expr = self.or()?;
+				self.expect_close()?;
+				Ok(expr)
+			},
+			Token::Identifier(name) => {
+				if let Some((_, field)) = FIELDS.iter().find(|(field, _)| *field == name) {
+					return Ok(Expr::Field(*field));
+				}
+				let Some((_, function)) = FUNCTIONS.iter().find(|(function, _)| *function == name) else {
+					let names: Vec<&str> = FIELDS.iter().map(|(name, _)| *name).chain(FUNCTIONS.iter().map(|(name, _)| *name)).collect();
+					return Err(ParseError { position, message: format!("unknown name `{name}`, expected one of {}", names.join(", ")) });
+				};
+				self.call(&name, *function)
+			},
+			token => Err(ParseError { position, message: format!("expected a number, series or indicator, found {token}") }),
+		}
+	}
+
+	/// Arguments of an indicator: a period and, except for `avg_volume`, an optional source series
+	fn call(&mut self, name: &str, function: Function) -> Result<Expr, ParseError> {
+		if *self.peek() != Token::OpenParen {
+			return self.error(format!("`{name}` needs a period, e.g. `{name}(14)`"));
+		}
+		self.advance();
+
+		let position = self.position();
+		let period = match self.advance() {
+			Token::Number(period) if period >= 1. && period.fract() == 0. => period as usize,
+			_ => return Err(ParseError { position, message: format!("the period of `{name}` must be a whole number of bars, e.g. `{name}(14)`") }),
+		};
+		let default_source = if function == Function::AvgVolume { Field::Volume } else { Field::Close };
+
+		let source = if *self.peek() == Token::Comma && function != Function::AvgVolume {
+			self.advance();
+			self.operand(false, &format!("`{name}`"), Self::additive)?
+		} else {
+			Expr::Field(default_source)
+		};
+		self.expect_close()?;
+
+		Ok(Expr::Indicator { function, period, source: Box::new(source) })
+	}
+}
+
+/// Parses a rule such as `rsi(14) < 30 and volume > 2 * avg_volume(20)`
+pub fn parse(source: &str) -> Result<Expr, ParseError> {
+	let mut parser = Parser {
+		tokens: tokenize(source)?,
+		next: 0,
+	};
+	if *parser.peek() == Token::End {
+		return parser.error("the rule is empty".to_string());
+	}
+
+	let expr = parser.or()?;
+	if *parser.peek() != Token::End {
+		return parser.error(format!("unexpected {} after a complete rule", parser.peek()));
+	}
+	if !expr.is_condition() {
+		return Err(ParseError { position: 0, message: "the rule is a number, compare it to something, e.g. `close > sma(50)`".to_string() });
+	}
+
+	Ok(expr)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn bars(closes: &[f64]) -> Vec<Quote> {
+		closes.iter().enumerate()
+			.map(|(i, close)| Quote { timestamp: i as u64 * 60, open: *close, high: *close, low: *close, volume: 100 * (i as u64 + 1), close: *close, adjclose: *close })
+			.collect()
+	}
+
+	fn value(source: &str, closes: &[f64], index: usize) -> Option<f64> {
+		let Expr::Compare(_, left, _) = parse(&format!("{source} > 0")).unwrap() else {
+			panic!("not a comparison");
+		};
+		left.value(&bars(closes), index)
+	}
+
+	fn error_at(source: &str) -> (usize, String) {
+		let err = parse(source).unwrap_err();
+		(err.position, err.message)
+	}
+
+	#[test]
+	fn parses_rules() {
+		assert!(parse("rsi(14) < 30 and volume > 2 * avg_volume(20)").is_ok());
+		assert!(parse("close crosses_above sma(50) or not (close < ema(10, sma(20)))").is_ok());
+		assert!(parse("-close + 3 >= highest(5, high) / 2").is_ok());
+	}
+
+	#[test]
+	fn errors_point_at_the_offending_token() {
+		assert_eq!(error_at("sma(14").0, 6);
+		assert!(error_at("sma(14").1.contains("the end of the rule"));
+		assert_eq!(error_at("sma(14 > 3").0, 7);
+		assert_eq!(error_at("close > sma").0, 11);
+		assert!(error_at("close > sma").1.contains("needs a period"));
+		assert_eq!(error_at("close > sma 5").0, 12);
+		assert_eq!(error_at("close > smaa(5)").0, 8);
+		assert_eq!(error_at("sma(1.5) > 3").0, 4);
+		assert_eq!(error_at("close > 3 4").0, 10);
+		assert!(error_at("close + 1").1.contains("is a number"));
+		assert!(error_at("").1.contains("empty"));
+		assert!(error_at("close > 3 and 4").1.contains("needs a condition"));
+		assert_eq!(error_at("close # 3").0, 6);
+	}
+
+	#[test]
+	fn simple_indicators() {
+		let closes = [1., 2., 3., 4., 5., 6.];
+		assert_eq!(value("sma(3)", &closes, 1), None);
+		assert_eq!(value("sma(3)", &closes, 2), Some(2.));
+		assert_eq!(value("sma(3)", &closes, 5), Some(5.));
+		assert_eq!(value("highest(4)", &closes, 4), Some(5.));
+		assert_eq!(value("lowest(4, high)", &closes, 4), Some(2.));
+		assert_eq!(value("avg_volume(2)", &closes, 1), Some(150.));
+	}
+
+	#[test]
+	fn ema_is_seeded_with_the_sma() {
+		let closes = [2., 4., 6., 8.];
+		assert_eq!(value("ema(3)", &closes, 1), None);
+		assert_eq!(value("ema(3)", &closes, 2), Some(4.));
+		// alpha = 0.5: 4 + 0.5 * (8 - 4)
+		assert_eq!(value("ema(3)", &closes, 3), Some(6.));
+	}
+
+	#[test]
+	fn nested_sources_start_once_they_have_values() {
+		let closes: Vec<f64> = (1..=30).map(f64::from).collect();
+		// sma(3) is 2, 3, 4, ... from bar 2 on, so ema(2, sma(3)) is seeded at bar 3
+		assert_eq!(value("ema(2, sma(3))", &closes, 2), None);
+		assert_eq!(value("ema(2, sma(3))", &closes, 3), Some(2.5));
+		assert_eq!(value("ema(2, sma(3))", &closes, 4), Some(3.5));
+		assert!(value("ema(10, sma(20))", &closes, 29).is_some());
+
+		// A steadily rising source only has gains
+		assert_eq!(value("rsi(3, sma(5))", &closes, 6), None);
+		assert_eq!(value("rsi(3, sma(5))", &closes, 7), Some(100.));
+	}
+
+	#[test]
+	fn rsi_uses_wilders_smoothing() {
+		let closes = [10., 11., 10., 11., 10.];
+		// Seed over bars 1..=2: gain 1/2, loss 1/2, then bar 3 +1 and bar 4 -1
+		assert_eq!(value("rsi(2)", &closes, 2), Some(50.));
+		let (gain, loss) = ((0.5 + 1.) / 2., 0.5 / 2.);
+		let (gain, loss) = (gain / 2., (loss + 1.) / 2.);
+		let expected = 100. - 100. / (1. + gain / loss);
+		assert!((value("rsi(2)", &closes, 4).unwrap() - expected).abs() < 1e-9);
+	}
+
+	#[test]
+	fn crossings_need_the_previous_bar() {
+		let quotes = bars(&[1., 2., 3., 2., 1.]);
+		let rule = parse("close crosses_below 2.5").unwrap();
+		assert_eq!(rule.holds(&quotes, 0), None);
+		assert_eq!(rule.holds(&quotes, 2), Some(false));
+		assert_eq!(rule.holds(&quotes, 3), Some(true));
+		assert_eq!(rule.holds(&quotes, 4), Some(false));
+	}
+}
@@ -1,89 +1,424 @@
+use std::{fs, time::{Duration, Instant}};
+
 use eframe::egui::*;
+use serde::{Deserialize, Serialize};
 use yahoo_finance_api::YSearchResult;
 
-use crate::{yahoo_api_helper::{YahooFetchSearchHandle, fetch_search_ticker}, stock_graph::StockGraph};
+use crate::{yahoo_api_helper::{YahooFetchSearchHandle, fetch_search_ticker}, stock_graph::StockGraph, storage, symbol_directory::{DirectoryEntry, SymbolDirectory}};
+
+const SEARCH_FILE: &str = "search.json";
+/// How long typing has to pause before a search is sent
+const SEARCH_DEBOUNCE: Duration = Duration::from_millis(300);
+const MIN_QUERY_LENGTH: usize = 2;
+const MAX_RECENT: usize = 10;
+const MAX_LOCAL_RESULTS: usize = 10;
+
+/// Yahoo quote types that can be filtered out of the results, with their labels
+const QUOTE_TYPES: [(&str, &str); 8] = [
+	("EQUITY", "Stocks"),
+	("ETF", "ETFs"),
+	("MUTUALFUND", "Funds"),
+	("INDEX", "Indices"),
+	("CURRENCY", "Currencies"),
+	("CRYPTOCURRENCY", "Crypto"),
+	("FUTURE", "Futures"),
+	("OPTION", "Options"),
+];
+
+/// A symbol opened from the search, listed again while the search box is empty
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SearchEntry {
+	pub symbol: String,
+	pub name: String,
+}
+
+/// A search result or history entry as listed under the search box
+struct ResultRow {
+	/// Section title shown above the first favourite and the first recent search
+	heading: Option<&'static str>,
+	symbol: String,
+	name: String,
+	exchange: String,
+	quote_type: String,
+}
+
+/// Result filters and search history, saved to the data directory
+#[derive(Serialize, Deserialize, Default)]
+struct SearchSettings {
+	hidden_types: Vec<String>,
+	recent: Vec<SearchEntry>,
+	favorites: Vec<SearchEntry>,
+}
 
 pub struct SearchBar {
     search_text: String,
-	prev_search_text: String,
+	/// Trimmed text of the latest search, sent or waiting for the debounce
+	query: String,
+	/// When `query` last changed
+	edited_at: Option<Instant>,
     search_handle: YahooFetchSearchHandle,
     search_result: Option<YSearchResult>,
+	/// Result highlighted with the arrow keys, opened with Enter
+	selected: Option<usize>,
 	pub searching: bool,
-	found_result: bool
+	found_result: bool,
+	settings: SearchSettings,
+	directory: SymbolDirectory,
+	/// Directory matches of `query`, shown before Yahoo answers or when it can't be reached
+	local_results: Vec<DirectoryEntry>,
+	import_path: String,
+	import_status: Option<Result<String, String>>
 }
 
 impl SearchBar {
 	pub fn new() -> Self {
 		Self {
             search_text: "".to_string(),
-			prev_search_text: "".to_string(),
+			query: "".to_string(),
+			edited_at: None,
 			search_handle: None,
 			search_result: None,
+			selected: None,
 			searching: false,
-			found_result: false
+			found_result: false,
+			settings: SearchSettings::default(),
+			directory: SymbolDirectory::new(),
+			local_results: vec![],
+			import_path: String::new(),
+			import_status: None
+		}
+	}
+
+	/// Restores the result filters, recent searches, favourites and the symbol directory
+	pub fn load() -> Self {
+		Self {
+			settings: storage::load(SEARCH_FILE).unwrap_or_default(),
+			directory: SymbolDirectory::load(),
+			..Self::new()
+		}
+	}
+
+	fn save(&self) {
+		storage::save(SEARCH_FILE, &self.settings);
+	}
+
+	/// Charts `symbol` and moves it to the top of the recent searches
+	fn open(&mut self, stock_graph: &mut StockGraph, symbol: &str, name: &str) {
+		stock_graph.change_ticker(symbol);
+		self.searching = false;
+		self.selected = None;
+
+		self.settings.recent.retain(|entry| entry.symbol != symbol);
+		self.settings.recent.insert(0, SearchEntry { symbol: symbol.to_string(), name: name.to_string() });
+		self.settings.recent.truncate(MAX_RECENT);
+		self.save();
+	}
+
+	fn is_favorite(&self, symbol: &str) -> bool {
+		self.settings.favorites.iter().any(|entry| entry.symbol == symbol)
+	}
+
+	fn toggle_favorite(&mut self, symbol: &str, name: &str) {
+		if self.is_favorite(symbol) {
+			self.settings.favorites.retain(|entry| entry.symbol != symbol);
+		} else {
+			self.settings.favorites.push(SearchEntry { symbol: symbol.to_string(), name: name.to_string() });
 		}
+		self.save();
+	}
+
+	pub fn search_text(&self) -> &str {
+		&self.search_text
 	}
 
-	pub fn show(&mut self, ui: &mut Ui, stock_graph: &mut StockGraph) {
-        let text_edit = TextEdit::singleline(&mut self.search_text).hint_text("Enter stock name");
+	/// Fills in a previous query without searching until the field is focused
+	pub fn set_search_text(&mut self, search_text: &str) {
+		self.search_text = search_text.to_string();
+	}
+
+	pub fn show(&mut self, ui: &mut Ui, stock_graph: &mut StockGraph, add_ticker: &mut Option<String>) {
+		let id = ui.make_persistent_id("search_text");
+		let focused = ui.memory(|memory| memory.has_focus(id));
+
+		// Taken before the text field sees them, which would move the cursor instead
+		let (down, up) = if focused && self.searching {
+			ui.input_mut(|input| (input.consume_key(Modifiers::NONE, Key::ArrowDown), input.consume_key(Modifiers::NONE, Key::ArrowUp)))
+		} else {
+			(false, false)
+		};
+
+        let text_edit = TextEdit::singleline(&mut self.search_text).id(id).hint_text("Enter stock name");
 		let response = ui.add(text_edit);
-		
+
         ui.separator();
-		
+
         if response.gained_focus() {
 			self.searching = true;
 		}
 
+		// The field gives up focus on both keys, which is when they are seen here
+		let (enter, escape) = if response.lost_focus() {
+			ui.input(|input| (input.key_pressed(Key::Enter), input.key_pressed(Key::Escape)))
+		} else {
+			(false, false)
+		};
+
+		if escape {
+			self.searching = false;
+			self.selected = None;
+		}
+
+		let list_top = ui.cursor().top();
 		if self.searching {
-			if self.prev_search_text != self.search_text {
-				self.found_result = false;
+			self.update_search(ui.ctx());
+			self.filter_toggles(ui);
+
+			let rows = self.rows();
+			if down {
+				self.selected = Some(self.selected.map_or(0, |selected| selected + 1).min(rows.len().saturating_sub(1)));
+			}
+			if up {
+				self.selected = self.selected.and_then(|selected| selected.checked_sub(1));
+			}
+			self.selected = self.selected.filter(|selected| *selected < rows.len());
+
+			let mut open = None;
+			let mut toggle_favorite = None;
+
+			if enter {
+				open = rows.get(self.selected.unwrap_or(0));
 			}
 
-			if !self.found_result {
-		        fetch_search_ticker(&mut self.search_handle, &self.search_text, &mut self.search_result, &mut self.found_result);
+			if self.query.is_empty() {
+				if rows.is_empty() {
+					ui.label(RichText::new("Opened and starred symbols show up here").weak());
+				}
+			} else if self.query.chars().count() < MIN_QUERY_LENGTH {
+				ui.label(RichText::new(format!("Type at least {MIN_QUERY_LENGTH} characters to search Yahoo")).weak());
+			} else if !self.found_result {
+				ui.horizontal(|ui| {
+					ui.spinner();
+					ui.label("Searching…");
+				});
+			} else if self.search_result.is_none() {
+				let message = if rows.is_empty() { "Yahoo search unavailable" } else { "Yahoo search unavailable, showing saved symbols" };
+				ui.label(RichText::new(message).color(Color32::LIGHT_RED));
+			} else if rows.is_empty() {
+				ui.label(RichText::new("No matches").weak());
 			}
 
-	        if let Some(s_result) = &self.search_result {
-	            for result in &s_result.quotes {
-					let qtype = result.quote_type.as_str();
-					if matches!(qtype, "MUTUALFUND" | "INDEX" | "OPTION" | "CURRENCY" | "FUTURE") {
-						continue;
+	            for (index, row) in rows.iter().enumerate() {
+					if let Some(heading) = row.heading {
+						ui.label(RichText::new(heading).strong());
 					}
-						
+					let selected = self.selected == Some(index);
+					let favorite = self.is_favorite(&row.symbol);
+
 					let response = ui.scope_builder(
 						UiBuilder::new().sense(Sense::click()), |ui| {
 						    let response = ui.response();
-						    let visuals = ui.style().interact(&response);
+						    let visuals = ui.style().interact_selectable(&response, selected);
 
 						    Frame::canvas(ui.style())
-						        .fill(visuals.bg_fill.gamma_multiply(0.3))
+						        .fill(if selected { visuals.bg_fill } else { visuals.bg_fill.gamma_multiply(0.3) })
 						        .stroke(visuals.bg_stroke)
 						        .inner_margin(ui.spacing().menu_margin)
 						        .show(ui, |ui| {
 						            ui.set_width(ui.available_width());
 
-									let name_label = Label::new(RichText::new(format!("{}", result.short_name)).heading().strong()).selectable(false);
+									let name_label = Label::new(RichText::new(&row.name).heading().strong()).selectable(false);
 
-									ui.add(name_label);
 									ui.horizontal(|ui| {
-										ui.label(format!("{}", result.symbol));
-										ui.label(format!("{}", result.exchange));
-										ui.label(format!("{}", result.quote_type));
+										ui.add(name_label);
+										ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+											if ui.small_button("➕").on_hover_text("Add to watchlist").clicked() {
+												*add_ticker = Some(row.symbol.clone());
+											}
+											let (star, hint) = if favorite { ("★", "Remove from favourites") } else { ("☆", "Add to favourites") };
+											if ui.small_button(star).on_hover_text(hint).clicked() {
+												toggle_favorite = Some(row);
+											}
+										});
+									});
+									ui.horizontal(|ui| {
+										ui.label(&row.symbol);
+										ui.label(&row.exchange);
+										ui.label(&row.quote_type);
 									});
 						        });
 					}).response;
 
+					if selected && (up || down) {
+						response.scroll_to_me(None);
+					}
 					if response.clicked() {
-						stock_graph.change_ticker(&result.symbol);
+						open = Some(row);
 					}
 	            }
-	        }
+
+			if self.query.is_empty() {
+				if !self.settings.recent.is_empty() && ui.small_button("Clear recent").clicked() {
+					self.settings.recent.clear();
+					self.save();
+				}
+				self.directory_ui(ui);
+			}
+
+			if let Some(row) = toggle_favorite {
+				self.toggle_favorite(&row.symbol, &row.name);
+			}
+			if let Some(row) = open {
+				self.open(stock_graph, &row.symbol, &row.name);
+			}
 		}
 
-		if response.lost_focus() && self.search_text.is_empty() {
+		// An empty search closes when clicking away from it, but not when picking from the
+		// history below it, which takes the focus away from the text field first
+		let list = Rect::from_x_y_ranges(ui.max_rect().x_range(), list_top..=ui.cursor().top());
+		let clicked_away = ui.input(|input| input.pointer.any_click()) && !ui.rect_contains_pointer(list);
+		let left = (response.lost_focus() && !ui.rect_contains_pointer(list)) || clicked_away;
+		if self.search_text.is_empty() && !response.has_focus() && left {
 			self.searching = false;
 		}
+	}
+
+	/// Search results the filters let through, or favourites and recent searches while the box is empty
+	fn rows(&self) -> Vec<ResultRow> {
+		if !self.query.is_empty() {
+			let remote = self.search_result.iter()
+				.flat_map(|s_result| &s_result.quotes)
+				.map(|result| ResultRow {
+					heading: None,
+					symbol: result.symbol.clone(),
+					name: result.short_name.clone(),
+					exchange: result.exchange.clone(),
+					quote_type: result.quote_type.clone(),
+				});
+			let local = self.local_results.iter().map(|entry| ResultRow {
+				heading: None,
+				symbol: entry.symbol.clone(),
+				name: entry.name.clone(),
+				exchange: entry.exchange.clone(),
+				quote_type: entry.quote_type.clone(),
+			});
+
+			// Yahoo ranks its own results better, local matches fill in what it didn't find
+			let mut rows: Vec<ResultRow> = Vec::new();
+			for row in remote.chain(local) {
+				if !self.settings.hidden_types.contains(&row.quote_type) && !rows.iter().any(|known| known.symbol == row.symbol) {
+					rows.push(row);
+				}
+			}
+			return rows;
+		}
+
+		let favorites = self.settings.favorites.iter().map(|entry| (entry, "Favourites"));
+		let recent = self.settings.recent.iter()
+			.filter(|entry| !self.is_favorite(&entry.symbol))
+			.map(|entry| (entry, "Recent"));
+
+		let mut previous_heading = None;
+		favorites.chain(recent).map(|(entry, heading)| {
+			let first = previous_heading != Some(heading);
+			previous_heading = Some(heading);
+			ResultRow {
+				heading: first.then_some(heading),
+				symbol: entry.symbol.clone(),
+				name: entry.name.clone(),
+				exchange: String::new(),
+				quote_type: String::new(),
+			}
+		}).collect()
+	}
+
+	/// Size of the offline directory and importing symbol lists into it
+	fn directory_ui(&mut self, ui: &mut Ui) {
+		CollapsingHeader::new(format!("Symbol directory ({} symbols)", self.directory.len())).show(ui, |ui| {
+			ui.label(RichText::new("Searched symbols are remembered for offline use. CSV files need a symbol column, name, exchange and type are optional.").weak());
+			ui.horizontal(|ui| {
+				ui.add(TextEdit::singleline(&mut self.import_path).hint_text("/path/to/symbols.csv").desired_width(ui.available_width() - 60.));
+				if ui.add_enabled(!self.import_path.trim().is_empty(), Button::new("Import")).clicked() {
+					let path = self.import_path.trim();
+					self.import_status = Some(fs::read_to_string(path)
+						.map_err(|err| format!("Can't read '{path}': {err}"))
+						.and_then(|contents| self.directory.import_csv(&contents))
+						.map(|(count, skipped)| format!("Imported {count} symbols, skipped {skipped} rows")));
+				}
+			});
+
+			match &self.import_status {
+				Some(Ok(message)) => {
+					ui.label(RichText::new(message).color(Color32::GREEN));
+				},
+				Some(Err(message)) => {
+					ui.label(RichText::new(message).color(Color32::RED));
+				},
+				None => {},
+			}
+		});
+	}
+
+	/// One toggle per quote type, hidden types are left out of the results
+	fn filter_toggles(&mut self, ui: &mut Ui) {
+		let mut changed = false;
+		ui.horizontal_wrapped(|ui| {
+			for (quote_type, label) in QUOTE_TYPES {
+				let shown = !self.settings.hidden_types.iter().any(|hidden| hidden == quote_type);
+				if ui.selectable_label(shown, label).clicked() {
+					if shown {
+						self.settings.hidden_types.push(quote_type.to_string());
+					} else {
+						self.settings.hidden_types.retain(|hidden| hidden != quote_type);
+					}
+					changed = true;
+				}
+			}
+		});
+
+		if changed {
+			self.selected = None;
+			self.save();
+		}
+	}
+
+	/// Starts a search once typing has paused, dropping any search for older text
+	fn update_search(&mut self, ctx: &Context) {
+		let query = self.search_text.trim();
+		if query != self.query {
+			self.query = query.to_string();
+			self.edited_at = Some(Instant::now());
+			// A superseded search finishes in the background, its result is never read
+			self.search_handle = None;
+			self.search_result = None;
+			self.found_result = false;
+			self.selected = None;
+			self.local_results = self.directory.search(&self.query, MAX_LOCAL_RESULTS);
+		}
+
+		if self.query.chars().count() < MIN_QUERY_LENGTH {
+			self.search_handle = None;
+			self.search_result = None;
+			return;
+		}
+		if self.found_result {
+			return;
+		}
+
+		let wait = self.edited_at.map_or(Duration::ZERO, |edited_at| SEARCH_DEBOUNCE.saturating_sub(edited_at.elapsed()));
+		if self.search_handle.is_none() && !wait.is_zero() {
+			ctx.request_repaint_after(wait);
+			return;
+		}
+
+		self.found_result = fetch_search_ticker(ctx, &mut self.search_handle, &self.query, &mut self.search_result);
+		if let Some(search_result) = self.search_result.as_ref().filter(|_| self.found_result) {
+			self.directory.learn(search_result);
+		}
+	}
+}
 
-		self.prev_search_text = self.search_text.clone();
+impl Default for SearchBar {
+	fn default() -> Self {
+		Self::new()
 	}
 }
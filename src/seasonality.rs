@@ -0,0 +1,291 @@
+use eframe::egui::*;
+use egui_plot::*;
+use time::{Date, Month, OffsetDateTime};
+use yahoo_finance_api::YMetaData;
+
+use crate::{config, statistics::mean, yahoo_api_helper::{fetch_history, ChartSeries, YahooFetchHandle}};
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SeasonalityChart {
+	Month,
+	Weekday,
+	DayOfYear,
+}
+
+/// Average return in percent and how many returns it's over
+#[derive(Clone, Copy, Default)]
+struct Average {
+	percent: f64,
+	count: usize,
+}
+
+impl Average {
+	fn of(values: &[f64]) -> Self {
+		Self {
+			percent: mean(values).unwrap_or(0.),
+			count: values.len(),
+		}
+	}
+}
+
+/// Day of the year as in a leap year, so the same calendar day lines up across years
+fn leap_ordinal(date: Date) -> u16 {
+	if !time::util::is_leap_year(date.year()) && date.month() as u8 > Month::February as u8 {
+		date.ordinal() + 1
+	} else {
+		date.ordinal()
+	}
+}
+
+/// Return since the close before the year started, by leap-year day of the year
+struct YearLine {
+	year: i32,
+	points: Vec<[f64; 2]>,
+}
+
+/// Average returns by calendar month and weekday and the path of every year, from daily closes
+struct Seasonality {
+	months: [Average; 12],
+	weekdays: [Average; 7],
+	years: Vec<YearLine>,
+	/// Mean of the year lines by day of the year
+	average_year: Vec<[f64; 2]>,
+}
+
+impl Seasonality {
+	/// `closes` are daily [unix time, close], dates are the exchange's `gmtoffset` seconds from UTC
+	fn new(closes: &[[f64; 2]], gmtoffset: i32) -> Self {
+		let dated: Vec<(Date, f64)> = closes.iter()
+			.filter(|close| close[1] > 0.)
+			.filter_map(|close| Some((OffsetDateTime::from_unix_timestamp(close[0] as i64 + gmtoffset as i64).ok()?.date(), close[1])))
+			.collect();
+
+		let mut weekday_returns: [Vec<f64>; 7] = Default::default();
+		for pair in dated.windows(2) {
+			weekday_returns[pair[1].0.weekday().number_days_from_monday() as usize].push((pair[1].1 / pair[0].1 - 1.) * 100.);
+		}
+
+		// Month-end closes, each month's return is over the previous one's
+		let mut month_closes: Vec<(i32, Month, f64)> = vec![];
+		for (date, close) in &dated {
+			match month_closes.last_mut() {
+				Some(last) if last.0 == date.year() && last.1 == date.month() => last.2 = *close,
+				_ => month_closes.push((date.year(), date.month(), *close)),
+			}
+		}
+		let mut month_returns: [Vec<f64>; 12] = Default::default();
+		for pair in month_closes.windows(2) {
+			month_returns[pair[1].1 as usize - 1].push((pair[1].2 / pair[0].2 - 1.) * 100.);
+		}
+
+		// Years start from the last close of the year before, or their own first close
+		let mut years: Vec<YearLine> = vec![];
+		let mut base = 0.;
+		for (index, (date, close)) in dated.iter().enumerate() {
+			if years.last().is_none_or(|line| line.year != date.year()) {
+				base = if index > 0 { dated[index - 1].1 } else { *close };
+				years.push(YearLine { year: date.year(), points: vec![] });
+			}
+			if let Some(line) = years.last_mut() {
+				line.points.push([leap_ordinal(*date) as f64, (close / base - 1.) * 100.]);
+			}
+		}
+
+		let mut by_day: Vec<Vec<f64>> = vec![vec![]; 367];
+		for line in &years {
+			// Days without a close carry the last one, so every year counts towards every day
+			let mut points = line.points.iter().peekable();
+			let mut last = None;
+			let end = line.points.last().map_or(0, |point| point[0] as usize);
+			for (day, values) in by_day.iter_mut().enumerate().take(end + 1).skip(1) {
+				while let Some(point) = points.next_if(|point| point[0] as usize <= day) {
+					last = Some(point[1]);
+				}
+				if let Some(last) = last {
+					values.push(last);
+				}
+			}
+		}
+
+		Self {
+			months: month_returns.map(|returns| Average::of(&returns)),
+			weekdays: weekday_returns.map(|returns| Average::of(&returns)),
+			years,
+			average_year: by_day.iter().enumerate().filter_map(|(day, values)| Some([day as f64, mean(values)?])).collect(),
+		}
+	}
+}
+
+/// Seasonality of the current symbol over its whole daily history
+pub struct SeasonalityView {
+	chart: SeasonalityChart,
+	fetch_handle: YahooFetchHandle,
+	/// Symbol the history was fetched for
+	fetched: Option<String>,
+	closes: Vec<[f64; 2]>,
+	metadata: Option<YMetaData>,
+	seasonality: Option<Seasonality>,
+}
+
+impl SeasonalityView {
+	pub fn new() -> Self {
+		Self {
+			chart: SeasonalityChart::Month,
+			fetch_handle: None,
+			fetched: None,
+			closes: vec![],
+			metadata: None,
+			seasonality: None,
+		}
+	}
+
+	/// Fetches the daily history of `ticker` when it changed
+	fn update(&mut self, ctx: &Context, ticker: &str) {
+		if self.fetch_handle.is_none() {
+			if self.fetched.as_deref() == Some(ticker) {
+				return;
+			}
+			self.fetched = Some(ticker.to_string());
+			self.seasonality = None;
+		}
+
+		if let Some(ticker) = &self.fetched {
+			let mut volumes = vec![];
+			let series = ChartSeries { prices: &mut self.closes, volumes: &mut volumes, metadata: &mut self.metadata };
+			if fetch_history(ctx, &mut self.fetch_handle, ticker, "max", "1d", series) {
+				let gmtoffset = self.metadata.as_ref().map_or(0, |metadata| metadata.gmtoffset);
+				self.seasonality = Some(Seasonality::new(&self.closes, gmtoffset));
+			}
+		}
+	}
+
+	pub fn show(&mut self, ui: &mut Ui, ticker: &str) {
+		self.update(ui.ctx(), ticker);
+
+		ui.horizontal(|ui| {
+			ui.selectable_value(&mut self.chart, SeasonalityChart::Month, "By month");
+			ui.selectable_value(&mut self.chart, SeasonalityChart::Weekday, "By weekday");
+			ui.selectable_value(&mut self.chart, SeasonalityChart::DayOfYear, "By day of year");
+			if self.fetch_handle.is_some() {
+				ui.spinner();
+			}
+		});
+
+		let Some(seasonality) = &self.seasonality else {
+			if self.fetch_handle.is_none() {
+				ui.label(RichText::new("No daily history for this symbol").weak());
+			}
+			return;
+		};
+		if let (Some(first), Some(last)) = (seasonality.years.first(), seasonality.years.last()) {
+			ui.label(RichText::new(format!("Daily closes {}–{}", first.year, last.year)).weak());
+		}
+
+		match self.chart {
+			SeasonalityChart::Month => average_chart(ui, "seasonality_month", &MONTHS, &seasonality.months, "month"),
+			SeasonalityChart::Weekday => {
+				// Weekends only show for markets trading on them
+				let days = if seasonality.weekdays[5..].iter().any(|average| average.count > 0) { 7 } else { 5 };
+				average_chart(ui, "seasonality_weekday", &WEEKDAYS[..days], &seasonality.weekdays[..days], "day");
+			},
+			SeasonalityChart::DayOfYear => year_chart(ui, seasonality),
+		}
+	}
+}
+
+impl Default for SeasonalityView {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Bars of the average return per category, colored by sign
+fn average_chart(ui: &mut Ui, id: &str, labels: &'static [&'static str], averages: &[Average], period: &'static str) {
+	let colors = &config::get().colors;
+	let bars = averages.iter().enumerate()
+		.map(|(index, average)| {
+			let bar = Bar::new(index as f64, average.percent).width(0.7).name(labels[index]);
+			match colors.change(average.percent) {
+				Some(color) => bar.fill(color),
+				None => bar,
+			}
+		})
+		.collect();
+	let counts: Vec<usize> = averages.iter().map(|average| average.count).collect();
+
+	Plot::new(id)
+		.x_axis_formatter(move |mark, _range| {
+			let index = mark.value.round();
+			if (mark.value - index).abs() < 1e-6 && (0. ..labels.len() as f64).contains(&index) {
+				labels[index as usize].to_string()
+			} else {
+				String::new()
+			}
+		})
+		.y_axis_formatter(|mark, _range| format!("{:.1}%", mark.value))
+		.label_formatter(move |_name, point| {
+			let index = point.x.round();
+			match (0. ..labels.len() as f64).contains(&index).then_some(index as usize) {
+				Some(index) => format!("{}: {:+.2}% average over {} {period}s", labels[index], point.y, counts[index]),
+				None => String::new(),
+			}
+		})
+		.allow_scroll(false)
+		.allow_drag(false)
+		.allow_zoom(false)
+		.show(ui, |plot_ui| {
+			plot_ui.bar_chart(BarChart::new(bars).name("Average return"));
+		});
+}
+
+/// One line per year on a Jan–Dec axis with their average on top
+fn year_chart(ui: &mut Ui, seasonality: &Seasonality) {
+	let day_label = |day: f64| -> String {
+		// Days are counted as in a leap year, see `leap_ordinal`
+		match Date::from_ordinal_date(2024, day.round().clamp(1., 366.) as u16) {
+			Ok(date) => format!("{} {}", MONTHS[date.month() as usize - 1], date.day()),
+			Err(_) => String::new(),
+		}
+	};
+	let current_year = seasonality.years.last().map(|line| line.year);
+
+	Plot::new("seasonality_day_of_year")
+		.legend(Legend::default())
+		.x_axis_formatter(move |mark, _range| day_label(mark.value))
+		.y_axis_formatter(|mark, _range| format!("{:.0}%", mark.value))
+		.label_formatter(move |name, point| format!("{name}\n{}: {:+.2}%", day_label(point.x), point.y))
+		.include_x(1.)
+		.include_x(366.)
+		.allow_scroll(false)
+		.show(ui, |plot_ui| {
+			for line in &seasonality.years {
+				let mut plot_line = Line::new(PlotPoints::from(line.points.clone())).name(line.year.to_string());
+				// Past years stay in the background, the current one and the average stand out
+				if Some(line.year) != current_year {
+					plot_line = plot_line.color(Color32::from_gray(128).gamma_multiply(0.35)).width(1.);
+				} else {
+					plot_line = plot_line.width(2.);
+				}
+				plot_ui.line(plot_line);
+			}
+			plot_ui.line(Line::new(PlotPoints::from(seasonality.average_year.clone())).name("Average").width(2.5).style(LineStyle::dashed_loose()));
+		});
+}
+
+#[cfg(test)]
+mod tests {
+	use time::macros::date;
+
+	use super::*;
+
+	#[test]
+	fn days_after_february_line_up_across_years() {
+		assert_eq!(leap_ordinal(date!(2023-02-28)), 59);
+		assert_eq!(leap_ordinal(date!(2024-02-29)), 60);
+		assert_eq!(leap_ordinal(date!(2023-03-01)), leap_ordinal(date!(2024-03-01)));
+		assert_eq!(leap_ordinal(date!(2023-12-31)), 366);
+	}
+}
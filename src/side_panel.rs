@@ -1,67 +1,177 @@
-use std::time::{Instant, Duration};
-
 use eframe::egui::*;
-use yahoo_finance_api::YMetaData;
+use serde::{Deserialize, Serialize};
 
-use crate::yahoo_api_helper::{YahooFetchHandle, fetch_now_data};
+use crate::{change_basis::ChangeBasis, config, currency::Currencies, market_hours::{market_status_badge, AssetClass}, quote_book::{QuoteBook, StockInfo}, watchlist::Watchlists, watchlist_table::{TableRow, WatchlistTable}};
 
-struct StockInfo {
-	ticker: String,
-	price: f64,
-	fetch_handle: YahooFetchHandle,
-	metadata: Option<YMetaData>,
-}
+/// Table values of `stock`, in the base currency if `currencies` converts and the rate is known
+fn table_row<'a>(stock: &'a StockInfo, quote_book: &QuoteBook, currencies: &'a Currencies, change_basis: ChangeBasis) -> TableRow<'a> {
+	let (rate, currency) = match currencies.display_rate(stock) {
+		Some(rate) => (rate, currencies.base.as_str()),
+		None => (1., stock.currency()),
+	};
 
-impl StockInfo {
-	pub fn new(ticker: &str) -> Self {
-		Self {
-			ticker: ticker.to_string(),
-			price: 0.,
-			fetch_handle: None,
-			metadata: None,
-		}
+	let change = quote_book.change_reference(stock, change_basis, None)
+		.map(|start_price| ((stock.price - start_price) * rate, (stock.price - start_price) / start_price * 100.));
+
+	let has_volume = stock.metadata.as_ref().is_none_or(|metadata| AssetClass::of(metadata).has_volume());
+
+	let day_range = (!stock.intraday.is_empty()).then(|| {
+		let (low, high) = stock.intraday.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), quote| (low.min(quote.low), high.max(quote.high)));
+		(low * rate, high * rate)
+	});
+
+	TableRow {
+		symbol: &stock.ticker,
+		name: stock.details.as_ref().and_then(|details| details.name.as_deref()),
+		currency,
+		last: stock.metadata.is_some().then_some(stock.price * rate),
+		change,
+		volume: (!stock.intraday.is_empty() && has_volume).then(|| stock.intraday.iter().map(|quote| quote.volume as f64).sum()),
+		day_range,
+		year_range: stock.details.as_ref().and_then(|details| details.year_range).map(|(low, high)| (low * rate, high * rate)),
+		sparkline: stock.intraday.iter().map(|quote| quote.close).collect(),
 	}
 }
 
+/// Display settings of the side panel restored between sessions
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SidePanelState {
+	pub table_mode: bool,
+	pub table: WatchlistTable,
+}
+
 pub struct StockSidePanel {
-	stock_list: Vec<StockInfo>,
-	timer: Instant
+	watchlists: Watchlists,
+	new_list_name: Option<String>,
+	table_mode: bool,
+	table: WatchlistTable,
 }
 
 impl StockSidePanel {
 	pub fn new() -> Self {
-		let ticker_list = vec![StockInfo::new("TSLA"),
-							   StockInfo::new("GOOGL"),
-							   StockInfo::new("AMZN"),
-							   StockInfo::new("NVDA"),
-							   StockInfo::new("AMD"),
-							   StockInfo::new("INTC"),
-							   StockInfo::new("MSFT"),
-							   StockInfo::new("META"),
-							   StockInfo::new("NFLX"),
-							   StockInfo::new("PLTR"),
-							   StockInfo::new("MCD"),
-							   StockInfo::new("KO"),
-							   StockInfo::new("MA"),
-							   StockInfo::new("SPOT"),
-							   StockInfo::new("AAPL")];
-
 		Self {
-			stock_list: ticker_list,
-			timer: Instant::now()
+			watchlists: Watchlists::load(),
+			new_list_name: None,
+			table_mode: false,
+			table: WatchlistTable::new(),
+		}
+	}
+
+	/// Adds `ticker` to the selected watchlist and saves it
+	pub fn add_ticker(&mut self, ticker: &str) {
+		if self.watchlists.add_ticker(ticker) {
+			self.watchlists.save();
+		}
+	}
+
+	pub fn state(&self) -> SidePanelState {
+		SidePanelState {
+			table_mode: self.table_mode,
+			table: self.table.clone(),
+		}
+	}
+
+	pub fn restore(&mut self, state: SidePanelState) {
+		self.table_mode = state.table_mode;
+		self.table = state.table;
+	}
+
+	/// Tickers of the selected watchlist
+	pub fn tickers(&self) -> &[String] {
+		&self.watchlists.selected().tickers
+	}
+
+	fn select_list(&mut self, index: usize) {
+		self.watchlists.selected = index;
+		self.watchlists.save();
+	}
+
+	fn show_list_selector(&mut self, ui: &mut Ui) {
+		let mut select = None;
+		ui.horizontal(|ui| {
+			ComboBox::from_id_salt("watchlist_selector")
+				.selected_text(&self.watchlists.selected().name)
+				.show_ui(ui, |ui| {
+					for (index, list) in self.watchlists.lists.iter().enumerate() {
+						if ui.selectable_label(index == self.watchlists.selected, &list.name).clicked() {
+							select = Some(index);
+						}
+					}
+				});
+
+			if ui.button("➕").on_hover_text("New watchlist").clicked() {
+				self.new_list_name = Some(String::new());
+			}
+
+			let can_remove = self.watchlists.lists.len() > 1;
+			if ui.add_enabled(can_remove, Button::new("🗑")).on_hover_text("Delete this watchlist").clicked() {
+				self.watchlists.remove_selected();
+				select = Some(self.watchlists.selected);
+			}
+
+			ui.separator();
+			ui.toggle_value(&mut self.table_mode, "▦").on_hover_text("Table view");
+			if self.table_mode {
+				ui.menu_button("Columns", |ui| self.table.column_menu(ui));
+			}
+		});
+
+		if let Some(name) = &mut self.new_list_name {
+			let mut create = false;
+			let mut cancel = false;
+			ui.horizontal(|ui| {
+				let response = ui.add(TextEdit::singleline(name).hint_text("Watchlist name").desired_width(120.));
+				if name.is_empty() {
+					response.request_focus();
+				}
+				create = ui.button("Create").clicked() || (response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)));
+				cancel = ui.button("Cancel").clicked() || ui.input(|i| i.key_pressed(Key::Escape));
+			});
+
+			let name = name.trim().to_string();
+			if create && !name.is_empty() {
+				self.watchlists.add_list(&name);
+				select = Some(self.watchlists.selected);
+			}
+			if create || cancel {
+				self.new_list_name = None;
+			}
+		}
+
+		if let Some(index) = select {
+			self.select_list(index);
 		}
+
+		ui.separator();
 	}
 
-	pub fn show(&mut self, ui: &mut Ui, change_ticker: &mut Option<String>) {
-		if self.timer.elapsed() > Duration::from_secs(2) {
-			for stock in &mut self.stock_list {
-				fetch_now_data(&mut stock.fetch_handle, &stock.ticker, &mut stock.price, &mut stock.metadata);
+	pub fn show(&mut self, ui: &mut Ui, quote_book: &mut QuoteBook, currencies: &Currencies, change_basis: ChangeBasis, change_ticker: &mut Option<String>) {
+		let colors = &config::get().colors;
+		self.show_list_selector(ui);
+
+		if self.table_mode {
+			// Names and 52-week ranges are only shown by the table, fetch them once it's opened
+			for ticker in self.tickers() {
+				quote_book.fetch_details(ui.ctx(), ticker);
 			}
-			self.timer = Instant::now();
+
+			ScrollArea::both().show(ui, |ui| {
+				let rows: Vec<TableRow> = self.tickers().iter().filter_map(|ticker| quote_book.get(ticker)).map(|stock| table_row(stock, quote_book, currencies, change_basis)).collect();
+				if let Some(index) = self.table.show(ui, &rows) {
+					*change_ticker = Some(rows[index].symbol.to_string());
+				}
+			});
+			return;
 		}
 
+		let mut remove = None;
+		let mut moved = None;
 		ScrollArea::vertical().show(ui, |ui| {
-			for stock in &self.stock_list {
+			for (index, ticker) in self.tickers().iter().enumerate() {
+				let Some(stock) = quote_book.get(ticker) else {
+					continue;
+				};
+
 		        let response = ui.scope_builder(
 		            UiBuilder::new().sense(Sense::click()), |ui| {
 		                let response = ui.response();
@@ -73,45 +183,94 @@ impl StockSidePanel {
 		                    .inner_margin(ui.spacing().menu_margin)
 		                    .show(ui, |ui| {
 		                        ui.set_width(ui.available_width());
-		                        Label::new(
-		                            RichText::new(stock.ticker.clone())
-		                                .heading().strong(),
-		                        ).selectable(false).ui(ui);
+		                        ui.horizontal(|ui| {
+			                        Label::new(
+			                            RichText::new(stock.ticker.clone())
+			                                .heading().strong(),
+			                        ).selectable(false).ui(ui);
+
+									ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+										if ui.small_button("✖").on_hover_text("Remove from watchlist").clicked() {
+											remove = Some(index);
+										}
+										ui.dnd_drag_source(Id::new(("watchlist_drag", index)), index, |ui| {
+											Label::new("☰").selectable(false).ui(ui);
+										}).response.on_hover_text("Drag to reorder");
+									});
+		                        });
 
 			                    let latest_price = stock.price;
-			                    let mut start_price = None;
+			                    let start_price = quote_book.change_reference(stock, change_basis, None);
 							
 								let currency = match &stock.metadata {
 							        Some(metadata) => {
-										start_price = metadata.previous_close;
 										metadata.currency.clone().unwrap()
 									},
 							        None => "".to_string()
 							    };
-								ui.label(format!("{:.2} {}", stock.price, currency));
+								ui.horizontal(|ui| {
+									ui.label(format!("{:.2} {}", stock.price, currency));
+									if let Some(rate) = currencies.display_rate(stock).filter(|_| currency != currencies.base) {
+										ui.label(RichText::new(format!("≈ {:.2} {}", stock.price * rate, currencies.base)).weak());
+									}
+								});
 
 								if let Some(start_price) = start_price {
 				                    let p_change = (latest_price - start_price) / start_price * 100.;
 
 									ui.horizontal(|ui| {
 				                        if p_change > 0. {
-				                            ui.label(RichText::new(format!("+{:.2}%", p_change)).color(Color32::GREEN));
-				                            ui.label(RichText::new(format!("+{:.2} {}", start_price * (p_change / 100.), currency)).color(Color32::GREEN));
+				                            ui.label(RichText::new(format!("+{:.2}%", p_change)).color(colors.gain));
+				                            ui.label(RichText::new(format!("+{:.2} {}", start_price * (p_change / 100.), currency)).color(colors.gain));
 				                        } else if p_change < 0. {
-				                            ui.label(RichText::new(format!("{:.2}%", p_change)).color(Color32::RED));
-				                            ui.label(RichText::new(format!("{:.2} {}", start_price * (p_change / 100.), currency)).color(Color32::RED));
+				                            ui.label(RichText::new(format!("{:.2}%", p_change)).color(colors.loss));
+				                            ui.label(RichText::new(format!("{:.2} {}", start_price * (p_change / 100.), currency)).color(colors.loss));
 				                        } else {
 				                            ui.label(format!("+{:.2}%", p_change));
 				                        }
 									});
 								}
+
+								if let Some(metadata) = &stock.metadata {
+									market_status_badge(ui, metadata);
+								}
 		                    });
 		            }).response;
 
 		        if response.clicked() {
 					*change_ticker = Some(stock.ticker.to_string());
 		        }
+
+				// Drop indicator above or below the hovered card
+				if let (Some(pointer), Some(_)) = (ui.input(|i| i.pointer.interact_pos()), response.dnd_hover_payload::<usize>()) {
+					let rect = response.rect;
+					let (insert_at, y) = if pointer.y < rect.center().y {
+						(index, rect.top())
+					} else {
+						(index + 1, rect.bottom())
+					};
+					ui.painter().hline(rect.x_range(), y, ui.visuals().selection.stroke);
+
+					if let Some(dragged) = response.dnd_release_payload::<usize>() {
+						moved = Some((*dragged, insert_at));
+					}
+				}
 			}
 		});
+
+		if let Some(index) = remove {
+			self.watchlists.remove_ticker(index);
+			self.watchlists.save();
+		}
+		if let Some((from, to)) = moved {
+			self.watchlists.move_ticker(from, to);
+			self.watchlists.save();
+		}
+	}
+}
+
+impl Default for StockSidePanel {
+	fn default() -> Self {
+		Self::new()
 	}
 }
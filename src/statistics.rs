@@ -0,0 +1,504 @@
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use yahoo_finance_api::YMetaData;
+
+use crate::{config, ledger::format_date, market_hours::{AssetClass, DAY}, stock_graph::{fetch_range, StockGraph}, yahoo_api_helper::{ChartSeries, YahooFetchHandle}};
+
+const DEFAULT_BENCHMARK: &str = "^GSPC";
+
+/// Percent changes from each price to the next, skipping ones from non-positive prices
+pub fn returns(prices: &[[f64; 2]]) -> Vec<f64> {
+	prices.windows(2)
+		.filter(|pair| pair[0][1] > 0.)
+		.map(|pair| (pair[1][1] / pair[0][1] - 1.) * 100.)
+		.collect()
+}
+
+pub fn mean(values: &[f64]) -> Option<f64> {
+	(!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+/// Sample standard deviation
+pub fn standard_deviation(values: &[f64]) -> Option<f64> {
+	let mean = mean(values)?;
+	(values.len() > 1).then(|| (values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / (values.len() - 1) as f64).sqrt())
+}
+
+/// Skewness and excess kurtosis from the population moments, `None` for fewer than three
+/// values or flat ones
+pub fn skew_kurtosis(values: &[f64]) -> Option<(f64, f64)> {
+	let mean = mean(values)?;
+	let moment = |power: i32| values.iter().map(|value| (value - mean).powi(power)).sum::<f64>() / values.len() as f64;
+	let variance = moment(2);
+	(values.len() > 2 && variance > 0.).then(|| (moment(3) / variance.powf(1.5), moment(4) / variance.powi(2) - 3.))
+}
+
+/// Pearson correlation of two equally long series, `None` if either is flat
+pub fn correlation(a: &[f64], b: &[f64]) -> Option<f64> {
+	let (mean_a, mean_b) = (mean(a)?, mean(b)?);
+	let covariance: f64 = a.iter().zip(b).map(|(a, b)| (a - mean_a) * (b - mean_b)).sum();
+	let variance_a: f64 = a.iter().map(|a| (a - mean_a).powi(2)).sum();
+	let variance_b: f64 = b.iter().map(|b| (b - mean_b).powi(2)).sum();
+	(variance_a > 0. && variance_b > 0.).then(|| covariance / (variance_a * variance_b).sqrt())
+}
+
+/// Percent below the highest price so far, for every point
+pub fn drawdowns(prices: &[[f64; 2]]) -> Vec<[f64; 2]> {
+	let mut peak = f64::NEG_INFINITY;
+	prices.iter()
+		.map(|point| {
+			peak = peak.max(point[1]);
+			[point[0], if peak > 0. { (point[1] / peak - 1.) * 100. } else { 0. }]
+		})
+		.collect()
+}
+
+/// Last price of each day in the time zone `offset` seconds from UTC, stamped with that price's time
+pub fn daily_closes(prices: &[[f64; 2]], offset: i64) -> Vec<[f64; 2]> {
+	let mut closes: Vec<[f64; 2]> = vec![];
+	for point in prices {
+		let day = (point[0] as i64 + offset).div_euclid(DAY);
+		match closes.last_mut() {
+			Some(last) if (last[0] as i64 + offset).div_euclid(DAY) == day => *last = *point,
+			_ => closes.push(*point),
+		}
+	}
+	closes
+}
+
+/// Returns of `prices` and `benchmark` over the same periods: bars are matched by timestamp,
+/// or by day for daily and longer bars whose timestamps differ between exchanges
+pub fn paired_returns(prices: &[[f64; 2]], benchmark: &[[f64; 2]], intraday: bool) -> (Vec<f64>, Vec<f64>) {
+	let key = |timestamp: f64| if intraday { timestamp as i64 } else { (timestamp as i64).div_euclid(DAY) };
+
+	let mut pairs: Vec<(f64, f64)> = vec![];
+	let mut benchmark = benchmark.iter().peekable();
+	for point in prices {
+		while benchmark.next_if(|other| key(other[0]) < key(point[0])).is_some() {}
+		if let Some(other) = benchmark.peek().filter(|other| key(other[0]) == key(point[0])) {
+			pairs.push((point[1], other[1]));
+		}
+	}
+
+	pairs.windows(2)
+		.filter(|pair| pair[0].0 > 0. && pair[0].1 > 0.)
+		.map(|pair| ((pair[1].0 / pair[0].0 - 1.) * 100., (pair[1].1 / pair[0].1 - 1.) * 100.))
+		.unzip()
+}
+
+/// Largest fall from a peak, in percent, with the times of the peak and the bottom
+pub struct Drawdown {
+	pub percent: f64,
+	pub peak: i64,
+	pub trough: i64,
+}
+
+/// Figures over a slice of the chart's bars, percentages are in percent
+pub struct RangeStatistics {
+	pub total_return: f64,
+	/// Annualized standard deviation of the bar returns
+	pub volatility: Option<f64>,
+	pub sharpe: Option<f64>,
+	pub sortino: Option<f64>,
+	pub max_drawdown: Option<Drawdown>,
+	/// Time of the day's last bar and its return over the day before
+	pub best_day: Option<(i64, f64)>,
+	pub worst_day: Option<(i64, f64)>,
+	/// Per trading day, or per bar for bars longer than a day
+	pub average_volume: Option<f64>,
+	pub beta: Option<f64>,
+	pub correlation: Option<f64>,
+}
+
+impl RangeStatistics {
+	/// `risk_free_rate` is annual in percent, `offset` the seconds from UTC days are counted in
+	pub fn new(prices: &[[f64; 2]], volumes: &[[f64; 2]], benchmark: &[[f64; 2]], risk_free_rate: f64, trading_days: f64, offset: i64) -> Option<Self> {
+		let (first, last) = (prices.first()?, prices.last()?);
+		if first[1] <= 0. {
+			return None;
+		}
+
+		let returns = returns(prices);
+		let intraday = is_intraday(prices);
+		let periods = periods_per_year(prices, returns.len(), intraday, trading_days, offset);
+
+		let annual_return = mean(&returns).map(|mean| mean * periods);
+		let volatility = standard_deviation(&returns).map(|deviation| deviation * periods.sqrt());
+		let sharpe = annual_return.zip(volatility).filter(|(_, volatility)| *volatility > 0.)
+			.map(|(annual_return, volatility)| (annual_return - risk_free_rate) / volatility);
+
+		// Only the returns below the risk-free rate count as risk
+		let period_rate = risk_free_rate / periods;
+		let downside = (!returns.is_empty()).then(|| {
+			(returns.iter().map(|value| (value - period_rate).min(0.).powi(2)).sum::<f64>() / returns.len() as f64).sqrt() * periods.sqrt()
+		});
+		let sortino = annual_return.zip(downside).filter(|(_, downside)| *downside > 0.)
+			.map(|(annual_return, downside)| (annual_return - risk_free_rate) / downside);
+
+		let day_returns: Vec<(i64, f64)> = daily_closes(prices, offset).windows(2)
+			.filter(|pair| pair[0][1] > 0.)
+			.map(|pair| (pair[1][0] as i64, (pair[1][1] / pair[0][1] - 1.) * 100.))
+			.collect();
+
+		let volumes: Vec<[f64; 2]> = if intraday {
+			// Summed per day through the same grouping as the closes
+			let mut days: Vec<[f64; 2]> = vec![];
+			for point in volumes {
+				let day = (point[0] as i64 + offset).div_euclid(DAY);
+				match days.last_mut() {
+					Some(last) if last[0] as i64 == day => last[1] += point[1],
+					_ => days.push([day as f64, point[1]]),
+				}
+			}
+			days
+		} else {
+			volumes.to_vec()
+		};
+
+		let (stock_returns, benchmark_returns) = paired_returns(prices, benchmark, intraday);
+		let beta = standard_deviation(&benchmark_returns).filter(|deviation| *deviation > 0.).and_then(|benchmark_deviation| {
+			Some(correlation(&stock_returns, &benchmark_returns)? * standard_deviation(&stock_returns)? / benchmark_deviation)
+		});
+
+		Some(Self {
+			total_return: (last[1] / first[1] - 1.) * 100.,
+			volatility,
+			sharpe,
+			sortino,
+			max_drawdown: max_drawdown(prices),
+			best_day: day_returns.iter().copied().max_by(|a, b| a.1.total_cmp(&b.1)),
+			worst_day: day_returns.iter().copied().min_by(|a, b| a.1.total_cmp(&b.1)),
+			average_volume: mean(&volumes.iter().map(|volume| volume[1]).collect::<Vec<_>>()).filter(|volume| *volume > 0.),
+			beta,
+			correlation: correlation(&stock_returns, &benchmark_returns),
+		})
+	}
+}
+
+/// Whether the bars are shorter than a day, judged by their median spacing
+pub fn is_intraday(prices: &[[f64; 2]]) -> bool {
+	let mut steps: Vec<f64> = prices.windows(2).map(|pair| pair[1][0] - pair[0][0]).collect();
+	if steps.is_empty() {
+		return false;
+	}
+	let middle = steps.len() / 2;
+	*steps.select_nth_unstable_by(middle, f64::total_cmp).1 < DAY as f64
+}
+
+/// How many of the bars fit in a year. Intraday bars count per trading day, as nights and
+/// weekends have none, longer bars per calendar time as closed days have none either.
+fn periods_per_year(prices: &[[f64; 2]], count: usize, intraday: bool, trading_days: f64, offset: i64) -> f64 {
+	if intraday {
+		let days = daily_closes(prices, offset).len().max(1);
+		return count as f64 / days as f64 * trading_days;
+	}
+
+	let span = prices.last().zip(prices.first()).map_or(0., |(last, first)| last[0] - first[0]);
+	if span <= 0. {
+		return trading_days;
+	}
+	count as f64 / (span / (365.25 * DAY as f64))
+}
+
+fn max_drawdown(prices: &[[f64; 2]]) -> Option<Drawdown> {
+	let mut peak = prices.first()?;
+	let mut worst: Option<Drawdown> = None;
+	for point in prices {
+		if point[1] > peak[1] {
+			peak = point;
+		}
+		if peak[1] <= 0. {
+			continue;
+		}
+		let percent = (point[1] / peak[1] - 1.) * 100.;
+		if worst.as_ref().is_none_or(|worst| percent < worst.percent) {
+			worst = Some(Drawdown { percent, peak: peak[0] as i64, trough: point[0] as i64 });
+		}
+	}
+	worst.filter(|worst| worst.percent < 0.)
+}
+
+/// Benchmark and risk-free rate of the statistics, restored between sessions
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StatisticsSettings {
+	pub benchmark: String,
+	/// Annual, in percent
+	pub risk_free_rate: f64,
+}
+
+impl StatisticsSettings {
+	pub fn new() -> Self {
+		Self {
+			benchmark: DEFAULT_BENCHMARK.to_string(),
+			risk_free_rate: 0.,
+		}
+	}
+}
+
+impl Default for StatisticsSettings {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Key the statistics were computed for, they're only recomputed when it changes
+#[derive(PartialEq)]
+struct StatisticsKey {
+	data_version: u64,
+	visible: Option<(f64, f64)>,
+	benchmark_version: u64,
+	risk_free_rate: f64,
+}
+
+/// Collapsible statistics of the bars visible on the chart
+pub struct StatisticsPanel {
+	pub settings: StatisticsSettings,
+	benchmark_text: String,
+	benchmark_prices: Vec<[f64; 2]>,
+	benchmark_fetch: YahooFetchHandle,
+	/// Symbol, range and interval `benchmark_prices` were fetched for, and on intraday
+	/// ranges the chart data version, so the benchmark keeps up with the refreshed chart
+	benchmark_fetched: Option<(String, String, Option<String>, Option<u64>)>,
+	benchmark_version: u64,
+	key: Option<StatisticsKey>,
+	statistics: Option<RangeStatistics>,
+}
+
+impl StatisticsPanel {
+	pub fn new(settings: StatisticsSettings) -> Self {
+		Self {
+			benchmark_text: settings.benchmark.clone(),
+			settings,
+			benchmark_prices: vec![],
+			benchmark_fetch: None,
+			benchmark_fetched: None,
+			benchmark_version: 0,
+			key: None,
+			statistics: None,
+		}
+	}
+
+	/// Fetches the benchmark over the chart's range and interval whenever one of them changes
+	fn update_benchmark(&mut self, ctx: &Context, stock_graph: &StockGraph) {
+		if self.settings.benchmark.is_empty() {
+			if !self.benchmark_prices.is_empty() {
+				self.benchmark_prices.clear();
+				self.benchmark_version += 1;
+			}
+			self.benchmark_fetched = None;
+			return;
+		}
+
+		let version = is_intraday(&stock_graph.price_data).then(|| stock_graph.data_version());
+		let wanted = (self.settings.benchmark.clone(), stock_graph.data_range.clone(), stock_graph.data_interval.clone(), version);
+		if self.benchmark_fetch.is_none() {
+			if self.benchmark_fetched.as_ref() == Some(&wanted) {
+				return;
+			}
+			self.benchmark_fetched = Some(wanted);
+		}
+
+		if let Some((ticker, range, interval, _)) = &self.benchmark_fetched {
+			let (mut volumes, mut metadata) = (vec![], None);
+			let series = ChartSeries { prices: &mut self.benchmark_prices, volumes: &mut volumes, metadata: &mut metadata };
+			if fetch_range(ctx, &mut self.benchmark_fetch, ticker, range, interval.as_deref(), series) {
+				self.benchmark_version += 1;
+			}
+		}
+	}
+
+	pub fn show(&mut self, ui: &mut Ui, stock_graph: &StockGraph) {
+		let Some(metadata) = &stock_graph.metadata else {
+			return;
+		};
+
+		CollapsingHeader::new("Statistics").id_salt("range_statistics").show(ui, |ui| {
+			self.update_benchmark(ui.ctx(), stock_graph);
+
+			ui.horizontal(|ui| {
+				ui.label("Benchmark:");
+				let response = ui.add(TextEdit::singleline(&mut self.benchmark_text).desired_width(80.));
+				if response.lost_focus() {
+					self.settings.benchmark = self.benchmark_text.trim().to_uppercase();
+					self.benchmark_text = self.settings.benchmark.clone();
+				}
+				if self.benchmark_fetch.is_some() {
+					ui.spinner();
+				}
+				ui.separator();
+				ui.label("Risk-free rate:");
+				ui.add(DragValue::new(&mut self.settings.risk_free_rate).speed(0.05).range(-10.0..=50.0).suffix("%"));
+			});
+			ui.add_space(4.);
+
+			self.update_statistics(stock_graph, metadata);
+			match &self.statistics {
+				Some(statistics) => show_statistics(ui, statistics, metadata, &self.settings.benchmark),
+				None => {
+					ui.label(RichText::new("Not enough data in the visible range").weak());
+				},
+			}
+		});
+	}
+
+	fn update_statistics(&mut self, stock_graph: &StockGraph, metadata: &YMetaData) {
+		let key = StatisticsKey {
+			data_version: stock_graph.data_version(),
+			visible: stock_graph.visible_range(),
+			benchmark_version: self.benchmark_version,
+			risk_free_rate: self.settings.risk_free_rate,
+		};
+		if self.key.as_ref() == Some(&key) {
+			return;
+		}
+
+		let (prices, volumes) = stock_graph.visible_data();
+		let trading_days = AssetClass::of(metadata).trading_days_per_year();
+		let offset = config::get().timezone.offset(metadata.gmtoffset) as i64;
+		self.statistics = RangeStatistics::new(prices, volumes, &self.benchmark_prices, self.settings.risk_free_rate, trading_days, offset);
+		self.key = Some(key);
+	}
+}
+
+impl Default for StatisticsPanel {
+	fn default() -> Self {
+		Self::new(StatisticsSettings::new())
+	}
+}
+
+fn show_statistics(ui: &mut Ui, statistics: &RangeStatistics, metadata: &YMetaData, benchmark: &str) {
+	let offset = config::get().timezone.offset(metadata.gmtoffset) as i64;
+	let date = |timestamp: i64| OffsetDateTime::from_unix_timestamp(timestamp + offset).map_or("-".to_string(), |time| format_date(time.date()));
+	let percent = |ui: &mut Ui, value: Option<f64>| {
+		let Some(value) = value else {
+			ui.label("-");
+			return;
+		};
+		let text = RichText::new(format!("{value:+.2}%"));
+		ui.label(match config::get().colors.change(value) {
+			Some(color) => text.color(color),
+			None => text,
+		});
+	};
+	let ratio = |ui: &mut Ui, value: Option<f64>| {
+		ui.label(value.map_or("-".to_string(), |value| format!("{value:.2}")));
+	};
+
+	Grid::new("range_statistics_grid").striped(true).spacing([16., 4.]).show(ui, |ui| {
+		ui.label("Return");
+		percent(ui, Some(statistics.total_return));
+		ui.label("Max drawdown");
+		match &statistics.max_drawdown {
+			Some(drawdown) => {
+				ui.horizontal(|ui| {
+					percent(ui, Some(drawdown.percent));
+					ui.label(RichText::new(format!("{} → {}", date(drawdown.peak), date(drawdown.trough))).weak());
+				});
+			},
+			None => {
+				ui.label("-");
+			},
+		}
+		ui.end_row();
+
+		ui.label("Volatility (annualized)");
+		ui.label(statistics.volatility.map_or("-".to_string(), |volatility| format!("{volatility:.2}%")));
+		ui.label("Best day");
+		day_cell(ui, statistics.best_day, &date, &percent);
+		ui.end_row();
+
+		ui.label("Sharpe ratio");
+		ratio(ui, statistics.sharpe);
+		ui.label("Worst day");
+		day_cell(ui, statistics.worst_day, &date, &percent);
+		ui.end_row();
+
+		ui.label("Sortino ratio");
+		ratio(ui, statistics.sortino);
+		ui.label("Average volume");
+		ui.label(statistics.average_volume.map_or("-".to_string(), |volume| format!("{volume:.0}")))
+			.on_hover_text("Per trading day, or per bar for bars longer than a day");
+		ui.end_row();
+
+		ui.label(format!("Beta vs {benchmark}"));
+		ratio(ui, statistics.beta);
+		ui.label(format!("Correlation with {benchmark}"));
+		ratio(ui, statistics.correlation);
+		ui.end_row();
+	});
+}
+
+fn day_cell(ui: &mut Ui, day: Option<(i64, f64)>, date: &dyn Fn(i64) -> String, percent: &dyn Fn(&mut Ui, Option<f64>)) {
+	match day {
+		Some((timestamp, change)) => {
+			ui.horizontal(|ui| {
+				percent(ui, Some(change));
+				ui.label(RichText::new(date(timestamp)).weak());
+			});
+		},
+		None => {
+			ui.label("-");
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn close(a: f64, b: f64) -> bool {
+		(a - b).abs() < 1e-9
+	}
+
+	#[test]
+	fn returns_skip_non_positive_prices() {
+		let prices = [[0., 100.], [1., 110.], [2., 0.], [3., 50.], [4., 55.]];
+		let returns = returns(&prices);
+		assert_eq!(returns.len(), 3);
+		assert!(close(returns[0], 10.));
+		assert!(close(returns[1], -100.));
+		assert!(close(returns[2], 10.));
+	}
+
+	#[test]
+	fn max_drawdown_finds_the_deepest_fall() {
+		let prices = [[0., 100.], [1., 80.], [2., 120.], [3., 60.], [4., 90.]];
+		let drawdown = max_drawdown(&prices).unwrap();
+		assert!(close(drawdown.percent, -50.));
+		assert_eq!((drawdown.peak, drawdown.trough), (2, 3));
+
+		assert!(max_drawdown(&[[0., 1.], [1., 2.], [2., 3.]]).is_none());
+		assert!(max_drawdown(&[]).is_none());
+	}
+
+	#[test]
+	fn paired_returns_match_days_across_exchanges() {
+		let day = DAY as f64;
+		// The benchmark closes at other times and misses the second day
+		let prices = [[14.5 * 3600., 100.], [day + 14.5 * 3600., 110.], [2. * day + 14.5 * 3600., 99.]];
+		let benchmark = [[21. * 3600., 50.], [2. * day + 21. * 3600., 55.]];
+
+		let (stock, other) = paired_returns(&prices, &benchmark, false);
+		assert_eq!(stock.len(), 1);
+		assert!(close(stock[0], -1.));
+		assert!(close(other[0], 10.));
+
+		// Intraday bars have to share their timestamps
+		let (stock, _) = paired_returns(&prices, &benchmark, true);
+		assert!(stock.is_empty());
+	}
+
+	#[test]
+	fn skew_and_kurtosis() {
+		let (skew, kurtosis) = skew_kurtosis(&[1., 2., 3., 4., 5.]).unwrap();
+		assert!(close(skew, 0.));
+		assert!(close(kurtosis, -1.3));
+
+		let (skew, _) = skew_kurtosis(&[0., 0., 0., 10.]).unwrap();
+		assert!(skew > 0.);
+
+		assert!(skew_kurtosis(&[1., 2.]).is_none());
+		assert!(skew_kurtosis(&[3., 3., 3.]).is_none());
+	}
+}
@@ -1,10 +1,19 @@
-use std::{time::{Duration, UNIX_EPOCH}, ops::RangeInclusive};
+use std::{time::{Duration, Instant, UNIX_EPOCH}, ops::RangeInclusive};
 
 use eframe::egui::*;
 use egui_plot::*;
 use yahoo_finance_api::{time::OffsetDateTime, YMetaData};
 
-use crate::yahoo_api_helper::{YahooFetchHandle, fetch_recent_interval, fetch_history};
+use crate::{config, downsample::SeriesCache, market_hours::{AssetClass, RefreshPolicy}, yahoo_api_helper::{ChartSeries, YahooFetchHandle, fetch_recent_interval, fetch_history}};
+
+/// Fetches the bars the chart shows for `range` and `interval`, see `StockGraph::data_interval`
+pub fn fetch_range(ctx: &Context, fetch_handle: &mut YahooFetchHandle, ticker: &str, range: &str, interval: Option<&str>, series: ChartSeries) -> bool {
+	if range == "Regular" {
+		fetch_recent_interval(ctx, fetch_handle, ticker, interval.unwrap_or("1m"), series)
+	} else {
+		fetch_history(ctx, fetch_handle, ticker, range, interval.unwrap_or("1d"), series)
+	}
+}
 
 pub struct StockGraph {
     ticker: String,
@@ -13,7 +22,16 @@ pub struct StockGraph {
 	reset_plot: bool,
     fetch_handle: YahooFetchHandle,
 	pub metadata: Option<YMetaData>,
-	pub data_range: String
+	pub data_range: String,
+	/// `None` uses 1m bars for the regular session and daily bars otherwise
+	pub data_interval: Option<String>,
+	data_version: u64,
+	series_cache: SeriesCache,
+	/// Gaps in the data where the market was closed, shaded on the chart
+	closed_spans: Vec<[f64; 2]>,
+	/// x range shown after zooming or panning, `None` while the whole series is
+	visible: Option<(f64, f64)>,
+	next_fetch: Option<Instant>
 }
 
 impl StockGraph {
@@ -25,16 +43,26 @@ impl StockGraph {
 			reset_plot: false,
 		    fetch_handle: None,
 			metadata: None,
-			data_range: "Regular".to_string()
+			data_range: "Regular".to_string(),
+			data_interval: None,
+			data_version: 0,
+			series_cache: SeriesCache::new(),
+			closed_spans: vec![],
+			visible: None,
+			next_fetch: None
 		}
 	}
 
 	pub fn show(&mut self, ui: &mut Ui) {
+        let config = config::get();
+        // Times are shifted into the configured time zone before formatting
+        let offset = config.timezone.offset(self.metadata.as_ref().map_or(0, |metadata| metadata.gmtoffset)) as i64;
+
         // Axis formatting
         let time_formatter = |mark: GridMark, _range: &RangeInclusive<f64>| {
-            let timestamp = mark.value;
+            let timestamp = mark.value as i64 + offset;
 
-            let time = OffsetDateTime::from(UNIX_EPOCH + Duration::from_secs(timestamp as u64));
+            let time = OffsetDateTime::from(UNIX_EPOCH + Duration::from_secs(timestamp.max(0) as u64));
             
             format!("{:02}:{:02}:{:02}", time.hour(), time.minute(), time.second())
         };
@@ -44,7 +72,7 @@ impl StockGraph {
 
         // Cursor label formatter
         let label_fmt = |_s: &str, val: &PlotPoint| {
-            let time = OffsetDateTime::from(UNIX_EPOCH + Duration::from_secs(val.x as u64));
+            let time = OffsetDateTime::from(UNIX_EPOCH + Duration::from_secs((val.x as i64 + offset).max(0) as u64));
             format!("Time: {:02}:{:02}:{:02}\nPrice: {:.4}", time.hour(), time.minute(), time.second(), val.y)
         };
 
@@ -63,92 +91,181 @@ impl StockGraph {
 			self.reset_plot = false;
 		}
 
-		let (mut min_price, mut max_price, mut min_volume, mut max_volume) = (0., 0., 0., 0.);
-		if !self.price_data.is_empty() && !self.volume_data.is_empty() {
-			min_price = self.price_data[0][1];
-			max_price = self.price_data[0][1];
-			for price in &self.price_data {
-				if price[1] < min_price {
-					min_price = price[1];
-				}
-				if price[1] > max_price {
-					max_price = price[1];
-				}
-			}
-	
-			min_volume = self.volume_data[0][1];
-			max_volume = self.volume_data[0][1];
-			for volume in &self.volume_data {
-				if volume[1] < min_volume{
-					min_volume = volume[1];
-				}
-				if volume[1] > max_volume{
-					max_volume = volume[1];
-				}
-			}
-		}
-
 		// Price chart
         my_plot.show(ui, |plot_ui| {
-			let mut bars = vec![];
-			for volume in &self.volume_data {
-				bars.push(Bar::new(volume[0], (map_value(volume[1], min_volume, max_volume, min_price, max_price) - min_price) / 10.).base_offset(min_price));
+			// Only the visible slice is downsampled once the user has zoomed or panned,
+			// while auto-bounding the whole series is needed to fit the plot to it
+			let view = if plot_ui.auto_bounds().x {
+				None
+			} else {
+				let bounds = plot_ui.plot_bounds();
+				Some((bounds.min()[0], bounds.max()[0]))
+			};
+			self.visible = view;
+			let pixel_width = plot_ui.response().rect.width();
+			self.series_cache.update(&self.price_data, &self.volume_data, self.data_version, view, pixel_width);
+
+			let cache = &self.series_cache;
+			for span in &self.closed_spans {
+				let corners = vec![[span[0], cache.min_price], [span[1], cache.min_price], [span[1], cache.max_price], [span[0], cache.max_price]];
+				plot_ui.polygon(Polygon::new(PlotPoints::from(corners)).fill_color(Color32::from_gray(128).gamma_multiply(0.12)).stroke(Stroke::NONE).allow_hover(false));
+			}
+
+			// Indices and FX pairs report no volume, which would only draw empty bars
+			let has_volume = self.metadata.as_ref().is_none_or(|metadata| AssetClass::of(metadata).has_volume());
+			if has_volume && cache.max_volume > 0. {
+				let bars = cache.volume_points.iter()
+					.map(|volume| Bar::new(volume[0], (map_value(volume[1], cache.min_volume, cache.max_volume, cache.min_price, cache.max_price) - cache.min_price) / 10.).base_offset(cache.min_price))
+					.collect();
+				plot_ui.bar_chart(BarChart::new(bars).color(config.colors.volume).width(cache.volume_width * 0.8).allow_hover(false).name("Volume"));
 			}
-			plot_ui.bar_chart(BarChart::new(bars).color(Color32::LIGHT_BLUE).width(20.).allow_hover(false).name("Volume"));
 			
-            plot_ui.line(Line::new(PlotPoints::from(self.price_data.clone())).name(&self.ticker));
+            let mut line = Line::new(PlotPoints::from(cache.price_points.clone())).name(&self.ticker);
+            if let Some(color) = config.colors.price {
+                line = line.color(color);
+            }
+            plot_ui.line(line);
 			// if !self.price_data.is_empty() {
 			// 	plot_ui.vline(VLine::new(self.price_data[0][0]).name("Market open"));
 			// }
         });
 
+		self.range_selector(ui);
+	}
+
+	/// Buttons switching the range of the chart and of everything built from its bars
+	pub fn range_selector(&mut self, ui: &mut Ui) {
 		ui.horizontal(|ui| {
 			ui.label("Range:");
 			if ui.button("Regular").clicked() {
-				self.data_range = "Regular".to_string();
-				self.reset_plot = true;
+				self.change_range("Regular");
 			}
 			if ui.button("1mo").clicked() {
-				self.data_range = "1mo".to_string();
-				self.reset_plot = true;
+				self.change_range("1mo");
 			}
 			if ui.button("3mo").clicked() {
-				self.data_range = "3mo".to_string();
-				self.reset_plot = true;
+				self.change_range("3mo");
 			}
 			if ui.button("6mo").clicked() {
-				self.data_range = "6mo".to_string();
-				self.reset_plot = true;
+				self.change_range("6mo");
 			}
 			if ui.button("1y").clicked() {
-				self.data_range = "1y".to_string();
-				self.reset_plot = true;
+				self.change_range("1y");
 			}
 			if ui.button("ytd").clicked() {
-				self.data_range = "ytd".to_string();
-				self.reset_plot = true;
+				self.change_range("ytd");
 			}
 			if ui.button("max").clicked() {
-				self.data_range = "max".to_string();
-				self.reset_plot = true;
+				self.change_range("max");
 			}
 		});
 	}
 
+	pub fn ticker(&self) -> &str {
+		&self.ticker
+	}
+
+	/// Bar size of the data, e.g. "1m" or "1d"
+	pub fn interval(&self) -> &str {
+		match (&self.data_interval, self.data_range.as_str()) {
+			(Some(interval), _) => interval,
+			(None, "Regular") => "1m",
+			(None, _) => "1d",
+		}
+	}
+
+	/// Bumped whenever new data arrived
+	pub fn data_version(&self) -> u64 {
+		self.data_version
+	}
+
+	pub fn visible_range(&self) -> Option<(f64, f64)> {
+		self.visible
+	}
+
+	/// Price and volume bars inside the visible x range
+	pub fn visible_data(&self) -> (&[[f64; 2]], &[[f64; 2]]) {
+		let Some((start, end)) = self.visible else {
+			return (&self.price_data, &self.volume_data);
+		};
+		let slice = |points: &'_ [[f64; 2]]| -> std::ops::Range<usize> {
+			points.partition_point(|point| point[0] < start)..points.partition_point(|point| point[0] <= end)
+		};
+		(&self.price_data[slice(&self.price_data)], &self.volume_data[slice(&self.volume_data)])
+	}
+
 	pub fn change_ticker(&mut self, ticker: &str) {
 		self.ticker = ticker.to_string();
 		self.reset_plot = true;
+		self.refetch();
+	}
+
+	pub fn change_range(&mut self, range: &str) {
+		self.change_range_interval(range, None);
+	}
+
+	/// `interval` has to be one Yahoo serves for `range`, see `config::check_interval_range`
+	pub fn change_range_interval(&mut self, range: &str, interval: Option<&str>) {
+		self.data_range = range.to_string();
+		self.data_interval = interval.map(str::to_string);
+		self.reset_plot = true;
+		self.refetch();
 	}
 
-	pub fn update_data(&mut self) {
-		if self.data_range == "Regular" {
-			fetch_recent_interval(&mut self.fetch_handle, &self.ticker, &mut self.price_data, &mut self.volume_data, &mut self.metadata);
-		} else {
-			fetch_history(&mut self.fetch_handle, &self.ticker, &self.data_range, &mut self.price_data, &mut self.volume_data, &mut self.metadata);
+	/// Fetches again right away, e.g. after the refresh policy changed
+	pub fn reschedule(&mut self) {
+		self.next_fetch = None;
+	}
+
+	/// Drops any fetch in flight for the previous ticker or range and fetches right away
+	fn refetch(&mut self) {
+		self.fetch_handle = None;
+		self.next_fetch = None;
+	}
+
+	pub fn update_data(&mut self, ctx: &Context, refresh_policy: &RefreshPolicy) {
+		if self.fetch_handle.is_none() {
+			if let Some(next_fetch) = self.next_fetch {
+				let now = Instant::now();
+				if now < next_fetch {
+					ctx.request_repaint_after(next_fetch - now);
+					return;
+				}
+			}
+		}
+
+		let updated = fetch_range(ctx, &mut self.fetch_handle, &self.ticker, &self.data_range, self.data_interval.as_deref(), ChartSeries {
+			prices: &mut self.price_data,
+			volumes: &mut self.volume_data,
+			metadata: &mut self.metadata,
+		});
+
+		if updated {
+			self.data_version += 1;
+			self.closed_spans = closed_spans(&self.price_data);
+			let next_fetch = refresh_policy.next_refresh(self.metadata.as_ref());
+			ctx.request_repaint_after(next_fetch.saturating_duration_since(Instant::now()));
+			self.next_fetch = Some(next_fetch);
 		}
 	}
 }
 
+/// Gaps between points much longer than their usual spacing, i.e. nights, weekends and
+/// breaks the market was closed for. Markets trading around the clock have none.
+fn closed_spans(points: &[[f64; 2]]) -> Vec<[f64; 2]> {
+	let mut steps: Vec<f64> = points.windows(2).map(|pair| pair[1][0] - pair[0][0]).filter(|step| *step > 0.).collect();
+	if steps.is_empty() {
+		return vec![];
+	}
+	let middle = steps.len() / 2;
+	let typical_step = *steps.select_nth_unstable_by(middle, f64::total_cmp).1;
+
+	points.windows(2)
+		.filter(|pair| pair[1][0] - pair[0][0] > typical_step * 5.)
+		.map(|pair| [pair[0][0], pair[1][0]])
+		.collect()
+}
+
 pub fn map_value(value: f64, begin: f64, end: f64, new_begin: f64, new_end: f64) -> f64 {
     new_begin + (new_end - new_begin) * ((value - begin) / (end - begin))
 }
@@ -0,0 +1,39 @@
+use std::{fs, path::PathBuf};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+pub const APP_NAME: &str = "Stonitor";
+
+/// Path of `file_name` in the app's data directory (`~/.local/share/stonitor` on Linux)
+pub fn data_path(file_name: &str) -> Option<PathBuf> {
+	eframe::storage_dir(APP_NAME).map(|dir| dir.join(file_name))
+}
+
+/// Reads a JSON file from the data directory, `None` if it doesn't exist or can't be parsed
+pub fn load<T: DeserializeOwned>(file_name: &str) -> Option<T> {
+	let path = data_path(file_name)?;
+	let contents = fs::read_to_string(&path).ok()?;
+
+	match serde_json::from_str(&contents) {
+		Ok(value) => Some(value),
+		Err(err) => {
+			eprintln!("Error reading '{}': {err}", path.display());
+			None
+		},
+	}
+}
+
+/// Writes `value` as JSON to the data directory
+pub fn save<T: Serialize>(file_name: &str, value: &T) {
+	let Some(path) = data_path(file_name) else {
+		eprintln!("Error saving '{file_name}': no data directory");
+		return;
+	};
+
+	let result = path.parent().map_or(Ok(()), fs::create_dir_all)
+		.and_then(|_| fs::write(&path, serde_json::to_string_pretty(value).unwrap_or_default()));
+
+	if let Err(err) = result {
+		eprintln!("Error saving '{}': {err}", path.display());
+	}
+}
@@ -0,0 +1,261 @@
+use std::cmp::Reverse;
+
+use serde::{Deserialize, Serialize};
+use yahoo_finance_api::YSearchResult;
+
+use crate::storage;
+
+const DIRECTORY_FILE: &str = "symbols.json";
+
+/// Header names accepted for each column of an imported directory
+const SYMBOL_HEADERS: [&str; 3] = ["symbol", "ticker", "act symbol"];
+const NAME_HEADERS: [&str; 5] = ["name", "security name", "company name", "description", "shortname"];
+const EXCHANGE_HEADERS: [&str; 3] = ["exchange", "market", "listing exchange"];
+const TYPE_HEADERS: [&str; 4] = ["type", "quote type", "quotetype", "asset type"];
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DirectoryEntry {
+	pub symbol: String,
+	pub name: String,
+	pub exchange: String,
+	/// Yahoo quote type such as "EQUITY" or "ETF", empty if unknown
+	pub quote_type: String,
+}
+
+/// Symbols known without asking Yahoo, from imported lists and past search results
+#[derive(Serialize, Deserialize, Default)]
+pub struct SymbolDirectory {
+	/// Sorted by symbol
+	entries: Vec<DirectoryEntry>,
+}
+
+impl SymbolDirectory {
+	pub fn new() -> Self {
+		Self {
+			entries: vec![],
+		}
+	}
+
+	pub fn load() -> Self {
+		let mut directory: Self = storage::load(DIRECTORY_FILE).unwrap_or_default();
+		directory.entries.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+		directory
+	}
+
+	fn save(&self) {
+		storage::save(DIRECTORY_FILE, self);
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	/// Adds `entry` or updates the known one with its non-empty fields, returns whether anything changed
+	fn upsert(&mut self, entry: DirectoryEntry) -> bool {
+		match self.entries.binary_search_by(|known| known.symbol.cmp(&entry.symbol)) {
+			Ok(index) => {
+				let known = &mut self.entries[index];
+				let mut changed = false;
+				for (field, value) in [(&mut known.name, entry.name), (&mut known.exchange, entry.exchange), (&mut known.quote_type, entry.quote_type)] {
+					if !value.is_empty() && *field != value {
+						*field = value;
+						changed = true;
+					}
+				}
+				changed
+			},
+			Err(index) => {
+				self.entries.insert(index, entry);
+				true
+			},
+		}
+	}
+
+	/// Remembers the symbols of a Yahoo search so they can be found offline later
+	pub fn learn(&mut self, search_result: &YSearchResult) {
+		let mut changed = false;
+		for quote in &search_result.quotes {
+			changed |= self.upsert(DirectoryEntry {
+				symbol: quote.symbol.clone(),
+				name: quote.short_name.clone(),
+				exchange: quote.exchange.clone(),
+				quote_type: quote.quote_type.clone(),
+			});
+		}
+
+		if changed {
+			self.save();
+		}
+	}
+
+	/// Adds the symbols of a CSV file, see `read_csv`. Returns how many rows were imported and skipped.
+	pub fn import_csv(&mut self, contents: &str) -> Result<(usize, usize), String> {
+		let (entries, skipped) = read_csv(contents)?;
+		let count = entries.len();
+		for entry in entries {
+			self.upsert(entry);
+		}
+
+		self.save();
+		Ok((count, skipped))
+	}
+
+	/// Best fuzzy matches of `query` against symbols and names, best first
+	pub fn search(&self, query: &str, limit: usize) -> Vec<DirectoryEntry> {
+		let query = query.trim().to_lowercase();
+		if query.is_empty() {
+			return vec![];
+		}
+
+		let mut matches: Vec<_> = self.entries.iter()
+			.filter_map(|entry| {
+				// A symbol match beats the same kind of match in the name
+				let symbol = fuzzy_score(&query, &entry.symbol.to_lowercase()).map(|score| score + 50);
+				let name = fuzzy_score(&query, &entry.name.to_lowercase());
+				symbol.max(name).map(|score| (score, entry))
+			})
+			.collect();
+
+		matches.sort_by_key(|(score, entry)| (Reverse(*score), entry.symbol.len()));
+		matches.into_iter().take(limit).map(|(_, entry)| entry.clone()).collect()
+	}
+}
+
+/// Reads the symbols of a CSV file with a header row, separated by commas, semicolons, tabs or pipes.
+/// Only a symbol column is required, rows that can't be read are skipped and counted.
+fn read_csv(contents: &str) -> Result<(Vec<DirectoryEntry>, usize), String> {
+	let mut reader = csv::ReaderBuilder::new()
+		.delimiter(detect_delimiter(contents.lines().next().unwrap_or_default()))
+		.flexible(true)
+		.trim(csv::Trim::All)
+		.from_reader(contents.as_bytes());
+
+	let headers = reader.headers().map_err(|err| format!("Can't read the header row: {err}"))?.clone();
+	let column = |names: &[&str]| headers.iter().position(|header| names.iter().any(|name| header.eq_ignore_ascii_case(name)));
+	let symbol_column = column(&SYMBOL_HEADERS)
+		.ok_or_else(|| format!("No symbol column found, the file has: {}", headers.iter().collect::<Vec<_>>().join(", ")))?;
+	let name_column = column(&NAME_HEADERS);
+	let exchange_column = column(&EXCHANGE_HEADERS);
+	let type_column = column(&TYPE_HEADERS);
+
+	let mut entries = vec![];
+	let mut skipped = 0;
+	for record in reader.records() {
+		let Ok(record) = record else {
+			skipped += 1;
+			continue;
+		};
+		let field = |column: Option<usize>| column.and_then(|column| record.get(column)).unwrap_or_default().to_string();
+
+		let symbol = field(Some(symbol_column)).to_uppercase();
+		if symbol.is_empty() {
+			continue;
+		}
+		entries.push(DirectoryEntry {
+			symbol,
+			name: field(name_column),
+			exchange: field(exchange_column),
+			quote_type: field(type_column).to_uppercase(),
+		});
+	}
+
+	Ok((entries, skipped))
+}
+
+/// The separator that occurs most often in the header row
+fn detect_delimiter(header: &str) -> u8 {
+	[b',', b';', b'\t', b'|'].into_iter()
+		.max_by_key(|delimiter| header.bytes().filter(|byte| byte == delimiter).count())
+		.unwrap_or(b',')
+}
+
+/// How well `query` matches `text`, both lowercase: exact, prefix, word prefix and substring
+/// matches rank in that order, then the query's characters appearing in order with few gaps
+fn fuzzy_score(query: &str, text: &str) -> Option<i32> {
+	if text == query {
+		return Some(1000);
+	}
+	if text.starts_with(query) {
+		return Some(800 - text.len().min(100) as i32);
+	}
+	if text.split(|c: char| !c.is_alphanumeric()).any(|word| word.starts_with(query)) {
+		return Some(600 - text.len().min(100) as i32);
+	}
+	if let Some(position) = text.find(query) {
+		return Some(400 - position.min(100) as i32);
+	}
+
+	let mut text_chars = text.chars();
+	let mut gaps = 0;
+	for query_char in query.chars() {
+		let mut skipped = 0;
+		loop {
+			match text_chars.next() {
+				Some(text_char) if text_char == query_char => break,
+				Some(_) => skipped += 1,
+				None => return None,
+			}
+		}
+		gaps += skipped.min(10);
+	}
+
+	// Scattered matches of short queries are mostly noise
+	(query.chars().count() >= 3).then_some(200 - gaps * 5)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn entry(symbol: &str, name: &str) -> DirectoryEntry {
+		DirectoryEntry {
+			symbol: symbol.to_string(),
+			name: name.to_string(),
+			exchange: String::new(),
+			quote_type: String::new(),
+		}
+	}
+
+	#[test]
+	fn fuzzy_matches_rank_by_kind() {
+		let exact = fuzzy_score("apple", "apple");
+		let prefix = fuzzy_score("apple", "apple inc.");
+		let word_prefix = fuzzy_score("apple", "pineapple apple co");
+		let substring = fuzzy_score("apple", "pineapple");
+		let scattered = fuzzy_score("apl", "a plus");
+		assert!(exact > prefix && prefix > word_prefix && word_prefix > substring && substring > scattered);
+		assert!(scattered.is_some());
+		assert_eq!(fuzzy_score("ap", "a plus"), None);
+		assert_eq!(fuzzy_score("xyz", "apple"), None);
+	}
+
+	#[test]
+	fn search_prefers_symbol_matches() {
+		let mut directory = SymbolDirectory::new();
+		for entry in [entry("MSFT", "Microsoft Corporation"), entry("AAPL", "Apple Inc."), entry("APLE", "Apple Hospitality REIT")] {
+			directory.upsert(entry);
+		}
+
+		let symbols: Vec<_> = directory.search("aapl", 3).into_iter().map(|entry| entry.symbol).collect();
+		assert_eq!(symbols[0], "AAPL");
+		let symbols: Vec<_> = directory.search("apple", 3).into_iter().map(|entry| entry.symbol).collect();
+		assert_eq!(symbols, ["AAPL", "APLE"]);
+	}
+
+	#[test]
+	fn delimiter_is_detected_from_the_header() {
+		assert_eq!(detect_delimiter("Symbol,Name,Exchange"), b',');
+		assert_eq!(detect_delimiter("Symbol;Name;Exchange"), b';');
+		assert_eq!(detect_delimiter("Symbol\tSecurity Name"), b'\t');
+		assert_eq!(detect_delimiter("ACT Symbol|Company Name|Security Name, Inc."), b'|');
+
+		let (entries, skipped) = read_csv("ACT Symbol|Security Name|Exchange\naapl|Apple Inc.|NASDAQ\n|no symbol|NYSE\n").unwrap();
+		assert_eq!(skipped, 0);
+		assert_eq!(entries.len(), 1);
+		assert_eq!((entries[0].symbol.as_str(), entries[0].name.as_str()), ("AAPL", "Apple Inc."));
+	}
+}
@@ -0,0 +1,433 @@
+use eframe::egui::*;
+use time::{Date, Duration};
+
+use crate::{config, ledger::{format_date, Ledger, Transaction, TransactionKind, QUANTITY_EPSILON}, storage};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LotMethod {
+	Fifo,
+	Lifo,
+	HighestCost,
+	/// Sells close the buy they name first, then fall back to FIFO
+	SpecificLot,
+}
+
+impl LotMethod {
+	pub const ALL: [LotMethod; 4] = [LotMethod::Fifo, LotMethod::Lifo, LotMethod::HighestCost, LotMethod::SpecificLot];
+
+	pub fn label(&self) -> &'static str {
+		match self {
+			LotMethod::Fifo => "FIFO",
+			LotMethod::Lifo => "LIFO",
+			LotMethod::HighestCost => "Highest cost",
+			LotMethod::SpecificLot => "Specific lot",
+		}
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Term {
+	Short,
+	Long,
+}
+
+impl Term {
+	pub fn label(&self) -> &'static str {
+		match self {
+			Term::Short => "Short-term",
+			Term::Long => "Long-term",
+		}
+	}
+
+	/// Long-term once the shares were held for more than a year
+	fn of(acquired: Date, sold: Date) -> Self {
+		let anniversary = acquired.replace_year(acquired.year() + 1).unwrap_or(acquired + Duration::days(365));
+		if sold > anniversary { Term::Long } else { Term::Short }
+	}
+}
+
+/// Shares bought together, with buy fees included in their cost
+#[derive(Clone)]
+pub struct Lot {
+	/// Id of the buy transaction
+	pub id: u64,
+	pub symbol: String,
+	pub currency: String,
+	pub acquired: Date,
+	pub quantity: f64,
+	pub cost_per_share: f64,
+}
+
+/// Part of a sell matched against one lot
+pub struct RealizedGain {
+	pub symbol: String,
+	pub currency: String,
+	pub acquired: Date,
+	pub sold: Date,
+	pub quantity: f64,
+	pub proceeds: f64,
+	pub cost_basis: f64,
+	pub term: Term,
+}
+
+impl RealizedGain {
+	pub fn gain(&self) -> f64 {
+		self.proceeds - self.cost_basis
+	}
+}
+
+/// Shares a sell had no open lot for
+pub struct Oversold {
+	pub symbol: String,
+	pub sold: Date,
+	pub quantity: f64,
+}
+
+/// Open lots and realized gains from replaying the ledger with one matching method
+pub struct TaxLots {
+	pub open: Vec<Lot>,
+	pub realized: Vec<RealizedGain>,
+	pub oversold: Vec<Oversold>,
+}
+
+impl TaxLots {
+	pub fn new(ledger: &Ledger, method: LotMethod) -> Self {
+		let mut order: Vec<&Transaction> = ledger.transactions.iter().collect();
+		order.sort_by_key(|transaction| transaction.date);
+
+		let mut tax_lots = Self {
+			open: vec![],
+			realized: vec![],
+			oversold: vec![],
+		};
+		for transaction in order {
+			match transaction.kind {
+				TransactionKind::Buy if transaction.quantity > 0. => tax_lots.open.push(Lot {
+					id: transaction.id,
+					symbol: transaction.symbol.clone(),
+					currency: transaction.currency.clone(),
+					acquired: transaction.date,
+					quantity: transaction.quantity,
+					cost_per_share: transaction.price + transaction.fees / transaction.quantity,
+				}),
+				TransactionKind::Sell if transaction.quantity > 0. => tax_lots.sell(transaction, method),
+				TransactionKind::Split if transaction.quantity > 0. => {
+					for lot in tax_lots.open.iter_mut().filter(|lot| lot.symbol == transaction.symbol) {
+						lot.quantity *= transaction.quantity;
+						lot.cost_per_share /= transaction.quantity;
+					}
+				},
+				_ => {},
+			}
+		}
+
+		tax_lots
+	}
+
+	fn sell(&mut self, transaction: &Transaction, method: LotMethod) {
+		let mut candidates: Vec<usize> = (0..self.open.len()).filter(|i| self.open[*i].symbol == transaction.symbol).collect();
+		match method {
+			LotMethod::Fifo => {},
+			LotMethod::Lifo => candidates.reverse(),
+			LotMethod::HighestCost => candidates.sort_by(|a, b| self.open[*b].cost_per_share.total_cmp(&self.open[*a].cost_per_share)),
+			// Stable sort keeps FIFO order behind the named lot
+			LotMethod::SpecificLot => candidates.sort_by_key(|i| Some(self.open[*i].id) != transaction.lot_id),
+		}
+
+		// Sell fees are spread over the matched shares
+		let proceeds_per_share = transaction.price - transaction.fees / transaction.quantity;
+		let mut remaining = transaction.quantity;
+		for i in candidates {
+			if remaining <= QUANTITY_EPSILON {
+				break;
+			}
+
+			let lot = &mut self.open[i];
+			let quantity = remaining.min(lot.quantity);
+			lot.quantity -= quantity;
+			remaining -= quantity;
+
+			self.realized.push(RealizedGain {
+				symbol: lot.symbol.clone(),
+				currency: lot.currency.clone(),
+				acquired: lot.acquired,
+				sold: transaction.date,
+				quantity,
+				proceeds: quantity * proceeds_per_share,
+				cost_basis: quantity * lot.cost_per_share,
+				term: Term::of(lot.acquired, transaction.date),
+			});
+		}
+
+		// Shares sold beyond what the ledger holds have no lot to report a gain against
+		if remaining > QUANTITY_EPSILON {
+			self.oversold.push(Oversold {
+				symbol: transaction.symbol.clone(),
+				sold: transaction.date,
+				quantity: remaining,
+			});
+		}
+		self.open.retain(|lot| lot.quantity > QUANTITY_EPSILON);
+	}
+
+	/// Years with realized gains, newest first
+	pub fn years(&self) -> Vec<i32> {
+		let mut years: Vec<i32> = self.realized.iter().map(|gain| gain.sold.year()).collect();
+		years.sort_unstable_by(|a, b| b.cmp(a));
+		years.dedup();
+		years
+	}
+}
+
+/// Writes the realized gains report to `path`
+pub fn export_csv<'a>(path: &str, gains: impl IntoIterator<Item = &'a RealizedGain>) -> Result<(), String> {
+	let mut writer = csv::Writer::from_path(path).map_err(|err| err.to_string())?;
+	writer.write_record(["Symbol", "Acquired", "Sold", "Quantity", "Proceeds", "Cost basis", "Gain", "Term", "Currency"])
+		.map_err(|err| err.to_string())?;
+
+	for gain in gains {
+		writer.write_record([
+			gain.symbol.clone(),
+			format_date(gain.acquired),
+			format_date(gain.sold),
+			gain.quantity.to_string(),
+			format!("{:.2}", gain.proceeds),
+			format!("{:.2}", gain.cost_basis),
+			format!("{:.2}", gain.gain()),
+			gain.term.label().to_string(),
+			gain.currency.clone(),
+		]).map_err(|err| err.to_string())?;
+	}
+
+	writer.flush().map_err(|err| err.to_string())
+}
+
+/// Yearly realized gains report and open lots
+pub struct TaxLotView {
+	method: LotMethod,
+	/// Matched with the method it was built for, rebuilt when the ledger changes
+	tax_lots: Option<(LotMethod, TaxLots)>,
+	year: Option<i32>,
+	export_path: String,
+	status: Option<Result<String, String>>,
+}
+
+impl TaxLotView {
+	pub fn new() -> Self {
+		Self {
+			method: LotMethod::Fifo,
+			tax_lots: None,
+			year: None,
+			export_path: String::new(),
+			status: None,
+		}
+	}
+
+	/// Rematches the lots, e.g. after the ledger changed
+	pub fn invalidate(&mut self) {
+		self.tax_lots = None;
+	}
+
+	pub fn show(&mut self, ui: &mut Ui, ledger: &Ledger) {
+		if self.tax_lots.as_ref().is_none_or(|(method, _)| *method != self.method) {
+			self.tax_lots = Some((self.method, TaxLots::new(ledger, self.method)));
+		}
+		let Some((_, tax_lots)) = &self.tax_lots else {
+			return;
+		};
+		let years = tax_lots.years();
+		let year = match self.year.filter(|year| years.contains(year)).or(years.first().copied()) {
+			Some(year) => year,
+			None => time::OffsetDateTime::now_utc().year(),
+		};
+
+		ui.horizontal(|ui| {
+			ui.label("Matching:");
+			ComboBox::from_id_salt("lot_method")
+				.selected_text(self.method.label())
+				.show_ui(ui, |ui| {
+					for method in LotMethod::ALL {
+						ui.selectable_value(&mut self.method, method, method.label());
+					}
+				});
+
+			ui.label("Year:");
+			ComboBox::from_id_salt("tax_year")
+				.selected_text(year.to_string())
+				.show_ui(ui, |ui| {
+					for option in &years {
+						if ui.selectable_label(*option == year, option.to_string()).clicked() {
+							self.year = Some(*option);
+						}
+					}
+				});
+		});
+		ui.add_space(8.);
+
+		for oversold in &tax_lots.oversold {
+			ui.label(RichText::new(format!("⚠ The sell of {} {} on {} is more than the open lots hold, the excess isn't in the report",
+				oversold.quantity, oversold.symbol, format_date(oversold.sold))).color(Color32::YELLOW));
+		}
+
+		let gains: Vec<&RealizedGain> = tax_lots.realized.iter().filter(|gain| gain.sold.year() == year).collect();
+		let total = |term: Term| gains.iter().filter(|gain| gain.term == term).map(|gain| gain.gain()).sum::<f64>();
+		ui.horizontal(|ui| {
+			ui.label(RichText::new(format!("{year} realized:")).strong());
+			ui.label(format!("{}: {:+.2}", Term::Short.label(), total(Term::Short)));
+			ui.separator();
+			ui.label(format!("{}: {:+.2}", Term::Long.label(), total(Term::Long)));
+		});
+		ui.add_space(4.);
+
+		Grid::new("realized_gains").striped(true).spacing([16., 6.]).show(ui, |ui| {
+			for title in ["Symbol", "Acquired", "Sold", "Quantity", "Proceeds", "Cost basis", "Gain", "Term"] {
+				ui.label(RichText::new(title).strong());
+			}
+			ui.end_row();
+
+			for gain in &gains {
+				ui.label(&gain.symbol);
+				ui.label(format_date(gain.acquired));
+				ui.label(format_date(gain.sold));
+				ui.label(format!("{}", gain.quantity));
+				ui.label(format!("{:.2} {}", gain.proceeds, gain.currency));
+				ui.label(format!("{:.2} {}", gain.cost_basis, gain.currency));
+				let colors = config::get().colors;
+				let color = if gain.gain() >= 0. { colors.gain } else { colors.loss };
+				ui.label(RichText::new(format!("{:+.2} {}", gain.gain(), gain.currency)).color(color));
+				ui.label(gain.term.label());
+				ui.end_row();
+			}
+		});
+
+		ui.add_space(8.);
+		ui.horizontal(|ui| {
+			let default_file = format!("realized_gains_{year}.csv");
+			ui.add(TextEdit::singleline(&mut self.export_path).hint_text(format!("{default_file} in the data directory")).desired_width(400.));
+			if ui.add_enabled(!gains.is_empty(), Button::new("Export CSV")).clicked() {
+				let path = match self.export_path.trim() {
+					"" => storage::data_path(&default_file).map(|path| path.display().to_string()),
+					path => Some(path.to_string()),
+				};
+				self.status = Some(match path {
+					Some(path) => match export_csv(&path, gains.iter().copied()) {
+						Ok(()) => Ok(format!("Exported {} rows to '{path}'", gains.len())),
+						Err(err) => Err(format!("Can't export to '{path}': {err}")),
+					},
+					None => Err("No data directory, enter a file path".to_string()),
+				});
+			}
+		});
+		match &self.status {
+			Some(Ok(message)) => {
+				ui.label(RichText::new(message).color(Color32::GREEN));
+			},
+			Some(Err(message)) => {
+				ui.label(RichText::new(message).color(Color32::RED));
+			},
+			None => {},
+		}
+
+		ui.add_space(12.);
+		ui.separator();
+		ui.label(RichText::new("Open lots").strong());
+		Grid::new("open_lots").striped(true).spacing([16., 6.]).show(ui, |ui| {
+			for title in ["Symbol", "Acquired", "Quantity", "Cost / share", "Term"] {
+				ui.label(RichText::new(title).strong());
+			}
+			ui.end_row();
+
+			let today = time::OffsetDateTime::now_utc().date();
+			for lot in &tax_lots.open {
+				ui.label(&lot.symbol);
+				ui.label(format_date(lot.acquired));
+				ui.label(format!("{}", lot.quantity));
+				ui.label(format!("{:.2} {}", lot.cost_per_share, lot.currency));
+				ui.label(Term::of(lot.acquired, today).label());
+				ui.end_row();
+			}
+		});
+	}
+}
+
+impl Default for TaxLotView {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use time::macros::date;
+
+	use super::*;
+
+	fn trade(ledger: &mut Ledger, kind: TransactionKind, date: Date, quantity: f64, price: f64) -> u64 {
+		let mut transaction = Transaction::new(kind, "AAPL");
+		transaction.date = date;
+		transaction.quantity = quantity;
+		transaction.price = price;
+		ledger.add(transaction);
+		ledger.transactions.last().map_or(0, |transaction| transaction.id)
+	}
+
+	/// Buys at 10, 30 and 20, then sells 15 shares
+	fn ledger() -> (Ledger, [u64; 3], u64) {
+		let mut ledger = Ledger::default();
+		let buys = [
+			trade(&mut ledger, TransactionKind::Buy, date!(2022-01-03), 10., 10.),
+			trade(&mut ledger, TransactionKind::Buy, date!(2023-06-01), 10., 30.),
+			trade(&mut ledger, TransactionKind::Buy, date!(2023-06-01), 10., 20.),
+		];
+		let sell = trade(&mut ledger, TransactionKind::Sell, date!(2023-09-01), 15., 40.);
+		(ledger, buys, sell)
+	}
+
+	fn matched(tax_lots: &TaxLots) -> Vec<(f64, f64)> {
+		tax_lots.realized.iter().map(|gain| (gain.quantity, gain.cost_basis)).collect()
+	}
+
+	#[test]
+	fn fifo_sells_the_oldest_lot_first() {
+		let tax_lots = TaxLots::new(&ledger().0, LotMethod::Fifo);
+		assert_eq!(matched(&tax_lots), [(10., 100.), (5., 150.)]);
+		assert!(tax_lots.realized[0].term == Term::Long);
+		assert!(tax_lots.realized[1].term == Term::Short);
+		assert_eq!(tax_lots.open.iter().map(|lot| lot.quantity).collect::<Vec<_>>(), [5., 10.]);
+	}
+
+	#[test]
+	fn lifo_sells_the_newest_lot_first() {
+		let tax_lots = TaxLots::new(&ledger().0, LotMethod::Lifo);
+		assert_eq!(matched(&tax_lots), [(10., 200.), (5., 150.)]);
+	}
+
+	#[test]
+	fn highest_cost_sells_the_dearest_lot_first() {
+		let tax_lots = TaxLots::new(&ledger().0, LotMethod::HighestCost);
+		assert_eq!(matched(&tax_lots), [(10., 300.), (5., 100.)]);
+	}
+
+	#[test]
+	fn specific_lot_tells_same_day_buys_apart() {
+		let (mut ledger, buys, sell) = ledger();
+		for transaction in ledger.transactions.iter_mut().filter(|transaction| transaction.id == sell) {
+			transaction.lot_id = Some(buys[2]);
+		}
+
+		let tax_lots = TaxLots::new(&ledger, LotMethod::SpecificLot);
+		// The named lot, then FIFO for the rest
+		assert_eq!(matched(&tax_lots), [(10., 200.), (5., 50.)]);
+	}
+
+	#[test]
+	fn sells_beyond_the_open_lots_are_reported() {
+		let (mut ledger, _, _) = ledger();
+		trade(&mut ledger, TransactionKind::Sell, date!(2024-01-02), 20., 50.);
+
+		let tax_lots = TaxLots::new(&ledger, LotMethod::Fifo);
+		assert!(tax_lots.open.is_empty());
+		assert_eq!(tax_lots.oversold.len(), 1);
+		assert_eq!(tax_lots.oversold[0].quantity, 5.);
+	}
+}
@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{config, storage};
+
+const WATCHLISTS_FILE: &str = "watchlists.json";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Watchlist {
+	pub name: String,
+	pub tickers: Vec<String>,
+}
+
+impl Watchlist {
+	pub fn new(name: &str) -> Self {
+		Self {
+			name: name.to_string(),
+			tickers: vec![],
+		}
+	}
+}
+
+/// Named watchlists and which one the side panel shows, saved to the data directory
+#[derive(Serialize, Deserialize)]
+pub struct Watchlists {
+	pub lists: Vec<Watchlist>,
+	pub selected: usize,
+}
+
+impl Watchlists {
+	pub fn new() -> Self {
+		let tickers = ["TSLA", "GOOGL", "AMZN", "NVDA", "AMD", "INTC", "MSFT", "META", "NFLX", "PLTR", "MCD", "KO", "MA", "SPOT", "AAPL"];
+
+		Self {
+			lists: vec![Watchlist {
+				name: "Default".to_string(),
+				tickers: tickers.iter().map(|t| t.to_string()).collect(),
+			}],
+			selected: 0,
+		}
+	}
+
+	/// Restores the saved watchlists, falling back to the ones from the config file or the default one
+	pub fn load() -> Self {
+		match storage::load::<Self>(WATCHLISTS_FILE) {
+			Some(mut watchlists) if !watchlists.lists.is_empty() => {
+				watchlists.selected = watchlists.selected.min(watchlists.lists.len() - 1);
+				watchlists
+			},
+			_ if !config::get().watchlists.is_empty() => Self {
+				lists: config::get().watchlists.clone(),
+				selected: 0,
+			},
+			_ => Self::new(),
+		}
+	}
+
+	pub fn save(&self) {
+		storage::save(WATCHLISTS_FILE, self);
+	}
+
+	pub fn selected(&self) -> &Watchlist {
+		&self.lists[self.selected]
+	}
+
+	pub fn selected_mut(&mut self) -> &mut Watchlist {
+		&mut self.lists[self.selected]
+	}
+
+	/// Adds `ticker` to the selected list, returns false if it was already in it
+	pub fn add_ticker(&mut self, ticker: &str) -> bool {
+		let list = self.selected_mut();
+		if list.tickers.iter().any(|t| t == ticker) {
+			return false;
+		}
+
+		list.tickers.push(ticker.to_string());
+		true
+	}
+
+	pub fn remove_ticker(&mut self, index: usize) {
+		self.selected_mut().tickers.remove(index);
+	}
+
+	pub fn move_ticker(&mut self, from: usize, to: usize) {
+		move_item(&mut self.selected_mut().tickers, from, to);
+	}
+
+	/// Adds an empty list and selects it
+	pub fn add_list(&mut self, name: &str) {
+		self.lists.push(Watchlist::new(name));
+		self.selected = self.lists.len() - 1;
+	}
+
+	/// Removes the selected list, keeping at least one around
+	pub fn remove_selected(&mut self) {
+		if self.lists.len() > 1 {
+			self.lists.remove(self.selected);
+			self.selected = self.selected.min(self.lists.len() - 1);
+		}
+	}
+}
+
+impl Default for Watchlists {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Moves the item at `from` so it ends up in front of the one at `to`
+/// (or last when `to` is the list length)
+fn move_item<T>(items: &mut Vec<T>, from: usize, to: usize) {
+	if from >= items.len() {
+		return;
+	}
+
+	let item = items.remove(from);
+	let to = if to > from { to - 1 } else { to };
+	items.insert(to.min(items.len()), item);
+}
@@ -0,0 +1,238 @@
+use std::cmp::Ordering;
+
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Column {
+	Symbol,
+	Name,
+	Last,
+	Change,
+	ChangePercent,
+	Volume,
+	DayRange,
+	YearRange,
+	Sparkline,
+}
+
+impl Column {
+	pub const ALL: [Column; 9] = [
+		Column::Symbol,
+		Column::Name,
+		Column::Last,
+		Column::Change,
+		Column::ChangePercent,
+		Column::Volume,
+		Column::DayRange,
+		Column::YearRange,
+		Column::Sparkline,
+	];
+
+	pub fn title(&self) -> &'static str {
+		match self {
+			Column::Symbol => "Symbol",
+			Column::Name => "Name",
+			Column::Last => "Last",
+			Column::Change => "Change",
+			Column::ChangePercent => "Change %",
+			Column::Volume => "Volume",
+			Column::DayRange => "Day range",
+			Column::YearRange => "52w range",
+			Column::Sparkline => "Intraday",
+		}
+	}
+}
+
+/// Values of one watchlist entry as shown in the table
+pub struct TableRow<'a> {
+	pub symbol: &'a str,
+	pub name: Option<&'a str>,
+	pub currency: &'a str,
+	pub last: Option<f64>,
+	/// Absolute and percent change
+	pub change: Option<(f64, f64)>,
+	pub volume: Option<f64>,
+	pub day_range: Option<(f64, f64)>,
+	pub year_range: Option<(f64, f64)>,
+	pub sparkline: Vec<f64>,
+}
+
+/// Compact, sortable table mode of the watchlist
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WatchlistTable {
+	/// Visible columns in display order
+	pub columns: Vec<Column>,
+	/// Sorted column and whether it's descending
+	pub sort: Option<(Column, bool)>,
+}
+
+impl WatchlistTable {
+	pub fn new() -> Self {
+		Self {
+			columns: Column::ALL.to_vec(),
+			sort: None,
+		}
+	}
+
+	pub fn column_menu(&mut self, ui: &mut Ui) {
+		for column in Column::ALL {
+			let mut visible = self.columns.contains(&column);
+			if ui.checkbox(&mut visible, column.title()).changed() {
+				if visible {
+					self.columns.push(column);
+					self.columns.sort_by_key(|c| Column::ALL.iter().position(|all| all == c));
+				} else {
+					self.columns.retain(|c| *c != column);
+				}
+			}
+		}
+	}
+
+	/// Draws the table, returning the index of the clicked row
+	pub fn show(&mut self, ui: &mut Ui, rows: &[TableRow]) -> Option<usize> {
+		let mut order: Vec<usize> = (0..rows.len()).collect();
+		if let Some((column, descending)) = self.sort {
+			order.sort_by(|a, b| {
+				let ordering = compare(&rows[*a], &rows[*b], column);
+				if descending { ordering.reverse() } else { ordering }
+			});
+		}
+
+		let mut clicked = None;
+		Grid::new("watchlist_table").striped(true).spacing([12., 4.]).show(ui, |ui| {
+			for column in &self.columns {
+				let arrow = match self.sort {
+					Some((sorted, false)) if sorted == *column => " ⏶",
+					Some((sorted, true)) if sorted == *column => " ⏷",
+					_ => "",
+				};
+
+				let header = Button::new(RichText::new(format!("{}{arrow}", column.title())).strong()).frame(false);
+				if *column == Column::Sparkline {
+					ui.label(RichText::new(column.title()).strong());
+				} else if ui.add(header).clicked() {
+					// Ascending, then descending, then back to the watchlist order
+					self.sort = match self.sort {
+						Some((sorted, false)) if sorted == *column => Some((*column, true)),
+						Some((sorted, true)) if sorted == *column => None,
+						_ => Some((*column, false)),
+					};
+				}
+			}
+			ui.end_row();
+
+			for index in order {
+				let row = &rows[index];
+				for column in &self.columns {
+					if cell(ui, row, *column).clicked() {
+						clicked = Some(index);
+					}
+				}
+				ui.end_row();
+			}
+		});
+
+		clicked
+	}
+}
+
+impl Default for WatchlistTable {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+fn cell(ui: &mut Ui, row: &TableRow, column: Column) -> Response {
+	let change_color = row.change
+		.and_then(|(change, _)| config::get().colors.change(change))
+		.unwrap_or(ui.visuals().text_color());
+
+	let text = match column {
+		Column::Symbol => RichText::new(row.symbol).strong(),
+		Column::Name => RichText::new(row.name.unwrap_or("-")),
+		Column::Last => RichText::new(row.last.map_or("-".to_string(), |last| format!("{last:.2} {}", row.currency))),
+		Column::Change => RichText::new(row.change.map_or("-".to_string(), |(change, _)| format!("{change:+.2}"))).color(change_color),
+		Column::ChangePercent => RichText::new(row.change.map_or("-".to_string(), |(_, percent)| format!("{percent:+.2}%"))).color(change_color),
+		Column::Volume => RichText::new(row.volume.map_or("-".to_string(), format_volume)),
+		Column::DayRange => RichText::new(format_range(row.day_range)),
+		Column::YearRange => RichText::new(format_range(row.year_range)),
+		Column::Sparkline => return sparkline(ui, &row.sparkline),
+	};
+
+	ui.add(Label::new(text).selectable(false).sense(Sense::click()))
+}
+
+fn compare(a: &TableRow, b: &TableRow, column: Column) -> Ordering {
+	let number = |row: &TableRow| match column {
+		Column::Last => row.last,
+		Column::Change => row.change.map(|(change, _)| change),
+		Column::ChangePercent => row.change.map(|(_, percent)| percent),
+		Column::Volume => row.volume,
+		// Ranges sort by where the last price sits within them
+		Column::DayRange => range_position(row.last, row.day_range),
+		Column::YearRange => range_position(row.last, row.year_range),
+		_ => None,
+	};
+
+	match column {
+		Column::Symbol => a.symbol.cmp(b.symbol),
+		Column::Name => a.name.unwrap_or(a.symbol).to_lowercase().cmp(&b.name.unwrap_or(b.symbol).to_lowercase()),
+		// Rows without data yet always go last
+		_ => match (number(a), number(b)) {
+			(Some(a), Some(b)) => a.total_cmp(&b),
+			(Some(_), None) => Ordering::Less,
+			(None, Some(_)) => Ordering::Greater,
+			(None, None) => Ordering::Equal,
+		},
+	}
+}
+
+fn range_position(last: Option<f64>, range: Option<(f64, f64)>) -> Option<f64> {
+	match (last, range) {
+		(Some(last), Some((low, high))) if high > low => Some((last - low) / (high - low)),
+		_ => None,
+	}
+}
+
+fn sparkline(ui: &mut Ui, values: &[f64]) -> Response {
+	let (rect, response) = ui.allocate_exact_size(vec2(80., 18.), Sense::click());
+	if values.len() < 2 {
+		return response;
+	}
+
+	let (min, max) = values.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| (min.min(*v), max.max(*v)));
+	let height = if max > min { max - min } else { 1. };
+	let points = values.iter().enumerate().map(|(i, v)| {
+		let x = rect.left() + rect.width() * i as f32 / (values.len() - 1) as f32;
+		let y = rect.bottom() - rect.height() * ((v - min) / height) as f32;
+		pos2(x, y)
+	}).collect();
+
+	let colors = config::get().colors;
+	let color = if values[values.len() - 1] >= values[0] { colors.gain } else { colors.loss };
+	ui.painter().add(Shape::line(points, Stroke::new(1., color)));
+
+	response
+}
+
+fn format_range(range: Option<(f64, f64)>) -> String {
+	match range {
+		Some((low, high)) => format!("{low:.2} - {high:.2}"),
+		None => "-".to_string(),
+	}
+}
+
+fn format_volume(volume: f64) -> String {
+	if volume >= 1e9 {
+		format!("{:.2}B", volume / 1e9)
+	} else if volume >= 1e6 {
+		format!("{:.2}M", volume / 1e6)
+	} else if volume >= 1e3 {
+		format!("{:.1}K", volume / 1e3)
+	} else {
+		format!("{volume:.0}")
+	}
+}
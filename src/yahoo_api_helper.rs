@@ -1,156 +1,286 @@
-use std::thread::{JoinHandle, self};
+use std::{collections::HashMap, sync::mpsc::{self, Receiver, TryRecvError}, thread};
 
-use yahoo::{YahooError, YResponse, YahooConnector, YMetaData, YSearchResult};
+use eframe::egui::Context;
+use yahoo::{time::OffsetDateTime, Quote, YahooError, YResponse, YahooConnector, YMetaData, YSearchResult};
 use yahoo_finance_api as yahoo;
 
-pub type YahooFetchHandle = Option<JoinHandle<Result<YResponse, YahooError>>>;
-pub type YahooFetchSearchHandle = Option<JoinHandle<Result<YSearchResult, YahooError>>>;
+use crate::config;
 
-pub fn fetch_recent_interval(fetch_handle: &mut YahooFetchHandle, ticker: &String, stock_data: &mut Vec<[f64; 2]>, volume_data: &mut Vec<[f64; 2]>, metadata: &mut Option<YMetaData>) {
-    if fetch_handle.is_none() {
-        let tick = ticker.clone();
-        let handle = thread::spawn(move || {
-            let provider = YahooConnector::new().unwrap();
-            let response = provider.get_quote_period_interval(&tick, "max", "1m", false);
+pub type YahooFetchHandle = Option<Receiver<Result<YResponse, YahooError>>>;
+pub type YahooFetchSearchHandle = Option<Receiver<Result<YSearchResult, YahooError>>>;
+pub type SymbolDetailsHandle = Option<Receiver<Result<SymbolDetails, String>>>;
+pub type DailyClosesHandle = Option<Receiver<HashMap<String, Vec<[f64; 2]>>>>;
+
+/// Slow-changing facts about a symbol that aren't part of the chart metadata
+#[derive(Clone)]
+pub struct SymbolDetails {
+    pub name: Option<String>,
+    pub year_range: Option<(f64, f64)>,
+    /// Average daily volume over the past year
+    pub average_volume: Option<f64>,
+}
 
-            response
-        });
+/// Connector sending the user agent from the config, if one is set
+fn connector() -> YahooConnector {
+    let builder = YahooConnector::builder();
+    match &config::get().user_agent {
+        Some(user_agent) => builder.build_with_agent(user_agent),
+        None => builder.build(),
+    }.unwrap()
+}
 
-        *fetch_handle = Some(handle);
+/// Runs `fetch` on a worker thread and repaints once its result has been sent,
+/// so the UI only wakes up when there is something new to show.
+fn spawn_fetch<T: Send + 'static>(ctx: &Context, fetch: impl FnOnce() -> T + Send + 'static) -> Receiver<T> {
+    let (sender, receiver) = mpsc::channel();
+    let ctx = ctx.clone();
+    thread::spawn(move || {
+        // The receiver is gone if the fetch was superseded, nothing to do then
+        if sender.send(fetch()).is_ok() {
+            ctx.request_repaint();
+        }
+    });
+
+    receiver
+}
+
+/// Takes the result out of `fetch_handle` if the worker is done, leaving the
+/// handle in place while it is still running.
+fn poll_fetch<T>(fetch_handle: &mut Option<Receiver<T>>) -> Option<T> {
+    let receiver = fetch_handle.take()?;
+    match receiver.try_recv() {
+        Ok(response) => Some(response),
+        Err(TryRecvError::Empty) => {
+            *fetch_handle = Some(receiver);
+            None
+        },
+        // The worker panicked, the next call starts a new fetch
+        Err(TryRecvError::Disconnected) => None,
     }
+}
 
-    let fh_temp = fetch_handle.take();
-    if let Some(handle) = fh_temp {
-        if handle.is_finished() {
-            let response = handle.join().unwrap();
-
-			stock_data.clear();
-            volume_data.clear();
-
-            match response {
-                Ok(resp) => {
-                    for quote in resp.quotes().unwrap() {
-                        stock_data.push([quote.timestamp as f64, quote.close]);
-                        volume_data.push([quote.timestamp as f64, quote.volume as f64]);
-                    }
-
-                    *metadata = Some(resp.metadata().unwrap());
-                },
-                Err(_) => {
-					eprintln!("Error stock '{ticker}' not found");
-                    *metadata = None;
-                },
-            }
-        } else {
-            *fetch_handle = Some(handle);
+/// Closes, volumes and metadata a chart fetch writes into
+pub struct ChartSeries<'a> {
+    pub prices: &'a mut Vec<[f64; 2]>,
+    pub volumes: &'a mut Vec<[f64; 2]>,
+    pub metadata: &'a mut Option<YMetaData>,
+}
+
+impl ChartSeries<'_> {
+    fn set(&mut self, ticker: &str, response: Result<YResponse, YahooError>) {
+        self.prices.clear();
+        self.volumes.clear();
+
+        match response {
+            Ok(resp) => {
+                for quote in resp.quotes().unwrap() {
+                    self.prices.push([quote.timestamp as f64, quote.close]);
+                    self.volumes.push([quote.timestamp as f64, quote.volume as f64]);
+                }
+
+                *self.metadata = Some(resp.metadata().unwrap());
+            },
+            Err(_) => {
+                eprintln!("Error stock '{ticker}' not found");
+                *self.metadata = None;
+            },
         }
     }
 }
 
-pub fn fetch_history(fetch_handle: &mut YahooFetchHandle, ticker: &String, range: &String, stock_data: &mut Vec<[f64; 2]>, volume_data: &mut Vec<[f64; 2]>, metadata: &mut Option<YMetaData>) {
+pub fn fetch_recent_interval(ctx: &Context, fetch_handle: &mut YahooFetchHandle, ticker: &str, interval: &str, mut series: ChartSeries) -> bool {
     if fetch_handle.is_none() {
-        let tick = ticker.clone();
-        let range = range.clone();
-        let handle = thread::spawn(move || {
-            let provider = YahooConnector::new().unwrap();
-            let response = provider.get_quote_range(&tick, "1d", &range);
+        let tick = ticker.to_string();
+        let interval = interval.to_string();
+        *fetch_handle = Some(spawn_fetch(ctx, move || {
+            let provider = connector();
+            provider.get_quote_period_interval(&tick, "max", &interval, false)
+        }));
+    }
+
+    let Some(response) = poll_fetch(fetch_handle) else {
+        return false;
+    };
 
-            response
-        });
+    series.set(ticker, response);
+    true
+}
 
-        *fetch_handle = Some(handle);
+/// Bars of `interval` over `range`, e.g. 1d bars over the last year
+pub fn fetch_history(ctx: &Context, fetch_handle: &mut YahooFetchHandle, ticker: &str, range: &str, interval: &str, mut series: ChartSeries) -> bool {
+    if fetch_handle.is_none() {
+        let tick = ticker.to_string();
+        let range = range.to_string();
+        let interval = interval.to_string();
+        *fetch_handle = Some(spawn_fetch(ctx, move || {
+            let provider = connector();
+            provider.get_quote_range(&tick, &interval, &range)
+        }));
     }
 
-    let fh_temp = fetch_handle.take();
-    if let Some(handle) = fh_temp {
-        if handle.is_finished() {
-            let response = handle.join().unwrap();
-
-			stock_data.clear();
-            volume_data.clear();
-
-            match response {
-                Ok(resp) => {
-                    for quote in resp.quotes().unwrap() {
-                        stock_data.push([quote.timestamp as f64, quote.close]);
-                        volume_data.push([quote.timestamp as f64, quote.volume as f64]);
-                    }
-
-                    *metadata = Some(resp.metadata().unwrap());
-                },
-                Err(_) => {
-					eprintln!("Error stock '{ticker}' not found");
-                    *metadata = None;
-                },
-            }
-        } else {
-            *fetch_handle = Some(handle);
+    let Some(response) = poll_fetch(fetch_handle) else {
+        return false;
+    };
+
+    series.set(ticker, response);
+    true
+}
+
+
+pub fn fetch_now_data(ctx: &Context, fetch_handle: &mut YahooFetchHandle, ticker: &str, latest_price: &mut f64, intraday: &mut Vec<Quote>, metadata: &mut Option<YMetaData>) -> bool {
+    if fetch_handle.is_none() {
+        let tick = ticker.to_string();
+        *fetch_handle = Some(spawn_fetch(ctx, move || {
+            let provider = connector();
+            provider.get_quote_period_interval(&tick, "max", "1m", false)
+        }));
+    }
+
+    if let Some(response) = poll_fetch(fetch_handle) {
+        match response {
+            Ok(resp) => {
+                *latest_price = resp.last_quote().unwrap().close;
+                let meta = resp.metadata().unwrap();
+                *intraday = last_day_quotes(resp.quotes().unwrap_or_default(), meta.gmtoffset);
+                *metadata = Some(meta);
+            },
+            Err(_) => {
+                eprintln!("Error stock '{ticker}' not found");
+                *latest_price = 0.;
+                intraday.clear();
+                *metadata = None;
+            },
         }
+
+        return true;
     }
+
+    false
 }
 
+/// Returns true once the search has finished, `search_result` is `None` if it failed
+pub fn fetch_search_ticker(ctx: &Context, fetch_handle: &mut YahooFetchSearchHandle, search: &str, search_result: &mut Option<YSearchResult>) -> bool {
+    if fetch_handle.is_none() {
+        let search = search.to_string();
+        *fetch_handle = Some(spawn_fetch(ctx, move || {
+            let provider = connector();
+            provider.search_ticker(&search)
+        }));
+    }
+
+    let Some(response) = poll_fetch(fetch_handle) else {
+        return false;
+    };
+
+    *search_result = response.ok();
+    true
+}
+
+/// Quotes from the exchange-local calendar day of the latest quote
+fn last_day_quotes(mut quotes: Vec<Quote>, gmtoffset: i32) -> Vec<Quote> {
+    const DAY: i64 = 24 * 60 * 60;
+
+    let local_day = |quote: &Quote| (quote.timestamp as i64 + gmtoffset as i64).div_euclid(DAY);
+    if let Some(last_day) = quotes.last().map(local_day) {
+        quotes.retain(|quote| local_day(quote) == last_day);
+    }
+
+    quotes
+}
 
-pub fn fetch_now_data(fetch_handle: &mut YahooFetchHandle, ticker: &String, latest_price: &mut f64, metadata: &mut Option<YMetaData>) {
+/// Looks up the symbol's name and 52-week range, which only need fetching once.
+/// Returns whether a fetch finished, `details` stay `None` if it failed.
+pub fn fetch_symbol_details(ctx: &Context, fetch_handle: &mut SymbolDetailsHandle, ticker: &str, details: &mut Option<SymbolDetails>) -> bool {
     if fetch_handle.is_none() {
-        let tick = ticker.clone();
-        let handle = thread::spawn(move || {
-            let provider = YahooConnector::new().unwrap();
-            let response = provider.get_quote_period_interval(&tick, "max", "1m", false);
+        let tick = ticker.to_string();
+        *fetch_handle = Some(spawn_fetch(ctx, move || {
+            let provider = connector();
 
-            response
-        });
+            let name = provider.search_ticker(&tick).map_err(|err| err.to_string())?
+                .quotes.into_iter()
+                .find(|quote| quote.symbol == tick)
+                .map(|quote| if quote.long_name.is_empty() { quote.short_name } else { quote.long_name });
 
-        *fetch_handle = Some(handle);
+            let year_quotes = provider.get_quote_range(&tick, "1d", "1y").map_err(|err| err.to_string())?
+                .quotes().ok()
+                .filter(|quotes| !quotes.is_empty());
+            let year_range = year_quotes.as_ref()
+                .map(|quotes| quotes.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), q| (low.min(q.low), high.max(q.high))));
+            let average_volume = year_quotes.as_ref()
+                .map(|quotes| quotes.iter().map(|q| q.volume as f64).sum::<f64>() / quotes.len() as f64);
+
+            Ok(SymbolDetails { name, year_range, average_volume })
+        }));
     }
 
-    let fh_temp = fetch_handle.take();
-    if let Some(handle) = fh_temp {
-        if handle.is_finished() {
-            let response = handle.join().unwrap();
-
-            match response {
-                Ok(resp) => {
-                    *latest_price = resp.last_quote().unwrap().close;
-                    *metadata = Some(resp.metadata().unwrap());
-                },
-                Err(_) => {
-					eprintln!("Error stock '{ticker}' not found");
-                    *latest_price = 0.;
-                    *metadata = None;
-                },
-            }
-        } else {
-            *fetch_handle = Some(handle);
-        }
+    let Some(response) = poll_fetch(fetch_handle) else {
+        return false;
+    };
+
+    match response {
+        Ok(response) => *details = Some(response),
+        Err(err) => eprintln!("Error fetching details of '{ticker}': {err}"),
     }
+    true
 }
 
-pub fn fetch_search_ticker(fetch_handle: &mut YahooFetchSearchHandle, search: &String, search_result: &mut Option<YSearchResult>, found_result: &mut bool) {
+/// Bars of `interval` over `range`, e.g. 5m bars over the last month
+pub fn fetch_bars(ctx: &Context, fetch_handle: &mut YahooFetchHandle, ticker: &str, interval: &str, range: &str, bars: &mut Vec<Quote>, metadata: &mut Option<YMetaData>) -> bool {
     if fetch_handle.is_none() {
-        let search = search.clone();
-        let handle = thread::spawn(move || {
-            let provider = YahooConnector::new().unwrap();
-            let response = provider.search_ticker(&search);
+        let tick = ticker.to_string();
+        let interval = interval.to_string();
+        let range = range.to_string();
+        *fetch_handle = Some(spawn_fetch(ctx, move || {
+            let provider = connector();
+            provider.get_quote_range(&tick, &interval, &range)
+        }));
+    }
 
-            response
-        });
+    let Some(response) = poll_fetch(fetch_handle) else {
+        return false;
+    };
 
-        *fetch_handle = Some(handle);
+    match response.and_then(|resp| Ok((resp.quotes()?, resp.metadata()?))) {
+        Ok((quotes, meta)) => {
+            *bars = quotes;
+            *metadata = Some(meta);
+        },
+        Err(_) => {
+            eprintln!("Error fetching bars of '{ticker}'");
+            bars.clear();
+            *metadata = None;
+        },
     }
 
-    let fh_temp = fetch_handle.take();
-    if let Some(handle) = fh_temp {
-        if handle.is_finished() {
-            let response = handle.join().unwrap();
+    true
+}
 
-            *found_result = true;
+/// Daily closes of every ticker since `start`, tickers that fail are left out
+pub fn fetch_daily_closes(ctx: &Context, fetch_handle: &mut DailyClosesHandle, tickers: &[String], start: OffsetDateTime, closes: &mut HashMap<String, Vec<[f64; 2]>>) -> bool {
+    if fetch_handle.is_none() {
+        let tickers = tickers.to_vec();
+        *fetch_handle = Some(spawn_fetch(ctx, move || {
+            let provider = connector();
+            let end = OffsetDateTime::now_utc();
 
-            *search_result = match response {
-                Ok(r) => Some(r),
-                Err(_) => None,
+            let mut closes = HashMap::new();
+            for ticker in tickers {
+                match provider.get_quote_history(&ticker, start, end).and_then(|resp| resp.quotes()) {
+                    Ok(quotes) => {
+                        closes.insert(ticker, quotes.iter().map(|quote| [quote.timestamp as f64, quote.close]).collect());
+                    },
+                    Err(_) => eprintln!("Error fetching the history of '{ticker}'"),
+                }
             }
-        } else {
-            *fetch_handle = Some(handle);
-        }
+
+            closes
+        }));
     }
+
+    if let Some(response) = poll_fetch(fetch_handle) {
+        *closes = response;
+        return true;
+    }
+
+    false
 }